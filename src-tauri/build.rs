@@ -1,15 +1,42 @@
 fn main() {
+    // the payloads below already narrow reruns to their files, so the
+    // variables that decide what gets embedded are listed as well
+    println!("cargo:rerun-if-env-changed=BUILD_MODE");
+    println!("cargo:rerun-if-env-changed=EMBEDDED_VERSION");
     let build_mode = std::env::var("BUILD_MODE").unwrap_or_else(|_| "online".into());
-    let embedded_version = std::env::var("EMBEDDED_VERSION").unwrap_or_else(|_| "".into());
+    let mut embedded_version = std::env::var("EMBEDDED_VERSION").unwrap_or_else(|_| "".into());
     if build_mode == "offline" {
-        if embedded_version.is_empty() {
-            panic!("offline mode requires EMBEDDED_VERSION to be set");
+        let identity = read_package_identity("Snap.Hutao.msix");
+        if !embedded_version.is_empty()
+            && normalize_version(&embedded_version) != normalize_version(&identity.version)
+        {
+            panic!(
+                "EMBEDDED_VERSION {embedded_version} does not match the version {} in Snap.Hutao.msix",
+                identity.version
+            );
         }
+        embedded_version = identity.version.clone();
 
-        compress("Snap.Hutao.msix")
+        let sha256 = compress("Snap.Hutao.msix");
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_SHA256={sha256}");
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_NAME={}", identity.name);
+        println!(
+            "cargo:rustc-env=EMBEDDED_PACKAGE_PUBLISHER={}",
+            identity.publisher
+        );
+        println!(
+            "cargo:rustc-env=EMBEDDED_PACKAGE_ARCHITECTURE={}",
+            identity.architecture
+        );
+    } else {
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_SHA256=");
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_NAME=");
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_PUBLISHER=");
+        println!("cargo:rustc-env=EMBEDDED_PACKAGE_ARCHITECTURE=");
     }
 
-    compress("SegoeIcons.ttf");
+    let sha256 = compress("SegoeIcons.ttf");
+    println!("cargo:rustc-env=EMBEDDED_FONT_SHA256={sha256}");
 
     println!("cargo:rustc-env=BUILD_MODE={build_mode}");
     println!("cargo:rustc-env=EMBEDDED_VERSION={embedded_version}");
@@ -44,38 +71,109 @@ fn main() {
         .expect("failed to build tauri app");
 }
 
-#[allow(unused_variables)]
-fn compress(file_name: &str) {
-    #[cfg(not(debug_assertions))]
-    {
-        use std::io::{Read, Write};
-        let start_time = std::time::Instant::now();
-        println!("cargo:warning=Compressing {}", file_name);
+struct PackageIdentity {
+    name: String,
+    publisher: String,
+    version: String,
+    architecture: String,
+}
+
+// The identity comes from AppxManifest.xml inside the package, so the
+// embedded metadata can't drift from the package itself.
+fn read_package_identity(file_name: &str) -> PackageIdentity {
+    use std::io::Read;
+
+    let file = std::fs::File::open(file_name).unwrap();
+    let mut archive = zip::ZipArchive::new(file)
+        .unwrap_or_else(|e| panic!("{file_name} is not a valid msix: {e}"));
+    let mut manifest = String::new();
+    archive
+        .by_name("AppxManifest.xml")
+        .unwrap_or_else(|e| panic!("{file_name} has no AppxManifest.xml: {e}"))
+        .read_to_string(&mut manifest)
+        .unwrap();
+
+    let doc = roxmltree::Document::parse(&manifest).unwrap();
+    let identity = doc
+        .descendants()
+        .find(|n| n.has_tag_name("Identity"))
+        .unwrap_or_else(|| panic!("AppxManifest.xml of {file_name} has no Identity"));
+    let attribute = |name: &str| {
+        identity
+            .attribute(name)
+            .unwrap_or_else(|| panic!("Identity of {file_name} has no {name}"))
+            .to_string()
+    };
+    PackageIdentity {
+        name: attribute("Name"),
+        publisher: attribute("Publisher"),
+        version: attribute("Version"),
+        architecture: identity
+            .attribute("ProcessorArchitecture")
+            .unwrap_or("neutral")
+            .to_string(),
+    }
+}
+
+// `1.2.3` and `1.2.3.0` are the same package version.
+fn normalize_version(version: &str) -> Vec<u16> {
+    let mut parts = version
+        .trim()
+        .split('.')
+        .map(|p| {
+            p.parse::<u16>()
+                .unwrap_or_else(|_| panic!("Invalid version {version}"))
+        })
+        .collect::<Vec<_>>();
+    parts.resize(4, 0);
+    parts
+}
 
-        let mut input = std::fs::File::open(file_name).unwrap();
-        let mut input_bytes = Vec::new();
-        input.read_to_end(&mut input_bytes).unwrap();
-        drop(input);
+// Payloads are zstd compressed into OUT_DIR, the sha256 of the original file
+// is returned so the installer can verify what it extracts.
+fn compress(file_name: &str) -> String {
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
 
-        let original_size = input_bytes.len();
+    println!("cargo:rerun-if-changed={file_name}");
+    let start_time = std::time::Instant::now();
+    println!("cargo:warning=Compressing {}", file_name);
 
-        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
-        encoder.write_all(&input_bytes).unwrap();
-        let compressed = encoder.finish().unwrap();
+    let input_bytes = std::fs::read(file_name).unwrap();
+    let original_size = input_bytes.len();
+    let sha256 = format!("{:x}", Sha256::digest(&input_bytes));
 
-        let compressed_size = compressed.len();
-        let compression_ratio = (1.0 - (compressed_size as f64 / original_size as f64)) * 100.0;
+    // the highest regular level only pays off for release builds
+    // `cfg!(debug_assertions)` would describe the build script, not the installer
+    let level = if std::env::var("PROFILE").as_deref() == Ok("release") {
+        19
+    } else {
+        3
+    };
+    let output_path =
+        std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join(format!("{file_name}.zst"));
+    let output = std::io::BufWriter::new(std::fs::File::create(&output_path).unwrap());
+    let mut encoder = zstd::stream::write::Encoder::new(output, level).unwrap();
+    // the content size in the frame header tells the installer how much
+    // disk space the payload needs
+    encoder
+        .set_pledged_src_size(Some(original_size as u64))
+        .unwrap();
+    encoder.include_checksum(true).unwrap();
+    encoder.write_all(&input_bytes).unwrap();
+    encoder.finish().unwrap().flush().unwrap();
 
-        std::fs::write(file_name, compressed).unwrap();
+    let compressed_size = std::fs::metadata(&output_path).unwrap().len();
+    let compression_ratio = (1.0 - (compressed_size as f64 / original_size as f64)) * 100.0;
 
-        let elapsed = start_time.elapsed();
-        println!(
-            "cargo:warning=Compressed {} ({} bytes -> {} bytes, {:.1}% reduction) in {:.2}ms",
-            file_name,
-            original_size,
-            compressed_size,
-            compression_ratio,
-            elapsed.as_secs_f64() * 1000.0
-        );
-    }
+    let elapsed = start_time.elapsed();
+    println!(
+        "cargo:warning=Compressed {} ({} bytes -> {} bytes, {:.1}% reduction) in {:.2}ms",
+        file_name,
+        original_size,
+        compressed_size,
+        compression_ratio,
+        elapsed.as_secs_f64() * 1000.0
+    );
+    sha256
 }
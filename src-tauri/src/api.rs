@@ -1,5 +1,27 @@
-use crate::REQUEST_CLIENT;
+use crate::{
+    REQUEST_CLIENT,
+    installer::check_temp_package_valid,
+    module::{
+        channel,
+        passport::{self, HomaSession},
+    },
+    utils::{
+        Version,
+        dir::get_cache_dir,
+        signature::{canonical_json, verify_ed25519},
+        unix_now,
+    },
+};
+use reqwest::{
+    StatusCode,
+    header::{ETAG, IF_NONE_MATCH},
+};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// Ed25519 public keys allowed to sign patch manifests. Append the new key when
+// rotating and drop the old one once no published manifest relies on it.
+const PATCH_MANIFEST_TRUSTED_KEYS: &[&str] = &["2nIVufIfrrxlTvrFMD7hxiwUILTS2AopUwAZYz7o4nU="];
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct HomaDistributionGetAcceleratedMirrorResp {
@@ -57,6 +79,26 @@ pub struct HomaPassportUserInfo {
     pub cdn_expire_at: String,
 }
 
+impl HomaPassportUserInfo {
+    pub fn is_cdn_available(&self) -> bool {
+        if self.is_licensed_developer || self.is_maintainer {
+            return true;
+        }
+
+        let now = chrono::Utc::now();
+        if let Ok(expire_at) = chrono::DateTime::parse_from_rfc3339(&self.cdn_expire_at) {
+            return expire_at > now;
+        }
+        // the server may omit the offset, in which case the time is UTC
+        if let Ok(expire_at) =
+            chrono::NaiveDateTime::parse_from_str(&self.cdn_expire_at, "%Y-%m-%dT%H:%M:%S%.f")
+        {
+            return expire_at.and_utc() > now;
+        }
+        false
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct HomaPassportUserInfoResp {
     pub retcode: i32,
@@ -88,7 +130,7 @@ pub struct GenericIpResp {
     pub data: Option<GenericIp>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GenericPatchData {
     pub version: String,
     pub validation: String,
@@ -96,9 +138,66 @@ pub struct GenericPatchData {
     pub mirrors: Vec<GenericPatchPackageMirror>,
     pub urls: Vec<String>,
     pub sha256: String,
+    // package size in bytes, older manifests don't carry it and the
+    // signature only covers it when present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    // binary patches to this version, only offered by the deployment manifest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<GenericDeltaPatch>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GenericDeltaPatch {
+    // the version the patch applies to
+    pub from: String,
+    // `zstd` is a `zstd --patch-from` patch, others are skipped
+    pub format: String,
+    pub urls: Vec<String>,
+}
+
+impl GenericPatchData {
+    // The signature covers the manifest serialized as compact JSON with sorted
+    // keys, excluding the `validation` field itself.
+    pub fn verify(&self) -> Result<(), anyhow::Error> {
+        self.verify_with(PATCH_MANIFEST_TRUSTED_KEYS)
+    }
+
+    fn verify_with(&self, trusted_keys: &[&str]) -> Result<(), anyhow::Error> {
+        if self.validation.is_empty() {
+            return Err(anyhow::anyhow!("Patch manifest is not signed"));
+        }
+        let value = serde_json::to_value(self);
+        if value.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to serialize patch manifest: {:?}",
+                value.err()
+            ));
+        }
+        let message = canonical_json(value?, &["validation"]);
+        verify_ed25519(&message, &self.validation, trusted_keys)
+    }
+
+    // `cache_time` is the number of seconds the manifest may be reused for.
+    pub fn cache_seconds(&self) -> u64 {
+        self.cache_time.trim().parse::<u64>().unwrap_or(0)
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
+pub struct CachedPatchData {
+    pub fetched_at: u64,
+    pub etag: Option<String>,
+    pub data: GenericPatchData,
+}
+
+impl CachedPatchData {
+    pub fn is_fresh(&self) -> bool {
+        unix_now() < self.fetched_at.saturating_add(self.data.cache_seconds())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct GenericPatchPackageMirror {
     pub url: String,
     pub mirror_name: String,
@@ -113,13 +212,16 @@ pub struct GenericPatchResp {
     pub data: Option<GenericPatchData>,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GenericPatchVersionsResp {
+    pub retcode: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<String>>,
+}
+
 pub async fn generic_get_ip_info() -> Result<GenericIp, anyhow::Error> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Fetching ip info".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "api", "Fetching ip info");
     let url = "https://api.snapgenshin.com/ip";
     let resp = REQUEST_CLIENT.get(url).send().await;
     if resp.is_err() {
@@ -139,12 +241,7 @@ pub async fn generic_get_ip_info() -> Result<GenericIp, anyhow::Error> {
 
 #[tauri::command]
 pub async fn generic_is_oversea() -> Result<bool, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Checking if oversea".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "api", "Checking if oversea");
     let data = generic_get_ip_info().await;
     if data.is_err() {
         return Err(format!("Failed to fetch ip info: {:?}", data.err()));
@@ -153,41 +250,242 @@ pub async fn generic_is_oversea() -> Result<bool, String> {
     Ok(division == "Oversea")
 }
 
+// The dry run reads the patch cache but never writes or prunes it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    ReadWrite,
+    ReadOnly,
+}
+
+// `name` may carry a version, anything but a plain file name is replaced.
+fn patch_cache_path(name: &str) -> PathBuf {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '-',
+        })
+        .collect();
+    get_cache_dir().join(format!("patch-{name}.json"))
+}
+
+pub async fn load_cached_patch(name: &str, mode: CacheMode) -> Option<CachedPatchData> {
+    let content = tokio::fs::read(patch_cache_path(name)).await.ok()?;
+    let cached: CachedPatchData = serde_json::from_slice(&content).ok()?;
+    // the cache lives in a user-writable folder, never trust it blindly
+    if cached.data.verify().is_err() {
+        if mode == CacheMode::ReadWrite {
+            let _ = tokio::fs::remove_file(patch_cache_path(name)).await;
+        }
+        return None;
+    }
+    Some(cached)
+}
+
+async fn save_cached_patch(name: &str, cached: &CachedPatchData, mode: CacheMode) {
+    if mode == CacheMode::ReadOnly {
+        return;
+    }
+    let _ = tokio::fs::create_dir_all(get_cache_dir()).await;
+    if let Ok(content) = serde_json::to_vec(cached) {
+        let _ = tokio::fs::write(patch_cache_path(name), content).await;
+    }
+}
+
+// Tells a manifest that could not be fetched apart from one that was fetched
+// but must not be trusted, callers may only work around the former.
+#[derive(Debug)]
+pub enum PatchError {
+    Unavailable(String),
+    Rejected(String),
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::Unavailable(message) => write!(f, "{message}"),
+            PatchError::Rejected(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+pub async fn fetch_patch(name: &str, mode: CacheMode) -> Result<GenericPatchData, PatchError> {
+    let cached = load_cached_patch(name, mode).await;
+    if let Some(cached) = &cached {
+        if cached.is_fresh() {
+            tracing::info!(target: "api", "Using cached {name} patch");
+            return Ok(cached.data.clone());
+        }
+    }
+
+    let url = format!("https://api.snapgenshin.com/patch/{name}");
+    let mut req = REQUEST_CLIENT.get(&url);
+    if let Some(etag) = cached.as_ref().and_then(|c| c.etag.as_ref()) {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    let resp = req.send().await;
+    if resp.is_err() {
+        return Err(PatchError::Unavailable(format!(
+            "Failed to send request: {:?}",
+            resp.err()
+        )));
+    }
+    let resp = resp.unwrap();
+
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        if let Some(mut cached) = cached {
+            tracing::info!(target: "api", "Cached {name} patch revalidated");
+            cached.fetched_at = unix_now();
+            save_cached_patch(name, &cached, mode).await;
+            return Ok(cached.data);
+        }
+        return Err(PatchError::Unavailable(
+            "Server returned 304 without a cached patch".to_string(),
+        ));
+    }
+    // a 404 or 5xx carries no manifest, only a success is parsed
+    if !resp.status().is_success() {
+        return Err(PatchError::Unavailable(format!(
+            "Failed to fetch patch: server returned {}",
+            resp.status()
+        )));
+    }
+
+    let etag = resp
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    // a body that is not a manifest at all, e.g. from a captive portal, is an
+    // outage as well, only a manifest failing verification is rejected
+    let json: Result<GenericPatchResp, reqwest::Error> = resp.json().await;
+    if json.is_err() {
+        return Err(PatchError::Unavailable(format!(
+            "Failed to parse json: {:?}",
+            json.err()
+        )));
+    }
+    let json = json.unwrap();
+    if json.retcode != 0 {
+        return Err(PatchError::Unavailable(format!(
+            "Failed to fetch patch: {:?}",
+            json.message
+        )));
+    }
+    let Some(data) = json.data else {
+        return Err(PatchError::Unavailable(
+            "Failed to fetch patch: response has no data".to_string(),
+        ));
+    };
+    let verify_res = data.verify();
+    if verify_res.is_err() {
+        let err = anyhow::anyhow!(
+            "Patch manifest signature verification failed, refusing to continue: {:?}",
+            verify_res.err()
+        );
+        sentry_anyhow::capture_anyhow(&err);
+        return Err(PatchError::Rejected(format!("{err}")));
+    }
+
+    save_cached_patch(
+        name,
+        &CachedPatchData {
+            fetched_at: unix_now(),
+            etag,
+            data: data.clone(),
+        },
+        mode,
+    )
+    .await;
+    Ok(data)
+}
+
 #[tauri::command]
 pub async fn generic_get_patch() -> Result<GenericPatchData, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Fetching patch".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let url = "https://api.snapgenshin.com/patch/hutao";
-    let resp = REQUEST_CLIENT.get(url).send().await;
+    get_patch(CacheMode::ReadWrite)
+        .await
+        .map_err(|e| format!("{e}"))
+}
+
+pub async fn get_patch(mode: CacheMode) -> Result<GenericPatchData, PatchError> {
+    tracing::info!(target: "api", "Fetching patch");
+    let name = channel::current().await.patch_name();
+    let res = fetch_patch(name, mode).await;
+    if let Err(PatchError::Unavailable(e)) = &res {
+        // keep installs working through short API outages when the package
+        // downloaded for the cached manifest is still around
+        if let Some(cached) = load_cached_patch(name, mode).await {
+            if check_temp_package_valid(cached.data.sha256.clone())
+                .await
+                .unwrap_or(false)
+            {
+                tracing::warn!(target: "api", "Falling back to cached patch: {e}");
+                return Ok(cached.data);
+            }
+        }
+    }
+    res
+}
+
+// The manifest of a single release, used to install or roll back to a version
+// that is no longer the latest one.
+pub async fn generic_get_patch_version(version: &str) -> Result<GenericPatchData, String> {
+    get_patch_version(version, CacheMode::ReadWrite)
+        .await
+        .map_err(|e| format!("{e}"))
+}
+
+pub async fn get_patch_version(
+    version: &str,
+    mode: CacheMode,
+) -> Result<GenericPatchData, PatchError> {
+    tracing::info!(target: "api", "Fetching patch of version {version}");
+    let parsed = Version::from_string(version);
+    if parsed.is_err() {
+        return Err(PatchError::Rejected(format!(
+            "Invalid version {version}: {:?}",
+            parsed.err()
+        )));
+    }
+    let parsed = parsed.unwrap();
+    let name = channel::current().await.patch_name();
+    let data = fetch_patch(&format!("{name}/{parsed}"), mode).await?;
+    if Version::from_string(&data.version).ok() != Some(parsed) {
+        return Err(PatchError::Rejected(format!(
+            "Patch manifest is for version {}, expected {version}",
+            data.version
+        )));
+    }
+    Ok(data)
+}
+
+pub async fn generic_list_versions() -> Result<Vec<String>, String> {
+    tracing::info!(target: "api", "Fetching available versions");
+    let url = format!(
+        "https://api.snapgenshin.com/patch/{}/versions",
+        channel::current().await.patch_name()
+    );
+    let resp = REQUEST_CLIENT.get(&url).send().await;
     if resp.is_err() {
         return Err(format!("Failed to send request: {:?}", resp.err()));
     }
     let resp = resp.unwrap();
-    let json: Result<GenericPatchResp, reqwest::Error> = resp.json().await;
+    let json: Result<GenericPatchVersionsResp, reqwest::Error> = resp.json().await;
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
     }
     let json = json.unwrap();
     if json.retcode != 0 {
-        return Err(format!("Failed to fetch patch: {:?}", json.message));
+        return Err(format!("Failed to fetch versions: {:?}", json.message));
     }
-    Ok(json.data.unwrap())
+    Ok(json.data.unwrap_or_default())
 }
 
 #[tauri::command]
 pub async fn homa_request_verify_code(
     username: String,
 ) -> Result<HomaPassportOperationResp, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Requesting verify code from homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "api", "Requesting verify code from homa");
     let url = "https://homa.snapgenshin.com/Passport/Verify";
     let resp = REQUEST_CLIENT
         .post(url)
@@ -206,15 +504,8 @@ pub async fn homa_request_verify_code(
 }
 
 #[tauri::command]
-pub async fn homa_register(
-    register_req: HomaPassportRegisterReq,
-) -> Result<HomaPassportOperationResp, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Registering homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn homa_register(register_req: HomaPassportRegisterReq) -> Result<HomaResp, String> {
+    tracing::info!(target: "api", "Registering homa");
     let url = "https://homa.snapgenshin.com/Passport/Register";
     let resp = REQUEST_CLIENT.post(url).json(&register_req).send().await;
     if resp.is_err() {
@@ -225,19 +516,12 @@ pub async fn homa_register(
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
     }
-    Ok(json.unwrap())
+    store_operation_token(json.unwrap()).await
 }
 
 #[tauri::command]
-pub async fn homa_login(
-    login_req: HomaPassportLoginReq,
-) -> Result<HomaPassportOperationResp, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Logging in homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn homa_login(login_req: HomaPassportLoginReq) -> Result<HomaResp, String> {
+    tracing::info!(target: "api", "Logging in homa");
     let url = "https://homa.snapgenshin.com/Passport/Login";
     let resp = REQUEST_CLIENT.post(url).json(&login_req).send().await;
     if resp.is_err() {
@@ -248,17 +532,48 @@ pub async fn homa_login(
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
     }
-    Ok(json.unwrap())
+    store_operation_token(json.unwrap()).await
+}
+
+// The token never leaves the backend, the frontend only learns whether the
+// operation succeeded.
+async fn store_operation_token(json: HomaPassportOperationResp) -> Result<HomaResp, String> {
+    if json.retcode == 0 {
+        if let Some(token) = json.data {
+            let res = passport::set_token(token).await;
+            if res.is_err() {
+                return Err(format!("Failed to store token: {:?}", res.err()));
+            }
+        }
+    }
+    Ok(HomaResp {
+        retcode: json.retcode,
+        message: json.message,
+    })
+}
+
+async fn require_token() -> Result<String, String> {
+    match passport::get_token().await {
+        Some(token) => Ok(token),
+        None => Err("Not logged in".to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn homa_logout() {
+    tracing::info!(target: "api", "Logging out homa");
+    passport::clear_token().await;
+}
+
+#[tauri::command]
+pub async fn homa_get_session() -> Result<HomaSession, String> {
+    passport::get_session().await
 }
 
 #[tauri::command]
-pub async fn homa_use_redeem_code(token: String, code: String) -> Result<HomaResp, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Using redeem code on homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn homa_use_redeem_code(code: String) -> Result<HomaResp, String> {
+    tracing::info!(target: "api", "Using redeem code on homa");
+    let token = require_token().await?;
     let url = "https://homa.snapgenshin.com/Redeem/Use";
     let resp = REQUEST_CLIENT
         .post(url)
@@ -274,17 +589,15 @@ pub async fn homa_use_redeem_code(token: String, code: String) -> Result<HomaRes
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
     }
+    // the code may have extended the cdn
+    passport::invalidate_session().await;
     Ok(json.unwrap())
 }
 
 #[tauri::command]
-pub async fn homa_fetch_userinfo(token: String) -> Result<HomaPassportUserInfo, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Fetching userinfo from homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn homa_fetch_userinfo() -> Result<HomaPassportUserInfo, String> {
+    tracing::info!(target: "api", "Fetching userinfo from homa");
+    let token = require_token().await?;
     let url = "https://homa.snapgenshin.com/Passport/UserInfo";
     let resp = REQUEST_CLIENT
         .get(url)
@@ -295,11 +608,16 @@ pub async fn homa_fetch_userinfo(token: String) -> Result<HomaPassportUserInfo,
         return Err(format!("Failed to send request: {:?}", resp.err()));
     }
     let resp = resp.unwrap();
+    if resp.status() == StatusCode::UNAUTHORIZED {
+        passport::clear_token().await;
+        return Err("Login expired".to_string());
+    }
     let json: Result<HomaPassportUserInfoResp, reqwest::Error> = resp.json().await;
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
     }
     let json = json.unwrap();
+    // only a 401 says the token itself is bad, keep it on any other failure
     if json.retcode != 0 {
         return Err(format!("Failed to fetch userinfo: {:?}", json.message));
     }
@@ -307,13 +625,9 @@ pub async fn homa_fetch_userinfo(token: String) -> Result<HomaPassportUserInfo,
 }
 
 #[tauri::command]
-pub async fn homa_fetch_cdn(token: String, filename: String) -> Result<String, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("api".to_string()),
-        message: Some("Fetching cdn from homa".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn homa_fetch_cdn(filename: String) -> Result<String, String> {
+    tracing::info!(target: "api", "Fetching cdn from homa");
+    let token = require_token().await?;
     let url = format!(
         "https://homa.snapgenshin.com/Distribution/GetAcceleratedMirror?Filename={filename}"
     );
@@ -326,6 +640,10 @@ pub async fn homa_fetch_cdn(token: String, filename: String) -> Result<String, S
         return Err(format!("Failed to send request: {:?}", resp.err()));
     }
     let resp = resp.unwrap();
+    if resp.status() == StatusCode::UNAUTHORIZED {
+        passport::clear_token().await;
+        return Err("Login expired".to_string());
+    }
     let json: Result<HomaDistributionGetAcceleratedMirrorResp, reqwest::Error> = resp.json().await;
     if json.is_err() {
         return Err(format!("Failed to parse json: {:?}", json.err()));
@@ -337,3 +655,101 @@ pub async fn homa_fetch_cdn(token: String, filename: String) -> Result<String, S
     let data = json.data.unwrap();
     Ok(data.url)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    fn trusted_key() -> String {
+        STANDARD.encode(signing_key().verifying_key().to_bytes())
+    }
+
+    fn signed_manifest() -> GenericPatchData {
+        let mut data = GenericPatchData {
+            version: "1.15.0".to_string(),
+            validation: String::new(),
+            cache_time: "600".to_string(),
+            mirrors: vec![GenericPatchPackageMirror {
+                url: "https://example.com/Snap.Hutao.1.15.0.msix".to_string(),
+                mirror_name: "Example".to_string(),
+                mirror_type: "direct".to_string(),
+            }],
+            urls: vec!["https://example.com/Snap.Hutao.1.15.0.msix".to_string()],
+            sha256: "ab".repeat(32),
+            size: Some(1024),
+            patches: vec![GenericDeltaPatch {
+                from: "1.14.0".to_string(),
+                format: "zstd".to_string(),
+                urls: vec!["https://example.com/1.14.0.patch".to_string()],
+            }],
+        };
+        let message = canonical_json(serde_json::to_value(&data).unwrap(), &["validation"]);
+        data.validation = STANDARD.encode(signing_key().sign(&message).to_bytes());
+        data
+    }
+
+    #[test]
+    fn signed_manifest_verifies() {
+        let key = trusted_key();
+        assert!(signed_manifest().verify_with(&[&key]).is_ok());
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let other = STANDARD.encode(
+            SigningKey::from_bytes(&[8u8; 32])
+                .verifying_key()
+                .to_bytes(),
+        );
+        assert!(signed_manifest().verify_with(&[&other]).is_err());
+    }
+
+    #[test]
+    fn changed_fields_are_rejected() {
+        let key = trusted_key();
+        let changes: Vec<fn(&mut GenericPatchData)> = vec![
+            |data| data.version = "1.15.1".to_string(),
+            |data| data.cache_time = "0".to_string(),
+            |data| data.mirrors[0].url = "https://evil.example/a.msix".to_string(),
+            |data| data.mirrors[0].mirror_name = "Other".to_string(),
+            |data| data.mirrors[0].mirror_type = "browser".to_string(),
+            |data| data.urls.push("https://evil.example/a.msix".to_string()),
+            |data| data.sha256 = "cd".repeat(32),
+            |data| data.size = Some(2048),
+            |data| data.size = None,
+            |data| data.patches[0].from = "1.13.0".to_string(),
+            |data| data.patches[0].urls.clear(),
+            |data| data.patches.clear(),
+        ];
+        for (idx, change) in changes.into_iter().enumerate() {
+            let mut data = signed_manifest();
+            change(&mut data);
+            assert!(data.verify_with(&[&key]).is_err(), "change #{idx} verified");
+        }
+    }
+
+    #[test]
+    fn changed_validation_is_rejected() {
+        let key = trusted_key();
+
+        let mut data = signed_manifest();
+        let mut signature = STANDARD.decode(&data.validation).unwrap();
+        signature[0] ^= 1;
+        data.validation = STANDARD.encode(signature);
+        assert!(data.verify_with(&[&key]).is_err());
+
+        let mut data = signed_manifest();
+        data.validation = String::new();
+        assert!(data.verify_with(&[&key]).is_err());
+
+        let mut data = signed_manifest();
+        data.validation = "not base64".to_string();
+        assert!(data.verify_with(&[&key]).is_err());
+    }
+}
@@ -1,29 +1,371 @@
-use clap::Subcommand;
+use crate::{
+    module::{channel::Channel, telemetry::TelemetryLevel},
+    utils::Version,
+};
+use clap::{ArgGroup, Subcommand};
+use std::path::PathBuf;
+use tracing_subscriber::filter::LevelFilter;
+
+// The version ends up in request urls and cache paths, so only its parsed
+// form is passed on.
+fn parse_version(value: &str) -> Result<String, String> {
+    match Version::from_string(value) {
+        Ok(version) => Ok(version.to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct TelemetryArgs {
+    /// Telemetry level for this run, a machine policy still takes precedence
+    #[arg(long, global = true, value_enum)]
+    pub telemetry: Option<TelemetryLevel>,
+    /// Send reports to this Sentry DSN, e.g. a self-hosted instance
+    #[arg(long, global = true)]
+    pub telemetry_dsn: Option<String>,
+    /// Do not look up or report the public IP address
+    #[arg(long, global = true)]
+    pub no_collect_ip: bool,
+    /// Do not send the device id with requests and reports
+    #[arg(long, global = true)]
+    pub no_device_id: bool,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct LogArgs {
+    /// Lowest level written to the local log: off, error, warn, info, debug or trace
+    #[arg(long, global = true)]
+    pub log_level: Option<LevelFilter>,
+    /// Write the local log to this file instead of the rotating ones
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+#[command(group(ArgGroup::new("headless").args(["silent", "dry_run"]).multiple(true)))]
+pub struct InstallArgs {
+    /// Run the whole pipeline without any UI, see README for exit codes
+    #[arg(long)]
+    pub silent: bool,
+    /// Mirror name, mirror URL or `cdn` to download the package from
+    #[arg(long, requires = "headless")]
+    pub mirror: Option<String>,
+    /// Do not create a desktop shortcut
+    #[arg(long, requires = "headless")]
+    pub no_shortcut: bool,
+    /// Launch Snap Hutao after deployment
+    #[arg(long, requires = "headless")]
+    pub launch: bool,
+    /// Close a running Snap Hutao instead of failing
+    #[arg(long, requires = "headless")]
+    pub force_close: bool,
+    /// Install this version instead of the latest one, older versions included
+    #[arg(long, requires = "headless", value_parser = parse_version)]
+    pub version: Option<String>,
+    /// Install from an offline bundle folder or zip without any network access
+    #[arg(long, requires = "headless", conflicts_with_all = ["mirror", "version"])]
+    pub from_bundle: Option<PathBuf>,
+    /// Install this msix package, it is checked against the manifest first
+    #[arg(long, requires = "headless", conflicts_with_all = ["mirror", "version", "from_bundle"])]
+    pub package: Option<PathBuf>,
+    /// Only report what would be changed, nothing is modified
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Print the dry-run plan as JSON
+    #[arg(long, requires = "dry_run")]
+    pub json: bool,
+}
+
+impl InstallArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.silent {
+            options.push_str(" --silent");
+        }
+        if let Some(mirror) = &self.mirror {
+            options.push_str(&format!(" --mirror \"{mirror}\""));
+        }
+        if self.no_shortcut {
+            options.push_str(" --no-shortcut");
+        }
+        if self.launch {
+            options.push_str(" --launch");
+        }
+        if self.force_close {
+            options.push_str(" --force-close");
+        }
+        if let Some(version) = &self.version {
+            options.push_str(&format!(" --version {version}"));
+        }
+        if let Some(path) = &self.from_bundle {
+            options.push_str(&format!(" --from-bundle \"{}\"", path.to_string_lossy()));
+        }
+        if let Some(path) = &self.package {
+            options.push_str(&format!(" --package \"{}\"", path.to_string_lossy()));
+        }
+        if self.dry_run {
+            options.push_str(" --dry-run");
+        }
+        if self.json {
+            options.push_str(" --json");
+        }
+        options
+    }
+}
 
 #[derive(Debug, Clone, clap::Args)]
 pub struct UpdateArgs {
+    /// Homa token, visible to other processes, prefer --token-stdin or --token-handle
     pub token: Option<String>,
+    /// Read the Homa token from the first line of stdin
+    #[arg(long, conflicts_with_all = ["token", "token_handle"])]
+    pub token_stdin: bool,
+    /// Read the Homa token from the first line of this inherited handle
+    #[arg(long, conflicts_with = "token")]
+    pub token_handle: Option<usize>,
+    #[command(flatten)]
+    pub options: InstallArgs,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct UninstallArgs {
+    /// Do not ask for confirmation or show the summary in a dialog
+    #[arg(long)]
+    pub silent: bool,
+    /// Remove the package for all users instead of the current user
+    #[arg(long)]
+    pub all_users: bool,
+    /// Also remove the local data of Snap Hutao
+    #[arg(long)]
+    pub remove_data: bool,
+    /// Also remove downloaded packages and installer caches
+    #[arg(long)]
+    pub remove_cache: bool,
+}
+
+impl UninstallArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.silent {
+            options.push_str(" --silent");
+        }
+        if self.all_users {
+            options.push_str(" --all-users");
+        }
+        if self.remove_data {
+            options.push_str(" --remove-data");
+        }
+        if self.remove_cache {
+            options.push_str(" --remove-cache");
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RepairArgs {
+    /// Do not ask for confirmation or show the report in a dialog
+    #[arg(long)]
+    pub silent: bool,
+    /// Mirror to download from when a reinstall is needed, see `install --mirror`
+    #[arg(long)]
+    pub mirror: Option<String>,
+}
+
+impl RepairArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.silent {
+            options.push_str(" --silent");
+        }
+        if let Some(mirror) = &self.mirror {
+            options.push_str(&format!(" --mirror \"{mirror}\""));
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ListVersionsArgs {
+    /// Print the versions as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RollbackArgs {
+    /// Do not ask for confirmation or show the result in a dialog
+    #[arg(long)]
+    pub silent: bool,
+    /// Mirror to download from when the package was not kept, see `install --mirror`
+    #[arg(long)]
+    pub mirror: Option<String>,
+}
+
+impl RollbackArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.silent {
+            options.push_str(" --silent");
+        }
+        if let Some(mirror) = &self.mirror {
+            options.push_str(&format!(" --mirror \"{mirror}\""));
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct BundleArgs {
+    /// Folder to write the bundle to, a path ending in `.zip` creates a zip
+    pub path: PathBuf,
+    /// Bundle this version instead of the latest one
+    #[arg(long, value_parser = parse_version)]
+    pub version: Option<String>,
+    /// Mirror to download the package from, see `install --mirror`
+    #[arg(long)]
+    pub mirror: Option<String>,
+}
+
+impl BundleArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = format!(" \"{}\"", self.path.to_string_lossy());
+        if let Some(version) = &self.version {
+            options.push_str(&format!(" --version {version}"));
+        }
+        if let Some(mirror) = &self.mirror {
+            options.push_str(&format!(" --mirror \"{mirror}\""));
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct PackArgs {
+    /// Offline bundle folder or zip created by `bundle`
+    pub bundle: PathBuf,
+    /// Where to write the offline installer
+    pub output: PathBuf,
+}
+
+impl PackArgs {
+    pub fn options_as_str(&self) -> String {
+        format!(
+            " \"{}\" \"{}\"",
+            self.bundle.to_string_lossy(),
+            self.output.to_string_lossy()
+        )
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct CheckUpdateArgs {
+    /// Print the result as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Language of the changelog, `zh`, `jp`, `id`, `ru` or `en`, locales such as `zh-CN` map onto them
+    #[arg(long)]
+    pub lang: Option<String>,
+}
+
+impl CheckUpdateArgs {
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.json {
+            options.push_str(" --json");
+        }
+        if let Some(lang) = &self.lang {
+            options.push_str(&format!(" --lang {lang}"));
+        }
+        options
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct PrefetchArgs {
+    /// Mirror to download the package from, see `install --mirror`
+    #[arg(long)]
+    pub mirror: Option<String>,
+}
+
+impl PrefetchArgs {
+    pub fn options_as_str(&self) -> String {
+        match &self.mirror {
+            Some(mirror) => format!(" --mirror \"{mirror}\""),
+            None => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ChannelArgs {
+    /// Channel to persist, the current one is printed when omitted
+    #[arg(value_enum)]
+    pub channel: Option<Channel>,
 }
 
 #[derive(Subcommand, Clone, Debug)]
 pub enum Command {
     #[clap(hide = true)]
-    Install,
+    Install(InstallArgs),
     #[clap(hide = true)]
     Update(UpdateArgs),
+    /// Remove Snap Hutao and what the installer created
+    Uninstall(UninstallArgs),
+    /// Fix prerequisites and a broken Snap Hutao installation
+    Repair(RepairArgs),
+    /// List the versions of Snap Hutao that can be installed
+    ListVersions(ListVersionsArgs),
+    /// Go back to the version replaced by the last upgrade
+    Rollback(RollbackArgs),
+    /// Show or persist the release channel
+    Channel(ChannelArgs),
+    /// Download everything an install needs for machines without network
+    Bundle(BundleArgs),
+    /// Append an offline bundle to this installer to make an offline installer
+    Pack(PackArgs),
+    /// Report the installed and the latest version without any UI
+    CheckUpdate(CheckUpdateArgs),
+    /// Download and verify the latest package in the background for `update`
+    Prefetch(PrefetchArgs),
 }
 
 impl Command {
     pub fn command_as_str(&self) -> String {
         match self {
-            Command::Install => "install".to_string(),
-            Command::Update(args) => {
-                if let Some(token) = &args.token {
-                    format!("update {token}")
-                } else {
-                    "update".to_string()
-                }
+            Command::Install(args) => format!("install{}", args.options_as_str()),
+            // the token is stored once imported, relaunches read it from there
+            Command::Update(args) => format!("update{}", args.options.options_as_str()),
+            Command::Uninstall(args) => format!("uninstall{}", args.options_as_str()),
+            Command::Repair(args) => format!("repair{}", args.options_as_str()),
+            Command::ListVersions(args) => {
+                format!("list-versions{}", if args.json { " --json" } else { "" })
             }
+            Command::Rollback(args) => format!("rollback{}", args.options_as_str()),
+            Command::Channel(args) => match args.channel {
+                Some(channel) => format!("channel {}", channel.as_str()),
+                None => "channel".to_string(),
+            },
+            Command::Bundle(args) => format!("bundle{}", args.options_as_str()),
+            Command::Pack(args) => format!("pack{}", args.options_as_str()),
+            Command::CheckUpdate(args) => format!("check-update{}", args.options_as_str()),
+            Command::Prefetch(args) => format!("prefetch{}", args.options_as_str()),
+        }
+    }
+
+    pub fn install_args(&self) -> Option<&InstallArgs> {
+        match self {
+            Command::Install(args) => Some(args),
+            Command::Update(args) => Some(&args.options),
+            Command::Uninstall(_)
+            | Command::Repair(_)
+            | Command::ListVersions(_)
+            | Command::Rollback(_)
+            | Command::Channel(_)
+            | Command::Bundle(_)
+            | Command::Pack(_)
+            | Command::CheckUpdate(_)
+            | Command::Prefetch(_) => None,
         }
     }
 }
@@ -1,6 +1,7 @@
 pub mod arg;
 
-use arg::Command;
+use crate::module::{channel::Channel, logging, telemetry};
+use arg::{Command, LogArgs, TelemetryArgs};
 use clap::Parser;
 
 #[derive(Parser)]
@@ -8,13 +9,40 @@ use clap::Parser;
 pub struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
+    /// Release channel for this run, overrides the persisted one
+    #[arg(long, global = true, value_enum)]
+    channel: Option<Channel>,
+    #[command(flatten)]
+    telemetry: TelemetryArgs,
+    #[command(flatten)]
+    log: LogArgs,
 }
 impl Cli {
     pub fn command(&self) -> Command {
-        self.command.clone().unwrap_or(Command::Install)
+        self.command
+            .clone()
+            .unwrap_or(Command::Install(Default::default()))
+    }
+
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel
+    }
+
+    pub fn telemetry(&self) -> &TelemetryArgs {
+        &self.telemetry
+    }
+
+    pub fn log(&self) -> &LogArgs {
+        &self.log
     }
 
     pub fn command_as_str(&self) -> String {
-        self.command().command_as_str().to_string()
+        let mut command = self.command().command_as_str();
+        if let Some(channel) = self.channel {
+            command.push_str(&format!(" --channel {}", channel.as_str()));
+        }
+        command.push_str(&telemetry::policy().options_as_str());
+        command.push_str(&logging::options_as_str());
+        command
     }
 }
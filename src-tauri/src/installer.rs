@@ -2,10 +2,21 @@ use crate::{
     REAL_CURRENT_DIR, REQUEST_CLIENT, capture_and_return_err_message_string,
     cli::arg::Command,
     fs::create_http_stream,
+    module::{
+        bundle::BundleFileKind,
+        changelog::{self, Changelog},
+        channel::{self, Channel},
+        payload,
+        pipeline::{
+            self, InstallPipeline, PipelineOptions, PipelineReport, PromptAnswer, WindowHost,
+        },
+        self_update,
+    },
     utils::{
         Version,
-        cert::{find_certificate, install_certificate},
-        dir::get_desktop,
+        authenticode::get_trusted_signer,
+        cert::{certificate_thumbprint, find_certificate, install_certificate},
+        dir::{get_desktop, get_run_dir},
         font::{get_font_path, get_font_version, install_font_permanently},
         hash::run_sha256_file_hash_async,
         package_manager::{add_package, need_migration, remove_package, try_get_hutao_version},
@@ -13,7 +24,7 @@ use crate::{
         windows_version::get_windows_version,
     },
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     path::Path,
     sync::{
@@ -22,9 +33,9 @@ use std::{
     },
     time::Instant,
 };
-use tauri::{AppHandle, Emitter, Runtime, State, WebviewWindow};
+use tauri::{AppHandle, Runtime, State, WebviewWindow};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncReadExt,
     time::{Duration, timeout},
 };
 use winreg::{RegKey, enums::HKEY_LOCAL_MACHINE};
@@ -34,17 +45,53 @@ use winsafe::{
     prelude::{ole_IPersistFile, ole_IUnknown, shell_IShellLink},
 };
 
+// Embedded payloads are zstd compressed by build.rs, which also records the
+// sha256 of the original files.
 #[cfg(feature = "offline")]
-const OFFLINE_PACKAGE_PAYLOAD: &[u8] = include_bytes!("../Snap.Hutao.msix");
+const OFFLINE_PACKAGE_PAYLOAD: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/Snap.Hutao.msix.zst"));
 
 #[cfg(not(feature = "offline"))]
 const OFFLINE_PACKAGE_PAYLOAD: &[u8] = &[];
 
-const EMBEDDED_SEGOE_FLUENT_ICON_BINARY: &[u8] = include_bytes!("../SegoeIcons.ttf");
-const EMBEDDED_SEGOE_FLUENT_ICON_NAME: &str = "Segoe Fluent Icons (TrueType)";
-const EMBEDDED_SEGOE_FLUENT_ICON_FILENAME: &str = "SegoeIcons.ttf";
+pub const EMBEDDED_PACKAGE_SHA256: &str = env!("EMBEDDED_PACKAGE_SHA256");
+
+const EMBEDDED_SEGOE_FLUENT_ICON_BINARY: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/SegoeIcons.ttf.zst"));
+const EMBEDDED_SEGOE_FLUENT_ICON_SHA256: &str = env!("EMBEDDED_FONT_SHA256");
+pub const EMBEDDED_SEGOE_FLUENT_ICON_NAME: &str = "Segoe Fluent Icons (TrueType)";
+pub const EMBEDDED_SEGOE_FLUENT_ICON_FILENAME: &str = "SegoeIcons.ttf";
 const EMBEDDED_SEGOE_FLUENT_ICON_VERSION: Version = Version::new(1, 44, 0, 0);
 
+pub const VCRT_INSTALLER_NAME: &str = "vc_redist.x64.exe";
+pub const VCRT_DOWNLOAD_URL: &str = "https://aka.ms/vs/17/release/vc_redist.x64.exe";
+pub const GLOBALSIGN_R45_SUBJECT: &str = "BE, GlobalSign nv-sa, GlobalSign Code Signing Root R45";
+pub const GLOBALSIGN_R45_URL: &str = "https://secure.globalsign.com/cacert/codesigningrootr45.crt";
+// the only certificate ever added to LocalMachine\Root
+const GLOBALSIGN_R45_THUMBPRINT: &str = "4efc31460c619ecae59c1bce2c008036d94c84b8";
+// vc_redist and the WebView2 installer are signed by Microsoft and chain to
+// one of its code signing roots
+const MICROSOFT_SIGNER: &str = "Microsoft Corporation";
+const MICROSOFT_ROOT_THUMBPRINTS: &[&str] = &[
+    // Microsoft Root Certificate Authority 2010
+    "3b1efd3a66ea28b16697394703a72ca340a05bd5",
+    // Microsoft Root Certificate Authority 2011
+    "8f43288ad272f3103b6fb1428485ea3014c0bcfe",
+];
+pub const OUTDATED_PACKAGE_FAMILY_NAME: &str = "60568DGPStudio.SnapHutao_ebfp3nyc27j86";
+pub const HUTAO_PACKAGE_FAMILY_NAME: &str = "60568DGPStudio.SnapHutao_wbnnev551gwxy";
+pub const HUTAO_APP_TARGET: &str = r#"shell:AppsFolder\60568DGPStudio.SnapHutao_wbnnev551gwxy!App"#;
+pub const LONG_PATHS_KEY: &str = r#"SYSTEM\CurrentControlSet\Control\FileSystem"#;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbeddedPackage {
+    pub name: String,
+    pub publisher: String,
+    pub version: String,
+    pub architecture: String,
+    pub sha256: String,
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct Config {
     pub version: String,
@@ -53,8 +100,9 @@ pub struct Config {
     pub skip_self_update: bool,
     pub is_offline_mode: bool,
     pub embedded_version: Option<String>,
+    pub embedded_package: Option<EmbeddedPackage>,
     pub curr_version: Option<String>,
-    pub token: Option<String>,
+    pub channel: Channel,
 }
 
 #[tauri::command]
@@ -139,27 +187,14 @@ pub async fn three_btn_custom_dialog(
     )
 }
 
-#[tauri::command]
-pub async fn need_self_update<R: Runtime>(app: AppHandle<R>) -> Result<bool, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Checking self update".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let exe_path = std::env::current_exe().unwrap();
-    let outdated = exe_path.with_extension("old");
-    let outdated_exists = tokio::fs::try_exists(&outdated).await.unwrap();
-    let _ = tokio::fs::remove_file(&outdated).await;
-
-    if outdated_exists {
-        return Ok(false);
-    }
-
-    let curr_ver = app.package_info().version.clone();
-    let curr_ver = Version::new(curr_ver.major, curr_ver.minor, curr_ver.patch, 0);
-    let url = "https://api.snapgenshin.com/patch/hutao-deployment";
-    let resp = REQUEST_CLIENT.get(url).send().await;
+pub async fn fetch_deployment_patch(
+    channel: Channel,
+) -> Result<crate::api::GenericPatchData, String> {
+    let url = format!(
+        "https://api.snapgenshin.com/patch/{}",
+        channel.deployment_patch_name()
+    );
+    let resp = REQUEST_CLIENT.get(&url).send().await;
     if resp.is_err() {
         return Err(format!("Failed to check self update: {:?}", resp.err()));
     }
@@ -176,138 +211,51 @@ pub async fn need_self_update<R: Runtime>(app: AppHandle<R>) -> Result<bool, Str
         return Err(format!("Failed to check self update: {:?}", json.message));
     }
     let data = json.data.unwrap();
-    let latest_ver = data.version;
-    let latest_ver = Version::from_string(&latest_ver);
-    if latest_ver.is_err() {
+    let res = data.verify();
+    if res.is_err() {
         return Err(format!(
-            "Failed to parse latest version: {:?}",
-            latest_ver.err()
+            "Deployment manifest verification failed: {:?}",
+            res.err()
         ));
     }
-    let latest_ver = latest_ver.unwrap();
-    Ok(curr_ver < latest_ver)
+    // every download of the new installer is checked against this hash
+    if data.sha256.is_empty() {
+        return Err("Deployment manifest has no sha256".to_string());
+    }
+    Ok(data)
 }
 
 #[tauri::command]
-pub async fn self_update<R: Runtime>(
-    args: State<'_, Command>,
-    app: AppHandle<R>,
-) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Self-updating".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub async fn need_self_update<R: Runtime>(app: AppHandle<R>) -> Result<bool, String> {
+    tracing::info!(target: "installer", "Checking self update");
     let exe_path = std::env::current_exe().unwrap();
     let outdated = exe_path.with_extension("old");
+    let outdated_exists = tokio::fs::try_exists(&outdated).await.unwrap();
     let _ = tokio::fs::remove_file(&outdated).await;
+    let _ = tokio::fs::remove_file(exe_path.with_extension("failed")).await;
 
-    let res = REQUEST_CLIENT
-        .get("https://api.qhy04.com/hutaocdn/deployment")
-        .send()
-        .await;
-    if res.is_err() {
-        return Err(format!("Failed to download new installer: {:?}", res.err()));
+    if outdated_exists {
+        return Ok(false);
     }
-    let res = res.unwrap();
-    let new_installer_blob = res.bytes().await;
-    if new_installer_blob.is_err() {
+
+    let curr_ver = app.package_info().version.clone();
+    let curr_ver = Version::new(curr_ver.major, curr_ver.minor, curr_ver.patch, 0);
+    let data = fetch_deployment_patch(channel::current().await).await?;
+    let latest_ver = data.version;
+    let latest_ver = Version::from_string(&latest_ver);
+    if latest_ver.is_err() {
         return Err(format!(
-            "Failed to get new installer content: {:?}",
-            new_installer_blob.err()
+            "Failed to parse latest version: {:?}",
+            latest_ver.err()
         ));
     }
-    let new_installer_blob = new_installer_blob.unwrap();
-
-    let mut rename_success = false;
-    let mut last_error = None;
-
-    for attempt in 1..=5 {
-        if attempt > 1 {
-            tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
-        }
-
-        match tokio::fs::rename(&exe_path, &outdated).await {
-            Ok(_) => {
-                rename_success = true;
-                break;
-            }
-            Err(e) => {
-                last_error = Some(e);
-                sentry::add_breadcrumb(sentry::Breadcrumb {
-                    category: Some("installer".to_string()),
-                    message: Some(format!("Rename attempt {} failed", attempt)),
-                    level: sentry::Level::Warning,
-                    ..Default::default()
-                });
-
-                if let Some(ref err) = last_error {
-                    if err.kind() == std::io::ErrorKind::NotFound {
-                        rename_success = true;
-                        break;
-                    }
-                }
-            }
-        }
-    }
-
-    // 写入新的安装程序
-    if rename_success {
-        let write_res = tokio::fs::write(&exe_path, new_installer_blob).await;
-        if write_res.is_err() {
-            let _ = tokio::fs::rename(&outdated, &exe_path).await;
-            capture_and_return_err_message_string!(format!(
-                "Failed to write new installer: {:?}",
-                write_res.err()
-            ));
-        }
-    } else {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("installer".to_string()),
-            message: Some("Rename failed, attempting direct overwrite".to_string()),
-            level: sentry::Level::Warning,
-            ..Default::default()
-        });
-
-        let direct_write_res = tokio::fs::write(&exe_path, new_installer_blob).await;
-        if direct_write_res.is_err() {
-            if let Some(rename_err) = last_error {
-                capture_and_return_err_message_string!(format!(
-                    "Failed to rename executable (last attempt): {:?}. Direct overwrite also failed: {:?}",
-                    rename_err,
-                    direct_write_res.err()
-                ));
-            } else {
-                capture_and_return_err_message_string!(format!(
-                    "Failed to update executable: {:?}",
-                    direct_write_res.err()
-                ));
-            }
-        }
-    }
-
-    crate::module::singleton::destroy_plugin(&app);
-
-    process::run(
-        true,
-        &exe_path,
-        REAL_CURRENT_DIR.clone().into(),
-        Some(args.inner().clone().command_as_str()),
-    );
-    app.exit(0);
-
-    Ok(())
+    let latest_ver = latest_ver.unwrap();
+    Ok(curr_ver < latest_ver)
 }
 
 #[tauri::command]
 pub async fn open_browser(url: String) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some(format!("Opening browser: {url}")),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Opening browser: {url}");
     if webbrowser::open(&url).is_ok() {
         Ok(())
     } else {
@@ -320,31 +268,24 @@ pub async fn get_config<R: Runtime>(
     args: State<'_, Command>,
     app: AppHandle<R>,
 ) -> Result<Config, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Getting config".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Getting config");
+    // the frontend asks for its config first, so a self update made it here
+    self_update::complete_handshake();
 
     let curr_ver = app.package_info().version.clone();
     let curr_ver = Version::new(curr_ver.major, curr_ver.minor, curr_ver.patch, 0);
-    let offline = env!("BUILD_MODE") == "offline";
-    let embedded_version = if offline {
-        Some(
-            Version::from_string(env!("EMBEDDED_VERSION"))
-                .unwrap()
-                .to_string(),
-        )
-    } else {
-        None
-    };
+    let embedded_package = embedded_package();
+    let embedded_version = embedded_package
+        .as_ref()
+        .map(|p| Version::from_string(&p.version).unwrap().to_string());
+    let offline = embedded_version.is_some();
 
     let need_migration = need_migration();
     let exists = try_get_hutao_version();
+    let channel = channel::current().await;
 
     let command = args.inner().clone();
-    if let Command::Update(update_args) = command {
+    if let Command::Update(_) = command {
         return Ok(Config {
             version: curr_ver.to_string(),
             is_update: true,
@@ -352,8 +293,9 @@ pub async fn get_config<R: Runtime>(
             skip_self_update: false,
             is_offline_mode: false,
             embedded_version,
+            embedded_package: embedded_package.clone(),
             curr_version: exists,
-            token: update_args.token,
+            channel,
         });
     }
 
@@ -364,32 +306,27 @@ pub async fn get_config<R: Runtime>(
         skip_self_update: offline,
         is_offline_mode: offline,
         embedded_version,
+        embedded_package,
         curr_version: exists,
-        token: None,
+        channel,
     })
 }
 
 #[tauri::command]
-pub async fn get_changelog(lang: String, from: String) -> Result<String, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some(format!("Getting {lang} changelog: {from}")),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let url = format!("https://api.qhy04.com/hutaocdn/changelog?lang={lang}&from={from}");
-    let res = REQUEST_CLIENT.get(&url).send().await;
-    if res.is_err() {
-        return Err(format!("Failed to send http request: {:?}", res.err()));
+pub async fn get_changelog(
+    lang: String,
+    from: String,
+    to: Option<String>,
+    html: Option<bool>,
+) -> Result<Changelog, String> {
+    tracing::info!(target: "installer", "Getting {lang} changelog: {from} -> {to:?}");
+    let changelog =
+        changelog::get_changelog(&lang, &from, to.as_deref(), html.unwrap_or(false)).await;
+    if changelog.is_err() {
+        return Err(format!("Failed to get changelog: {:?}", changelog.err()));
     }
 
-    let res = res.unwrap();
-    let ctnt = res.text().await;
-    if ctnt.is_err() {
-        return Err(format!("Failed to get response content: {:?}", ctnt.err()));
-    }
-
-    Ok(ctnt.unwrap())
+    Ok(changelog.unwrap())
 }
 
 #[tauri::command]
@@ -637,61 +574,86 @@ pub async fn head_package(mirror_url: String) -> Result<u64, String> {
     Ok(len.unwrap())
 }
 
-#[tauri::command]
-pub async fn extract_package() -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Extracting package".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let temp_dir = std::env::temp_dir();
-    let installer_path = temp_dir.join("Snap.Hutao.msix");
+fn is_offline_build() -> bool {
+    env!("BUILD_MODE") == "offline"
+}
 
-    let decompressed_data = decompress(OFFLINE_PACKAGE_PAYLOAD);
-    if decompressed_data.is_err() {
-        capture_and_return_err_message_string!(format!(
-            "Failed to decompress offline package: {:?}",
-            decompressed_data.err()
-        ));
+// Offline installers have the package compiled in, or appended to a stock
+// binary by `pack`. Either way its identity was read from the package itself.
+pub fn embedded_package() -> Option<EmbeddedPackage> {
+    if is_offline_build() {
+        return Some(EmbeddedPackage {
+            name: env!("EMBEDDED_PACKAGE_NAME").to_string(),
+            publisher: env!("EMBEDDED_PACKAGE_PUBLISHER").to_string(),
+            version: env!("EMBEDDED_VERSION").to_string(),
+            architecture: env!("EMBEDDED_PACKAGE_ARCHITECTURE").to_string(),
+            sha256: EMBEDDED_PACKAGE_SHA256.to_string(),
+        });
     }
-    let decompressed_data = decompressed_data?;
+    payload::get().map(|payload| payload.index.package.clone())
+}
 
-    let file = tokio::fs::File::create(installer_path).await;
-    if file.is_err() {
-        capture_and_return_err_message_string!(format!(
-            "Failed to create installer: {:?}",
-            file.err()
-        ));
+pub fn embedded_version() -> Option<String> {
+    embedded_package().map(|package| package.version)
+}
+
+pub fn embedded_package_sha256() -> String {
+    embedded_package()
+        .map(|package| package.sha256)
+        .unwrap_or_default()
+}
+
+// Size of the embedded package once extracted, taken from the zstd frame.
+pub fn embedded_package_size() -> u64 {
+    if !is_offline_build() {
+        return payload::get()
+            .and_then(|payload| payload.entry(BundleFileKind::Package))
+            .map(|entry| entry.raw_size)
+            .unwrap_or_default();
     }
+    zstd::zstd_safe::get_frame_content_size(OFFLINE_PACKAGE_PAYLOAD)
+        .ok()
+        .flatten()
+        .unwrap_or(OFFLINE_PACKAGE_PAYLOAD.len() as u64)
+}
+
+pub async fn extract_package() -> Result<(), String> {
+    tracing::info!(target: "installer", "Extracting package");
+    let temp_dir = std::env::temp_dir();
+    let installer_path = temp_dir.join("Snap.Hutao.msix");
 
-    let mut file = file.unwrap();
-    let write_res = file.write_all(&decompressed_data).await;
-    if write_res.is_err() {
+    let res = if is_offline_build() {
+        extract_embedded(
+            OFFLINE_PACKAGE_PAYLOAD,
+            &installer_path,
+            EMBEDDED_PACKAGE_SHA256,
+        )
+        .await
+    } else {
+        match payload::extract(BundleFileKind::Package, &installer_path).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("No offline package is embedded".to_string()),
+            Err(e) => Err(e),
+        }
+    };
+    if res.is_err() {
         capture_and_return_err_message_string!(format!(
-            "Failed to write installer: {:?}",
-            write_res.err()
+            "Failed to extract offline package: {}",
+            res.err().unwrap()
         ));
     }
     Ok(())
 }
 
-#[tauri::command]
 pub async fn download_package(
-    mirror_url: String,
-    id: String,
-    window: WebviewWindow,
+    mirror_url: &str,
+    on_progress: impl Fn(usize, u64) + Send + Sync + 'static,
 ) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some(format!("Downloading package from {mirror_url}")),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Downloading package from {mirror_url}");
     let temp_dir = std::env::temp_dir();
     let installer_path = temp_dir.join("Snap.Hutao.msix");
 
-    let total_size = crate::fs::get_content_length(&mirror_url).await;
+    let total_size = crate::fs::get_content_length(mirror_url).await;
     if total_size.is_err() {
         return Err(format!(
             "Failed to get content length: {:?}",
@@ -701,11 +663,11 @@ pub async fn download_package(
     let total_size = total_size.unwrap();
 
     let progress_noti = move |downloaded: usize| {
-        let _ = window.emit(&id, serde_json::json!((downloaded, total_size)));
+        on_progress(downloaded, total_size);
     };
 
     let res = crate::fs::multi_threaded_download(
-        &mirror_url,
+        mirror_url,
         installer_path.as_os_str().to_str().unwrap(),
         progress_noti,
     )
@@ -718,14 +680,8 @@ pub async fn download_package(
     Ok(())
 }
 
-#[tauri::command]
 pub async fn check_vcrt() -> Result<bool, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Checking vcrt".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Checking vcrt");
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
     let x64_path = r#"SOFTWARE\Microsoft\VisualStudio\14.0\VC\Runtimes\x64"#.to_string();
     let x86_path =
@@ -746,30 +702,62 @@ pub async fn check_vcrt() -> Result<bool, String> {
     Ok(false)
 }
 
-#[tauri::command]
-pub async fn install_vcrt(id: String, window: WebviewWindow) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Installing vcrt".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+// Prerequisite installers run elevated, so whatever their source is, they
+// must come from Microsoft.
+pub fn verify_microsoft_signature(path: &Path) -> Result<(), String> {
+    let signer = get_trusted_signer(path);
+    if signer.is_err() {
+        return Err(format!(
+            "{} is not signed: {:?}",
+            path.to_string_lossy(),
+            signer.err()
+        ));
+    }
+    let signer = signer.unwrap();
+    if signer.name != MICROSOFT_SIGNER
+        || !MICROSOFT_ROOT_THUMBPRINTS.contains(&signer.root_thumbprint.as_str())
+    {
+        return Err(format!(
+            "{} is signed by {}, not by Microsoft",
+            path.to_string_lossy(),
+            signer.name
+        ));
+    }
+    Ok(())
+}
 
-    const VCRT_INSTALLER_NAME: &str = "vc_redist.x64.exe";
+// Returns whether the runtime asks for a reboot to finish installing. A
+// `bundled` installer is used instead of downloading one, both have to carry
+// a Microsoft signature.
+pub async fn install_vcrt(
+    bundled: Option<&Path>,
+    on_progress: impl Fn(usize, u64) + Send + Sync + 'static,
+) -> Result<bool, String> {
+    tracing::info!(target: "installer", "Installing vcrt");
 
-    let temp_dir = std::env::temp_dir();
-    let installer_path = temp_dir.join(VCRT_INSTALLER_NAME);
+    // the installer runs elevated, it must not be replaceable once verified
+    let run_dir = get_run_dir();
+    if run_dir.is_err() {
+        return Err(format!(
+            "Failed to create run directory: {:?}",
+            run_dir.err()
+        ));
+    }
+    let installer_path = run_dir.unwrap().join(VCRT_INSTALLER_NAME);
 
     let installer_running_status =
         is_process_running(VCRT_INSTALLER_NAME.to_string(), None).unwrap_or_default();
-    if !installer_running_status.0 {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("installer".to_string()),
-            message: Some("Downloading vcrt".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
-        let url = "https://aka.ms/vs/17/release/vc_redist.x64.exe";
+    if let (false, Some(bundled)) = (installer_running_status.0, bundled) {
+        let res = tokio::fs::copy(bundled, &installer_path).await;
+        if res.is_err() {
+            return Err(format!(
+                "Failed to copy bundled vcrt installer: {:?}",
+                res.err()
+            ));
+        }
+    } else if !installer_running_status.0 {
+        tracing::info!(target: "installer", "Downloading vcrt");
+        let url = VCRT_DOWNLOAD_URL;
 
         let total_size = crate::fs::get_content_length(url).await;
         if total_size.is_err() {
@@ -781,7 +769,7 @@ pub async fn install_vcrt(id: String, window: WebviewWindow) -> Result<(), Strin
         let total_size = total_size.unwrap();
 
         let progress_noti = move |downloaded: usize| {
-            let _ = window.emit(&id, serde_json::json!((downloaded, total_size)));
+            on_progress(downloaded, total_size);
         };
 
         let res = crate::fs::multi_threaded_download(
@@ -800,20 +788,11 @@ pub async fn install_vcrt(id: String, window: WebviewWindow) -> Result<(), Strin
     }
 
     let id = if installer_running_status.0 {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("installer".to_string()),
-            message: Some("VCRT installer running, wait for it".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+        tracing::info!(target: "installer", "VCRT installer running, wait for it");
         installer_running_status.1.unwrap()
     } else {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("installer".to_string()),
-            message: Some("Spawning vcrt installer".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+        tracing::info!(target: "installer", "Spawning vcrt installer");
+        verify_microsoft_signature(&installer_path)?;
         let cmd = tokio::process::Command::new(&installer_path)
             .arg("/install")
             .arg("/quiet")
@@ -841,18 +820,15 @@ pub async fn install_vcrt(id: String, window: WebviewWindow) -> Result<(), Strin
         capture_and_return_err_message_string!(format!("VCRT installer failed: {:?}", status));
     }
     let _ = tokio::fs::remove_file(installer_path).await;
-    Ok(())
+    Ok(code == 3010)
 }
 
-#[tauri::command]
-pub async fn check_globalsign_r45(window: WebviewWindow) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Checking globalsign r45 certificate".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let find_res = find_certificate("BE, GlobalSign nv-sa, GlobalSign Code Signing Root R45").await;
+pub async fn check_globalsign_r45(
+    window: Option<WebviewWindow>,
+    bundled: Option<&Path>,
+) -> Result<(), String> {
+    tracing::info!(target: "installer", "Checking globalsign r45 certificate");
+    let find_res = find_certificate(GLOBALSIGN_R45_SUBJECT).await;
     if find_res.is_err() {
         return Err(format!("Failed to find certificate: {:?}", find_res.err()));
     }
@@ -862,14 +838,51 @@ pub async fn check_globalsign_r45(window: WebviewWindow) -> Result<(), String> {
         return Ok(());
     }
 
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Installing globalsign r45 certificate".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let url = "https://secure.globalsign.com/cacert/codesigningrootr45.crt";
-    let res = REQUEST_CLIENT.get(url).send().await;
+    tracing::info!(target: "installer", "Installing globalsign r45 certificate");
+    let cert_ctnt = match bundled {
+        Some(bundled) => {
+            let cert_ctnt = tokio::fs::read(bundled).await;
+            if cert_ctnt.is_err() {
+                return Err(format!(
+                    "Failed to read bundled certificate: {:?}",
+                    cert_ctnt.err()
+                ));
+            }
+            cert_ctnt.unwrap().into()
+        }
+        None => download_globalsign_r45().await?,
+    };
+    verify_globalsign_r45(&cert_ctnt)?;
+    let install_res = install_certificate(cert_ctnt, window).await;
+    if install_res.is_err() {
+        return Err(format!(
+            "Failed to install certificate: {:?}",
+            install_res.err()
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn verify_globalsign_r45(content: &[u8]) -> Result<(), String> {
+    let thumbprint = certificate_thumbprint(content);
+    if thumbprint.is_err() {
+        return Err(format!(
+            "Failed to read certificate: {:?}",
+            thumbprint.err()
+        ));
+    }
+    let thumbprint = thumbprint.unwrap();
+    if thumbprint != GLOBALSIGN_R45_THUMBPRINT {
+        return Err(format!(
+            "Certificate {thumbprint} is not GlobalSign Code Signing Root R45"
+        ));
+    }
+    Ok(())
+}
+
+pub async fn download_globalsign_r45() -> Result<tokio_util::bytes::Bytes, String> {
+    let res = REQUEST_CLIENT.get(GLOBALSIGN_R45_URL).send().await;
     if res.is_err() {
         return Err(format!("Failed to send http request: {:?}", res.err()));
     }
@@ -882,27 +895,11 @@ pub async fn check_globalsign_r45(window: WebviewWindow) -> Result<(), String> {
             cert_ctnt.err()
         ));
     }
-
-    let cert_ctnt = cert_ctnt.unwrap();
-    let install_res = install_certificate(cert_ctnt, window).await;
-    if install_res.is_err() {
-        return Err(format!(
-            "Failed to install certificate: {:?}",
-            install_res.err()
-        ));
-    }
-
-    Ok(())
+    Ok(cert_ctnt.unwrap())
 }
 
-#[tauri::command]
 pub async fn check_segoe_fluent_icons_font() -> Result<bool, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Checking Segoe Fluent Icons font".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Checking Segoe Fluent Icons font");
 
     let win_ver = get_windows_version();
     if win_ver.build >= 22000 {
@@ -938,41 +935,26 @@ pub async fn check_segoe_fluent_icons_font() -> Result<bool, String> {
     Ok(font_version >= EMBEDDED_SEGOE_FLUENT_ICON_VERSION)
 }
 
-#[tauri::command]
+pub async fn extract_segoe_fluent_icons_font(path: &Path) -> Result<(), String> {
+    extract_embedded(
+        EMBEDDED_SEGOE_FLUENT_ICON_BINARY,
+        path,
+        EMBEDDED_SEGOE_FLUENT_ICON_SHA256,
+    )
+    .await
+}
+
 pub async fn install_segoe_fluent_icons_font() -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Installing Segoe Fluent Icons font".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Installing Segoe Fluent Icons font");
 
     let temp_dir = std::env::temp_dir();
     let font_file = temp_dir.join(EMBEDDED_SEGOE_FLUENT_ICON_FILENAME);
 
-    let decompressed_data = decompress(EMBEDDED_SEGOE_FLUENT_ICON_BINARY);
-    if decompressed_data.is_err() {
-        capture_and_return_err_message_string!(format!(
-            "Failed to decompress embedded font: {:?}",
-            decompressed_data.err()
-        ));
-    }
-    let decompressed_data = decompressed_data?;
-
-    let file = tokio::fs::File::create(&font_file).await;
-    if file.is_err() {
-        capture_and_return_err_message_string!(format!(
-            "Failed to create font file: {:?}",
-            file.err()
-        ));
-    }
-
-    let mut file = file.unwrap();
-    let write_res = file.write_all(&decompressed_data).await;
-    if write_res.is_err() {
+    let res = extract_segoe_fluent_icons_font(&font_file).await;
+    if res.is_err() {
         capture_and_return_err_message_string!(format!(
-            "Failed to write font file: {:?}",
-            write_res.err()
+            "Failed to extract embedded font: {}",
+            res.err().unwrap()
         ));
     }
 
@@ -989,33 +971,23 @@ pub async fn install_segoe_fluent_icons_font() -> Result<(), String> {
     Ok(())
 }
 
-#[tauri::command]
-pub async fn check_win32_long_path_support() -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Checking Win32 long path support".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+pub fn is_win32_long_path_enabled() -> Result<bool, String> {
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    let key = hklm.open_subkey(r#"SYSTEM\CurrentControlSet\Control\FileSystem"#);
+    let key = hklm.open_subkey(LONG_PATHS_KEY);
     if key.is_err() {
         return Err(format!("Failed to open registry key: {:?}", key.err()));
     }
     let key = key.unwrap();
-    let value = key.get_value::<u32, _>("LongPathsEnabled").unwrap_or(0);
-    if value == 0 {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("installer".to_string()),
-            message: Some("Enabling long path support".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+    Ok(key.get_value::<u32, _>("LongPathsEnabled").unwrap_or(0) != 0)
+}
+
+pub async fn check_win32_long_path_support() -> Result<(), String> {
+    tracing::info!(target: "installer", "Checking Win32 long path support");
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    if !is_win32_long_path_enabled()? {
+        tracing::info!(target: "installer", "Enabling long path support");
 
-        let key = hklm.open_subkey_with_flags(
-            r#"SYSTEM\CurrentControlSet\Control\FileSystem"#,
-            winreg::enums::KEY_SET_VALUE,
-        );
+        let key = hklm.open_subkey_with_flags(LONG_PATHS_KEY, winreg::enums::KEY_SET_VALUE);
         if key.is_err() {
             return Err(format!(
                 "Failed to open registry key for writing: {:?}",
@@ -1034,7 +1006,6 @@ pub async fn check_win32_long_path_support() -> Result<(), String> {
     Ok(())
 }
 
-#[tauri::command]
 pub async fn is_hutao_running() -> Result<(bool, Option<u32>), String> {
     is_process_running(
         "Snap.Hutao.exe".to_string(),
@@ -1042,14 +1013,8 @@ pub async fn is_hutao_running() -> Result<(bool, Option<u32>), String> {
     )
 }
 
-#[tauri::command]
 pub async fn kill_process(pid: u32) -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some(format!("Killing process {pid}")),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Killing process {pid}");
 
     if !is_process_running_by_pid(pid) {
         return Ok(());
@@ -1084,15 +1049,9 @@ pub async fn kill_process(pid: u32) -> Result<(), String> {
     Ok(())
 }
 
-#[tauri::command]
 pub async fn remove_outdated_package() -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Removing outdated package".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let res = remove_package("60568DGPStudio.SnapHutao_ebfp3nyc27j86".to_string());
+    tracing::info!(target: "installer", "Removing outdated package");
+    let res = remove_package(OUTDATED_PACKAGE_FAMILY_NAME.to_string(), true);
     if res.is_err() {
         capture_and_return_err_message_string!(format!(
             "Failed to remove package: {:?}",
@@ -1103,41 +1062,39 @@ pub async fn remove_outdated_package() -> Result<(), String> {
     Ok(())
 }
 
-#[tauri::command]
 pub async fn install_package(
     sha256: String,
-    id: String,
     offline_mode: bool,
-    window: WebviewWindow,
+    interactive: bool,
+    allow_downgrade: bool,
+    on_progress: impl Fn(serde_json::Value) + Send + 'static,
 ) -> Result<bool, String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Installing package".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "installer", "Installing package");
     let temp_dir = std::env::temp_dir();
     let installer_path = temp_dir.join("Snap.Hutao.msix");
-    if !offline_mode {
-        let hash = run_sha256_file_hash_async(installer_path.to_str().unwrap()).await;
-        if hash.is_err() {
-            capture_and_return_err_message_string!(format!(
-                "Failed to hash installer: {:?}",
-                hash.err()
-            ));
-        }
+    let expected = if offline_mode {
+        embedded_package_sha256()
+    } else {
+        sha256
+    };
+    let hash = run_sha256_file_hash_async(installer_path.to_str().unwrap()).await;
+    if hash.is_err() {
+        capture_and_return_err_message_string!(format!(
+            "Failed to hash installer: {:?}",
+            hash.err()
+        ));
+    }
 
-        let hash = hash.unwrap();
-        if hash != sha256 {
-            return Err("Installer hash mismatch".to_string());
-        }
+    let hash = hash.unwrap();
+    if hash != expected {
+        return Err("Installer hash mismatch".to_string());
     }
 
     let install_res = add_package(
         installer_path.as_os_str().to_str().unwrap().to_string(),
-        move |opr| {
-            let _ = window.emit(&id, opr);
-        },
+        interactive,
+        allow_downgrade,
+        on_progress,
     );
     if install_res.is_err() {
         capture_and_return_err_message_string!(format!(
@@ -1146,25 +1103,19 @@ pub async fn install_package(
         ));
     }
 
-    if install_res.unwrap() {
-        let _ = tokio::fs::remove_file(installer_path).await;
-        Ok(true)
-    } else {
-        Ok(false)
-    }
+    // a deployed package is left in place for the pipeline to keep
+    Ok(install_res.unwrap())
+}
+
+pub fn get_desktop_lnk_path(desktop: &str) -> String {
+    format!(r#"{desktop}\Snap Hutao.lnk"#)
 }
 
-#[tauri::command]
 pub async fn create_desktop_lnk() -> Result<(), String> {
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("installer".to_string()),
-        message: Some("Creating desktop lnk".to_string()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-    let target = r#"shell:AppsFolder\60568DGPStudio.SnapHutao_wbnnev551gwxy!App"#.to_string();
+    tracing::info!(target: "installer", "Creating desktop lnk");
+    let target = HUTAO_APP_TARGET.to_string();
     let desktop = get_desktop().unwrap();
-    let lnk = format!(r#"{desktop}\Snap Hutao.lnk"#);
+    let lnk = get_desktop_lnk_path(&desktop);
 
     let desktop_path = Path::new(&desktop);
 
@@ -1225,6 +1176,27 @@ pub async fn create_desktop_lnk() -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+pub async fn run_install_pipeline(
+    options: PipelineOptions,
+    id: String,
+    window: WebviewWindow,
+) -> Result<PipelineReport, String> {
+    tracing::info!(target: "installer", "Running install pipeline");
+    let pipeline = InstallPipeline::new(options, WindowHost { window, id });
+    let res = pipeline.run().await;
+    if res.is_err() {
+        return Err(format!("{}", res.err().unwrap()));
+    }
+
+    Ok(res.unwrap())
+}
+
+#[tauri::command]
+pub async fn answer_install_prompt(id: u64, answer: PromptAnswer) -> Result<(), String> {
+    pipeline::answer_prompt(id, answer)
+}
+
 #[tauri::command]
 pub async fn exit(app: AppHandle) {
     app.exit(0);
@@ -1232,31 +1204,48 @@ pub async fn exit(app: AppHandle) {
 
 #[tauri::command]
 pub async fn launch_and_exit(app: AppHandle) {
-    let target = r#"shell:AppsFolder\60568DGPStudio.SnapHutao_wbnnev551gwxy!App"#.to_string();
+    let target = HUTAO_APP_TARGET.to_string();
     process::run(true, target, REAL_CURRENT_DIR.clone().into(), None::<&str>);
     app.exit(0);
 }
 
-fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
-    let mut decompressed_data = Vec::new();
-    #[cfg(debug_assertions)]
-    {
-        decompressed_data.extend_from_slice(data);
-        if decompressed_data.is_empty() {
-            return Err("Offline package payload is empty".to_string());
-        }
+// Streams a zstd compressed payload to `path` without holding it in memory,
+// then checks the result against the sha256 recorded when it was packed.
+pub async fn extract_embedded(
+    data: impl std::io::Read + Send + 'static,
+    path: &Path,
+    sha256: &str,
+) -> Result<(), String> {
+    let target = path.to_path_buf();
+    let res = tokio::task::spawn_blocking(move || -> Result<u64, std::io::Error> {
+        let mut decoder = zstd::stream::read::Decoder::new(data)?;
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&target)?);
+        let written = std::io::copy(&mut decoder, &mut writer)?;
+        std::io::Write::flush(&mut writer)?;
+        Ok(written)
+    })
+    .await;
+    if res.is_err() {
+        return Err(format!("Failed to run extraction: {:?}", res.err()));
     }
-    #[cfg(not(debug_assertions))]
-    {
-        use std::io::Read;
-        let mut decoder = flate2::read::GzDecoder::new(data);
-        let decompress_res = decoder.read_to_end(&mut decompressed_data);
-        if decompress_res.is_err() {
-            return Err(format!(
-                "Failed to decompress data: {:?}",
-                decompress_res.err()
-            ));
-        }
+    let res = res.unwrap();
+    if res.is_err() {
+        return Err(format!("Failed to decompress payload: {:?}", res.err()));
+    }
+
+    let hash = run_sha256_file_hash_async(path.to_str().unwrap()).await;
+    if hash.is_err() {
+        return Err(format!(
+            "Failed to hash extracted payload: {:?}",
+            hash.err()
+        ));
     }
-    Ok(decompressed_data)
+    if hash.unwrap() != sha256 {
+        let _ = tokio::fs::remove_file(path).await;
+        return Err(format!(
+            "Extracted {} does not match the build-time hash",
+            path.to_string_lossy()
+        ));
+    }
+    Ok(())
 }
@@ -21,6 +21,7 @@ use sentry::protocol::Context;
 use std::collections::BTreeMap;
 use tauri::{WindowEvent, window::Color};
 use tauri_utils::{WindowEffect, config::WindowEffectsConfig};
+use tracing::Instrument;
 use winreg::{RegKey, enums::HKEY_CURRENT_USER};
 
 lazy_static::lazy_static! {
@@ -67,6 +68,9 @@ fn ua_string() -> String {
 
 fn hutao_trace_headers() -> HeaderMap {
     let mut headers = HeaderMap::new();
+    if !module::telemetry::policy().send_device_id {
+        return headers;
+    }
     let hutao_device_id = get_device_id();
     if hutao_device_id.is_err() {
         return headers;
@@ -88,40 +92,65 @@ fn main() {
         std::env::set_var("RUST_LIB_BACKTRACE", "1");
     }
 
-    let _guard = sentry::init((
-        "https://59ff148bff0f509baf01516d1f075d11@sentry.snapgenshin.com/10",
-        sentry::ClientOptions {
-            release: sentry::release_name!(),
-            debug: cfg!(debug_assertions),
-            auto_session_tracking: true,
-            max_breadcrumbs: 1000,
-            sample_rate: 1.0,
-            traces_sample_rate: 1.0,
-            ..Default::default()
-        },
-    ));
-
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("app".into()),
-        message: Some("HutaoInstaller started".into()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
-
+    // parsed first, the telemetry policy decides how Sentry is set up
     let cli = cli::Cli::parse();
+    let telemetry = module::telemetry::init(cli.telemetry());
+    let _guard = sentry::init(telemetry.client_options());
+    module::logging::init(cli.log());
+
+    tracing::info!(
+        target: "app",
+        "HutaoInstaller {} started",
+        env!("CARGO_PKG_VERSION")
+    );
+
     let command = cli.command();
-    tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(configure_sentry_scope(cli.command_as_str()));
+    if let Some(channel) = cli.channel() {
+        module::channel::set_override(channel);
+    }
+    // the token is imported before anything can relaunch the installer
+    // an install from a bundle must not touch the network
+    let offline = command
+        .install_args()
+        .is_some_and(|args| args.from_bundle.is_some());
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        import_update_token(&command).await;
+        configure_sentry_scope(cli.command_as_str(), offline).await;
+    });
+
+    // headless runs never touch WebView2, see README.md for the exit codes
+    let span = module::logging::command_span(&command);
+    match &command {
+        Command::Uninstall(args) => run_headless(_guard, span, module::uninstall::run(args)),
+        Command::Repair(args) => run_headless(_guard, span, module::repair::run(args)),
+        Command::Channel(args) => run_headless(_guard, span, module::channel::run(args)),
+        Command::Bundle(args) => run_headless(_guard, span, module::bundle::run_create(args)),
+        Command::Pack(args) => run_headless(_guard, span, module::payload::run_pack(args)),
+        Command::CheckUpdate(args) => run_headless(_guard, span, module::prefetch::run_check(args)),
+        Command::Prefetch(args) => run_headless(_guard, span, module::prefetch::run_prefetch(args)),
+        Command::ListVersions(args) => run_headless(_guard, span, module::versions::run_list(args)),
+        Command::Rollback(args) => run_headless(_guard, span, module::versions::run_rollback(args)),
+        _ => {}
+    }
+    if command
+        .install_args()
+        .is_some_and(|args| args.silent || args.dry_run)
+    {
+        run_headless(_guard, span, async {
+            let res = module::control::start();
+            if res.is_err() {
+                let e = res.err().unwrap();
+                eprintln!("Failed to start control channel: {e:?}");
+                sentry_anyhow::capture_anyhow(&e);
+                return module::headless::ExitCode::Failure;
+            }
+            module::headless::run(command).await
+        });
+    }
 
     let wv2ver = tauri::webview_version();
     if wv2ver.is_err() {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("webview2".into()),
-            message: Some("WebView2 not installed".into()),
-            level: sentry::Level::Warning,
-            ..Default::default()
-        });
+        tracing::warn!(target: "webview2", "WebView2 not installed");
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
@@ -134,11 +163,36 @@ fn main() {
         .enable_all()
         .build()
         .unwrap()
-        .block_on(tauri_main(command));
+        .block_on(tauri_main(command).instrument(span));
+}
+
+// Runs a headless command to completion and exits with its code, Sentry is
+// flushed before the process goes away.
+fn run_headless(
+    guard: sentry::ClientInitGuard,
+    span: tracing::Span,
+    fut: impl std::future::Future<Output = module::headless::ExitCode>,
+) -> ! {
+    let code = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(fut.instrument(span));
+    drop(guard);
+    std::process::exit(code as i32);
 }
 
 async fn tauri_main(args: Command) {
     tauri::async_runtime::set(tokio::runtime::Handle::current());
+    let res = module::control::start();
+    if res.is_err() {
+        rfd::MessageDialog::new()
+            .set_title("错误")
+            .set_description("无法创建控制通道")
+            .set_level(rfd::MessageLevel::Error)
+            .show();
+        capture_and_return!(res.err().unwrap());
+    }
     let win_ver = get_windows_version();
     let win10_22h2_ver = Version::new(10, 0, 19045, 5371);
     let win11_ver = Version::new(10, 0, 22000, 0);
@@ -218,34 +272,30 @@ async fn tauri_main(args: Command) {
             api::homa_use_redeem_code,
             api::homa_fetch_cdn,
             api::homa_fetch_userinfo,
+            api::homa_get_session,
+            api::homa_logout,
             installer::error_dialog,
             installer::confirm_dialog,
             installer::message_dialog,
             installer::two_btn_custom_dialog,
             installer::three_btn_custom_dialog,
             installer::need_self_update,
-            installer::self_update,
+            module::self_update::self_update,
             installer::get_config,
             installer::get_changelog,
             installer::open_browser,
             installer::speedtest_5mb,
             installer::check_temp_package_valid,
             installer::head_package,
-            installer::extract_package,
-            installer::download_package,
-            installer::check_vcrt,
-            installer::install_vcrt,
-            installer::check_globalsign_r45,
-            installer::check_segoe_fluent_icons_font,
-            installer::install_segoe_fluent_icons_font,
-            installer::check_win32_long_path_support,
-            installer::is_hutao_running,
-            installer::kill_process,
-            installer::remove_outdated_package,
-            installer::install_package,
-            installer::create_desktop_lnk,
+            installer::run_install_pipeline,
+            installer::answer_install_prompt,
             installer::exit,
-            installer::launch_and_exit
+            installer::launch_and_exit,
+            module::sideload::pick_package,
+            module::prefetch::stage_prefetched_package,
+            module::browser_download::watch_browser_download,
+            module::browser_download::stop_browser_download_watch,
+            module::browser_download::pick_browser_download_dir
         ])
         .manage(args)
         .setup(move |app| {
@@ -267,14 +317,7 @@ async fn tauri_main(args: Command) {
                 main_window = main_window.data_directory(temp_dir_for_data).visible(false);
             }
             let main_window = main_window.build().unwrap();
-            sentry::add_breadcrumb(
-                sentry::Breadcrumb {
-                    category: Some("app".into()),
-                    message: Some("Main window created".into()),
-                    level: sentry::Level::Info,
-                    ..Default::default()
-                },
-            );
+            tracing::info!(target: "app", "Main window created");
             #[cfg(debug_assertions)]
             {
                 let window = tauri::Manager::get_webview_window(app, "main");
@@ -317,8 +360,44 @@ async fn tauri_main(args: Command) {
         .expect("error while running tauri application");
 }
 
-async fn configure_sentry_scope(command: String) {
-    let ip_address = api::generic_get_ip_info().await.unwrap_or_default().ip;
+// Snap Hutao hands over its session when it starts an update, relaunched
+// installers find it stored by passport and never see it in argv
+async fn import_update_token(args: &Command) {
+    let Command::Update(update_args) = args else {
+        return;
+    };
+    let token = if update_args.token_stdin {
+        utils::secret::read_stdin().map(Some)
+    } else if let Some(handle) = update_args.token_handle {
+        utils::secret::read_handle(handle).map(Some)
+    } else {
+        Ok(update_args.token.clone())
+    };
+    let res = match token {
+        Ok(Some(token)) => module::passport::set_token(token).await,
+        Ok(None) => return,
+        Err(e) => Err(e),
+    };
+    if res.is_err() {
+        tracing::warn!(target: "passport", "Failed to store update token: {:?}", res.err());
+    }
+}
+
+async fn configure_sentry_scope(command: String, offline: bool) {
+    let policy = module::telemetry::policy();
+    if policy.level == module::telemetry::TelemetryLevel::Off {
+        return;
+    }
+    let ip_address = if policy.collect_ip && !offline {
+        api::generic_get_ip_info().await.ok().map(|info| info.ip)
+    } else {
+        None
+    };
+    let device_id = if policy.send_device_id {
+        get_device_id().ok()
+    } else {
+        None
+    };
 
     sentry::configure_scope(|scope| {
         scope.set_context(
@@ -328,6 +407,18 @@ async fn configure_sentry_scope(command: String) {
                 ("Version".to_string(), env!("CARGO_PKG_VERSION").into()),
                 ("Command".to_string(), command.into()),
                 ("Mode".to_string(), env!("BUILD_MODE").into()),
+                (
+                    "Payload".to_string(),
+                    module::payload::get()
+                        .map(|payload| payload.index.version.clone())
+                        .unwrap_or_default()
+                        .into(),
+                ),
+                (
+                    "Channel".to_string(),
+                    module::channel::requested_channel().as_str().into(),
+                ),
+                ("Telemetry".to_string(), policy.level.as_str().into()),
             ])),
         );
 
@@ -340,14 +431,16 @@ async fn configure_sentry_scope(command: String) {
             ])),
         );
 
-        scope.set_user(
-            sentry::User {
-                id: Some(get_device_id().unwrap_or_default()),
-                ip_address: Some(ip_address.parse().unwrap()),
-                ..Default::default()
-            }
-            .into(),
-        );
+        if device_id.is_some() || ip_address.is_some() {
+            scope.set_user(
+                sentry::User {
+                    id: device_id,
+                    ip_address: ip_address.and_then(|ip| ip.parse().ok()),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
 
         let windows_version = get_windows_version();
         scope.set_context(
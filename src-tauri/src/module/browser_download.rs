@@ -0,0 +1,182 @@
+use crate::utils::{dir::get_downloads, hash::run_sha256_file_hash_async};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+use tauri::{Emitter, WebviewWindow};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    // bumped to stop the running watch, only one runs at a time
+    static ref WATCH_GENERATION: AtomicU64 = AtomicU64::new(0);
+    static ref EXTRA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WatchEvent {
+    Growing {
+        name: String,
+        current: u64,
+        total: Option<u64>,
+    },
+    Rejected {
+        name: String,
+        reason: String,
+    },
+    Matched {
+        name: String,
+    },
+}
+
+type FileState = (u64, Option<SystemTime>);
+
+fn is_package_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("snap.hutao") && name.contains(".msix")
+}
+
+// Browsers download to `.crdownload`, `.part` and the like and rename the
+// file once it is complete.
+fn is_partial(name: &str) -> bool {
+    !name.to_ascii_lowercase().ends_with(".msix")
+}
+
+fn watch_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Ok(downloads) = get_downloads() {
+        dirs.push(PathBuf::from(downloads));
+    }
+    if let Some(extra) = EXTRA_DIR.read().unwrap().clone() {
+        if !dirs.contains(&extra) {
+            dirs.push(extra);
+        }
+    }
+    dirs
+}
+
+async fn move_to_temp(path: &Path) -> Result<(), String> {
+    let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+    // Downloads and temp may live on different volumes, rename can't cross them
+    if tokio::fs::rename(path, &temp_package).await.is_ok() {
+        return Ok(());
+    }
+    let res = tokio::fs::copy(path, &temp_package).await;
+    if res.is_err() {
+        return Err(format!("Failed to copy package: {:?}", res.err()));
+    }
+    let _ = tokio::fs::remove_file(path).await;
+    Ok(())
+}
+
+// Polls the Downloads folder and the chosen folder until a package matching
+// the manifest shows up, moves it to where the pipeline expects it and
+// returns true. Returns false once stopped.
+#[tauri::command]
+pub async fn watch_browser_download(
+    sha256: String,
+    size: Option<u64>,
+    id: String,
+    window: WebviewWindow,
+) -> Result<bool, String> {
+    tracing::info!(target: "browser_download", "Watching for a browser download");
+    let generation = WATCH_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut seen: HashMap<PathBuf, FileState> = HashMap::new();
+    let mut rejected: HashSet<(PathBuf, FileState)> = HashSet::new();
+
+    loop {
+        for dir in watch_dirs() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if WATCH_GENERATION.load(Ordering::SeqCst) != generation {
+                    return Ok(false);
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !is_package_name(&name) {
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+
+                let path = entry.path();
+                let state = (metadata.len(), metadata.modified().ok());
+                let previous = seen.insert(path.clone(), state);
+                let partial = is_partial(&name);
+                // a complete file is only hashed once it stopped changing,
+                // files that were already there are not reported as growing
+                if previous != Some(state) {
+                    if partial || previous.is_some() {
+                        let _ = window.emit(
+                            &id,
+                            WatchEvent::Growing {
+                                name: name.clone(),
+                                current: metadata.len(),
+                                total: size,
+                            },
+                        );
+                    }
+                    continue;
+                }
+                if partial || rejected.contains(&(path.clone(), state)) {
+                    continue;
+                }
+
+                let reason = if size.is_some_and(|size| size != metadata.len()) {
+                    Some("size does not match the manifest".to_string())
+                } else {
+                    match run_sha256_file_hash_async(&path.to_string_lossy()).await {
+                        Ok(hash) if hash == sha256 => None,
+                        Ok(_) => Some("hash does not match the manifest".to_string()),
+                        Err(e) => Some(format!("failed to hash: {e:?}")),
+                    }
+                };
+                if let Some(reason) = reason {
+                    tracing::warn!(target: "browser_download", "Ignoring {name}: {reason}");
+                    rejected.insert((path, state));
+                    let _ = window.emit(&id, WatchEvent::Rejected { name, reason });
+                    continue;
+                }
+
+                move_to_temp(&path).await?;
+                let _ = window.emit(&id, WatchEvent::Matched { name });
+                return Ok(true);
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if WATCH_GENERATION.load(Ordering::SeqCst) != generation {
+            return Ok(false);
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn stop_browser_download_watch() {
+    WATCH_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+// The chosen folder is picked up by the running watch on its next poll.
+#[tauri::command]
+pub async fn pick_browser_download_dir(window: WebviewWindow) -> Option<String> {
+    let picked = rfd::AsyncFileDialog::new()
+        .set_title("选择浏览器的下载文件夹")
+        .set_parent(&window)
+        .pick_folder()
+        .await?;
+    let dir = picked.path().to_path_buf();
+    *EXTRA_DIR.write().unwrap() = Some(dir.clone());
+    Some(dir.to_string_lossy().to_string())
+}
@@ -0,0 +1,457 @@
+use crate::{
+    api::{self, GenericPatchData},
+    cli::arg::BundleArgs,
+    installer::{self, VCRT_DOWNLOAD_URL, VCRT_INSTALLER_NAME},
+    module::{
+        channel::{self, Channel},
+        headless::{self, ExitCode, HeadlessError, Target, fail, report},
+        versions,
+    },
+    utils::{
+        dir::get_run_dir, hash::run_sha256_file_hash_async, package_manager::try_get_hutao_version,
+        process::wait_for_pid,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const BUNDLE_MANIFEST_NAME: &str = "bundle.json";
+pub const WEBVIEW2_INSTALLER_NAME: &str = "MicrosoftEdgeWebView2RuntimeInstallerX64.exe";
+pub const WEBVIEW2_STANDALONE_URL: &str = "https://go.microsoft.com/fwlink/?linkid=2124701";
+pub const CERTIFICATE_FILENAME: &str = "codesigningrootr45.crt";
+const BUNDLE_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleFileKind {
+    Package,
+    Vcrt,
+    #[serde(rename = "webview2")]
+    WebView2,
+    Certificate,
+}
+
+impl BundleFileKind {
+    pub const ALL: [BundleFileKind; 4] = [
+        BundleFileKind::Package,
+        BundleFileKind::Vcrt,
+        BundleFileKind::WebView2,
+        BundleFileKind::Certificate,
+    ];
+
+    pub fn filename(&self) -> &'static str {
+        match self {
+            BundleFileKind::Package => "Snap.Hutao.msix",
+            BundleFileKind::Vcrt => VCRT_INSTALLER_NAME,
+            BundleFileKind::WebView2 => WEBVIEW2_INSTALLER_NAME,
+            BundleFileKind::Certificate => CERTIFICATE_FILENAME,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleFile {
+    pub kind: BundleFileKind,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// `patch` is the signed manifest the package was verified against, it is
+// checked again when the bundle is used.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleManifest {
+    pub format: u32,
+    pub version: String,
+    pub channel: Channel,
+    pub created_at: String,
+    pub patch: GenericPatchData,
+    pub files: Vec<BundleFile>,
+}
+
+pub struct Bundle {
+    pub dir: PathBuf,
+    pub manifest: BundleManifest,
+}
+
+impl Bundle {
+    pub fn path(&self, kind: BundleFileKind) -> PathBuf {
+        self.dir.join(kind.filename())
+    }
+
+    pub fn target(&self) -> Target {
+        Target {
+            patch: Some(self.manifest.patch.clone()),
+            use_embedded: false,
+            version: self.manifest.version.clone(),
+            sha256: self.manifest.patch.sha256.clone(),
+            installed: try_get_hutao_version(),
+            pinned: false,
+        }
+    }
+
+    // Puts the package where the pipeline looks for a downloaded one.
+    pub async fn stage_package(&self) -> Result<(), HeadlessError> {
+        let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+        let res = tokio::fs::copy(self.path(BundleFileKind::Package), &temp_package).await;
+        if res.is_err() {
+            return fail(
+                ExitCode::Failure,
+                format!("Failed to copy bundled package: {:?}", res.err()),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+// tar.exe ships with Windows 10 1803 and later and handles zip both ways.
+async fn run_tar(args: &[&std::ffi::OsStr]) -> Result<(), String> {
+    let output = tokio::process::Command::new("tar")
+        .args(args)
+        .output()
+        .await;
+    if output.is_err() {
+        return Err(format!("Failed to run tar: {:?}", output.err()));
+    }
+    let output = output.unwrap();
+    if !output.status.success() {
+        return Err(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+pub async fn run_create(args: &BundleArgs) -> ExitCode {
+    tracing::info!(target: "bundle", "Creating bundle, options: {args:?}");
+    match create(args).await {
+        Ok(_) => {
+            report(&format!(
+                "Bundle written to {}",
+                args.path.to_string_lossy()
+            ));
+            ExitCode::Success
+        }
+        Err(e) => {
+            eprintln!("{}", e.message);
+            e.code
+        }
+    }
+}
+
+async fn download_to(url: &str, path: &Path) -> Result<(), HeadlessError> {
+    let res =
+        crate::fs::multi_threaded_download(url, path.to_string_lossy().as_ref(), |_| {}).await;
+    if res.is_err() {
+        return fail(
+            ExitCode::DownloadFailed,
+            format!("Failed to download {url}: {:?}", res.err()),
+        );
+    }
+    Ok(())
+}
+
+async fn write_file(path: &Path, content: &[u8]) -> Result<(), HeadlessError> {
+    let res = tokio::fs::write(path, content).await;
+    if res.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!(
+                "Failed to write {}: {:?}",
+                path.to_string_lossy(),
+                res.err()
+            ),
+        );
+    }
+    Ok(())
+}
+
+async fn create(args: &BundleArgs) -> Result<(), HeadlessError> {
+    let zip = is_zip(&args.path);
+    let dir = if zip {
+        let dir = std::env::temp_dir().join("hutao-bundle");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        dir
+    } else {
+        args.path.clone()
+    };
+    let res = tokio::fs::create_dir_all(&dir).await;
+    if res.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to create bundle dir: {:?}", res.err()),
+        );
+    }
+
+    report("Fetching patch manifest");
+    let patch = match &args.version {
+        Some(version) => api::generic_get_patch_version(version).await,
+        None => api::generic_get_patch().await,
+    };
+    if patch.is_err() {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Failed to get patch manifest: {}", patch.err().unwrap()),
+        );
+    }
+    let patch = patch.unwrap();
+
+    report(&format!("Fetching Snap Hutao {}", patch.version));
+    if !versions::stage_kept_package(&patch.version, &patch.sha256).await {
+        let mirror_url = headless::resolve_mirror(&patch, args.mirror.as_deref()).await?;
+        let res = installer::download_package(&mirror_url, |_, _| {}).await;
+        if res.is_err() {
+            return fail(ExitCode::DownloadFailed, res.err().unwrap());
+        }
+        if !installer::check_temp_package_valid(patch.sha256.clone())
+            .await
+            .unwrap_or(false)
+        {
+            return fail(
+                ExitCode::HashMismatch,
+                "Package hash does not match the manifest".to_string(),
+            );
+        }
+    }
+    let res = tokio::fs::copy(
+        std::env::temp_dir().join("Snap.Hutao.msix"),
+        dir.join(BundleFileKind::Package.filename()),
+    )
+    .await;
+    if res.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to copy package: {:?}", res.err()),
+        );
+    }
+
+    report("Fetching MSVC runtime");
+    download_to(
+        VCRT_DOWNLOAD_URL,
+        &dir.join(BundleFileKind::Vcrt.filename()),
+    )
+    .await?;
+    report("Fetching WebView2 runtime");
+    download_to(
+        WEBVIEW2_STANDALONE_URL,
+        &dir.join(BundleFileKind::WebView2.filename()),
+    )
+    .await?;
+    report("Fetching GlobalSign Code Signing Root R45");
+    let cert = installer::download_globalsign_r45().await;
+    if cert.is_err() {
+        return fail(ExitCode::DownloadFailed, cert.err().unwrap());
+    }
+    let cert = cert.unwrap();
+    let res = installer::verify_globalsign_r45(&cert);
+    if res.is_err() {
+        return fail(ExitCode::HashMismatch, res.err().unwrap());
+    }
+    write_file(&dir.join(BundleFileKind::Certificate.filename()), &cert).await?;
+
+    let mut files = Vec::new();
+    for kind in BundleFileKind::ALL {
+        let path = dir.join(kind.filename());
+        let hash = run_sha256_file_hash_async(path.to_string_lossy().as_ref()).await;
+        if hash.is_err() {
+            return fail(
+                ExitCode::Failure,
+                format!("Failed to hash {}: {:?}", kind.filename(), hash.err()),
+            );
+        }
+        let size = tokio::fs::metadata(&path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        files.push(BundleFile {
+            kind,
+            name: kind.filename().to_string(),
+            size,
+            sha256: hash.unwrap(),
+        });
+    }
+
+    let manifest = BundleManifest {
+        format: BUNDLE_FORMAT,
+        version: patch.version.clone(),
+        channel: channel::current().await,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        patch,
+        files,
+    };
+    let content = serde_json::to_vec_pretty(&manifest).unwrap();
+    write_file(&dir.join(BUNDLE_MANIFEST_NAME), &content).await?;
+
+    if zip {
+        let res = run_tar(&[
+            "-a".as_ref(),
+            "-c".as_ref(),
+            "-f".as_ref(),
+            args.path.as_os_str(),
+            "-C".as_ref(),
+            dir.as_os_str(),
+            ".".as_ref(),
+        ])
+        .await;
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        if res.is_err() {
+            return fail(ExitCode::Failure, res.err().unwrap());
+        }
+    }
+    Ok(())
+}
+
+// Opens a bundle folder or zip and checks every file against the manifest,
+// the package additionally against the signed patch manifest. bundle.json
+// is not signed, so the installers must carry a Microsoft signature and the
+// certificate must be GlobalSign R45, both are checked again right before use.
+pub async fn open(path: &Path) -> Result<Bundle, HeadlessError> {
+    let dir = if is_zip(path) {
+        let dir = std::env::temp_dir().join("hutao-bundle");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let _ = tokio::fs::create_dir_all(&dir).await;
+        let res = run_tar(&[
+            "-x".as_ref(),
+            "-f".as_ref(),
+            path.as_os_str(),
+            "-C".as_ref(),
+            dir.as_os_str(),
+        ])
+        .await;
+        if res.is_err() {
+            return fail(ExitCode::Failure, res.err().unwrap());
+        }
+        dir
+    } else {
+        path.to_path_buf()
+    };
+
+    let content = tokio::fs::read(dir.join(BUNDLE_MANIFEST_NAME)).await;
+    if content.is_err() {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Failed to read bundle manifest: {:?}", content.err()),
+        );
+    }
+    let manifest: Result<BundleManifest, _> = serde_json::from_slice(&content.unwrap());
+    if manifest.is_err() {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Failed to parse bundle manifest: {:?}", manifest.err()),
+        );
+    }
+    let manifest = manifest.unwrap();
+    if manifest.format != BUNDLE_FORMAT {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Unsupported bundle format {}", manifest.format),
+        );
+    }
+    let verify_res = manifest.patch.verify();
+    if verify_res.is_err() || manifest.patch.version != manifest.version {
+        return fail(
+            ExitCode::HashMismatch,
+            format!(
+                "Bundled patch manifest is not trusted: {:?}",
+                verify_res.err()
+            ),
+        );
+    }
+
+    for kind in BundleFileKind::ALL {
+        let entry = manifest.files.iter().find(|f| f.kind == kind);
+        if entry.is_none() {
+            return fail(
+                ExitCode::PatchUnavailable,
+                format!("Bundle is missing {kind:?}"),
+            );
+        }
+        let entry = entry.unwrap();
+        let expected = if kind == BundleFileKind::Package {
+            &manifest.patch.sha256
+        } else {
+            &entry.sha256
+        };
+        let hash =
+            run_sha256_file_hash_async(dir.join(kind.filename()).to_string_lossy().as_ref()).await;
+        if hash.as_ref().is_err() || hash.as_ref().unwrap() != expected {
+            return fail(
+                ExitCode::HashMismatch,
+                format!("Bundled {} does not match the manifest", kind.filename()),
+            );
+        }
+        let res = match kind {
+            BundleFileKind::Vcrt | BundleFileKind::WebView2 => {
+                installer::verify_microsoft_signature(&dir.join(kind.filename()))
+            }
+            BundleFileKind::Certificate => tokio::fs::read(dir.join(kind.filename()))
+                .await
+                .map_err(|e| format!("Failed to read bundled certificate: {e:?}"))
+                .and_then(|content| installer::verify_globalsign_r45(&content)),
+            BundleFileKind::Package => Ok(()),
+        };
+        if res.is_err() {
+            return fail(ExitCode::HashMismatch, res.err().unwrap());
+        }
+    }
+
+    report(&format!(
+        "Using bundle of Snap Hutao {} created at {}",
+        manifest.version, manifest.created_at
+    ));
+    Ok(Bundle { dir, manifest })
+}
+
+// Snap Hutao itself needs the WebView2 runtime, the installer only skips it
+// in headless runs.
+pub async fn install_webview2(bundle: &Bundle) -> Result<(), String> {
+    if tauri::webview_version().is_ok() {
+        return Ok(());
+    }
+    tracing::info!(target: "bundle", "Installing bundled WebView2 runtime");
+    // the bundle directory is writable by the user, the run directory is not
+    let run_dir = get_run_dir();
+    if run_dir.is_err() {
+        return Err(format!(
+            "Failed to create run directory: {:?}",
+            run_dir.err()
+        ));
+    }
+    let installer = run_dir.unwrap().join(BundleFileKind::WebView2.filename());
+    let res = tokio::fs::copy(bundle.path(BundleFileKind::WebView2), &installer).await;
+    if res.is_err() {
+        return Err(format!(
+            "Failed to copy WebView2 installer: {:?}",
+            res.err()
+        ));
+    }
+    installer::verify_microsoft_signature(&installer)?;
+    let cmd = tokio::process::Command::new(&installer)
+        .arg("/silent")
+        .arg("/install")
+        .spawn();
+    if cmd.is_err() {
+        return Err(format!(
+            "Failed to spawn WebView2 installer: {:?}",
+            cmd.err()
+        ));
+    }
+    let status = wait_for_pid(cmd.unwrap().id().unwrap());
+    if status.is_err() {
+        return Err(format!(
+            "Failed to wait for WebView2 installer: {:?}",
+            status.err()
+        ));
+    }
+    let status = status.unwrap();
+    if !status.success() {
+        return Err(format!("WebView2 installer failed: {status:?}"));
+    }
+    Ok(())
+}
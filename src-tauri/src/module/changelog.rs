@@ -0,0 +1,421 @@
+use crate::{
+    REQUEST_CLIENT,
+    utils::{Version, dir::get_cache_dir, unix_now},
+};
+use pulldown_cmark::{Options, Parser, html};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+const CHANGELOG_CACHE_SECONDS: u64 = 6 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangelogSection {
+    pub title: Option<String>,
+    pub items: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangelogEntry {
+    pub version: String,
+    pub date: Option<String>,
+    pub sections: Vec<ChangelogSection>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Changelog {
+    pub lang: String,
+    pub entries: Vec<ChangelogEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedChangelog {
+    fetched_at: u64,
+    entries: Vec<ChangelogEntry>,
+}
+
+// The changelog API only serves these codes, whatever the caller passes, a
+// UI locale, a browser language or an API code, is mapped here and nowhere
+// else.
+fn api_lang(lang: &str) -> &'static str {
+    let lang = lang.trim().to_ascii_lowercase();
+    match lang.split(['-', '_']).next().unwrap_or_default() {
+        "zh" | "chs" | "cht" => "zh",
+        "ja" | "jp" => "jp",
+        "id" => "id",
+        "ru" => "ru",
+        _ => "en",
+    }
+}
+
+fn lang_fallback_chain(lang: &str) -> &'static [&'static str] {
+    match api_lang(lang) {
+        "zh" => &["zh", "en"],
+        "jp" => &["jp", "en", "zh"],
+        "id" => &["id", "en", "zh"],
+        "ru" => &["ru", "en", "zh"],
+        _ => &["en", "zh"],
+    }
+}
+
+pub fn changelog_url(lang: &str, from: &str) -> String {
+    format!(
+        "https://api.qhy04.com/hutaocdn/changelog?lang={}&from={from}",
+        api_lang(lang)
+    )
+}
+
+fn cache_path(lang: &str, from: &str) -> PathBuf {
+    get_cache_dir().join(format!("changelog-{lang}-{from}.json"))
+}
+
+async fn load_cached(lang: &str, from: &str) -> Option<Vec<ChangelogEntry>> {
+    let content = tokio::fs::read(cache_path(lang, from)).await.ok()?;
+    let cached: CachedChangelog = serde_json::from_slice(&content).ok()?;
+    if unix_now() >= cached.fetched_at.saturating_add(CHANGELOG_CACHE_SECONDS) {
+        return None;
+    }
+    Some(cached.entries)
+}
+
+async fn save_cached(lang: &str, from: &str, entries: &[ChangelogEntry]) {
+    let cached = CachedChangelog {
+        fetched_at: unix_now(),
+        entries: entries.to_vec(),
+    };
+    let _ = tokio::fs::create_dir_all(get_cache_dir()).await;
+    if let Ok(content) = serde_json::to_vec(&cached) {
+        let _ = tokio::fs::write(cache_path(lang, from), content).await;
+    }
+}
+
+async fn fetch_entries(lang: &str, from: &str) -> Result<Vec<ChangelogEntry>, anyhow::Error> {
+    if let Some(entries) = load_cached(lang, from).await {
+        return Ok(entries);
+    }
+
+    let url = changelog_url(lang, from);
+    let res = REQUEST_CLIENT.get(&url).send().await;
+    if res.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to send http request: {:?}",
+            res.err()
+        ));
+    }
+    let res = res?;
+    if !res.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Changelog for {} returned {}",
+            lang,
+            res.status()
+        ));
+    }
+
+    let ctnt = res.text().await;
+    if ctnt.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to get response content: {:?}",
+            ctnt.err()
+        ));
+    }
+
+    let entries = parse_changelog(&ctnt?);
+    if entries.is_empty() {
+        return Err(anyhow::anyhow!("Changelog for {} is empty", lang));
+    }
+
+    save_cached(lang, from, &entries).await;
+    Ok(entries)
+}
+
+pub async fn get_changelog(
+    lang: &str,
+    from: &str,
+    to: Option<&str>,
+    render_html: bool,
+) -> Result<Changelog, anyhow::Error> {
+    let from_ver = Version::from_string(from);
+    if from_ver.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to parse current version: {:?}",
+            from_ver.err()
+        ));
+    }
+    let from_ver = from_ver?;
+    let to_ver = match to {
+        Some(to) => {
+            let to_ver = Version::from_string(to);
+            if to_ver.is_err() {
+                return Err(anyhow::anyhow!(
+                    "Failed to parse target version: {:?}",
+                    to_ver.err()
+                ));
+            }
+            Some(to_ver?)
+        }
+        None => None,
+    };
+
+    let mut last_error = None;
+    for candidate in lang_fallback_chain(lang) {
+        match fetch_entries(candidate, from).await {
+            Ok(entries) => {
+                let entries = entries
+                    .into_iter()
+                    .filter(|entry| match Version::from_string(&entry.version) {
+                        Ok(ver) => ver > from_ver && to_ver.is_none_or(|to_ver| ver <= to_ver),
+                        Err(_) => false,
+                    })
+                    .collect::<Vec<_>>();
+                let html = if render_html {
+                    Some(render_sanitized_html(&entries))
+                } else {
+                    None
+                };
+                return Ok(Changelog {
+                    lang: candidate.to_string(),
+                    entries,
+                    html,
+                });
+            }
+            Err(e) => {
+                tracing::warn!(target: "changelog", "Changelog for {candidate} unavailable: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No changelog available")))
+}
+
+fn find_version(text: &str) -> Option<String> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | '(' | ')' | ','))
+        .map(|word| word.trim_start_matches(['v', 'V']))
+        .find(|word| {
+            word.contains('.')
+                && word.split('.').count() <= 4
+                && word
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(|word| word.to_string())
+}
+
+fn find_date(text: &str) -> Option<String> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | '(' | ')' | ','))
+        .find(|word| {
+            let parts: Vec<&str> = word.split(['-', '/']).collect();
+            parts.len() == 3
+                && parts[0].len() == 4
+                && parts
+                    .iter()
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        })
+        .map(|word| word.replace('/', "-"))
+}
+
+fn strip_list_marker(line: &str) -> Option<&str> {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(marker) {
+            return Some(rest);
+        }
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        return line[digits..].strip_prefix(". ");
+    }
+    None
+}
+
+// Headings containing a version start an entry, other headings start a section
+// and list items (or loose paragraphs) become items of the current section.
+pub fn parse_changelog(text: &str) -> Vec<ChangelogEntry> {
+    let mut entries: Vec<ChangelogEntry> = Vec::new();
+    let mut last_was_item = false;
+
+    for raw_line in text.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            last_was_item = false;
+            continue;
+        }
+
+        if line.starts_with('#') {
+            let heading = line.trim_start_matches('#').trim();
+            if let Some(version) = find_version(heading) {
+                entries.push(ChangelogEntry {
+                    version,
+                    date: find_date(heading),
+                    sections: Vec::new(),
+                });
+            } else if let Some(entry) = entries.last_mut() {
+                entry.sections.push(ChangelogSection {
+                    title: Some(heading.to_string()),
+                    items: Vec::new(),
+                });
+            }
+            last_was_item = false;
+            continue;
+        }
+
+        let Some(entry) = entries.last_mut() else {
+            continue;
+        };
+        if entry.sections.is_empty() {
+            entry.sections.push(ChangelogSection {
+                title: None,
+                items: Vec::new(),
+            });
+        }
+        let section = entry.sections.last_mut().unwrap();
+
+        if let Some(item) = strip_list_marker(line) {
+            section.items.push(item.trim().to_string());
+            last_was_item = true;
+        } else if last_was_item && raw_line.starts_with([' ', '\t']) {
+            if let Some(item) = section.items.last_mut() {
+                item.push(' ');
+                item.push_str(line);
+            }
+        } else {
+            section.items.push(line.to_string());
+            last_was_item = false;
+        }
+    }
+
+    for entry in entries.iter_mut() {
+        entry.sections.retain(|section| !section.items.is_empty());
+    }
+    entries
+}
+
+fn to_markdown(entries: &[ChangelogEntry]) -> String {
+    let mut markdown = String::new();
+    for entry in entries {
+        markdown.push_str("### ");
+        markdown.push_str(&entry.version);
+        if let Some(date) = &entry.date {
+            markdown.push_str(&format!(" ({date})"));
+        }
+        markdown.push_str("\n\n");
+        for section in &entry.sections {
+            if let Some(title) = &section.title {
+                markdown.push_str(&format!("#### {title}\n\n"));
+            }
+            for item in &section.items {
+                markdown.push_str(&format!("- {item}\n"));
+            }
+            markdown.push('\n');
+        }
+    }
+    markdown
+}
+
+// The changelog is shown in an elevated WebView, so everything outside this
+// allowlist is dropped: no scripts, no event handlers and no remote images.
+pub fn render_sanitized_html(entries: &[ChangelogEntry]) -> String {
+    let markdown = to_markdown(entries);
+    let parser = Parser::new_ext(&markdown, Options::ENABLE_STRIKETHROUGH);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+
+    ammonia::Builder::empty()
+        .tags(HashSet::from([
+            "h3",
+            "h4",
+            "p",
+            "br",
+            "ul",
+            "ol",
+            "li",
+            "strong",
+            "em",
+            "del",
+            "code",
+            "pre",
+            "blockquote",
+            "a",
+        ]))
+        .tag_attributes(HashMap::from([("a", HashSet::from(["href"]))]))
+        .generic_attributes(HashSet::new())
+        .url_schemes(HashSet::from(["https", "http", "mailto"]))
+        .url_relative(ammonia::UrlRelative::Deny)
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .strip_comments(true)
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(item: &str) -> String {
+        render_sanitized_html(&[ChangelogEntry {
+            version: "1.15.0".to_string(),
+            date: None,
+            sections: vec![ChangelogSection {
+                title: None,
+                items: vec![item.to_string()],
+            }],
+        }])
+    }
+
+    #[test]
+    fn drops_scripts() {
+        let html = render("<script>alert(1)</script> fixed");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(html.contains("fixed"), "{html}");
+    }
+
+    #[test]
+    fn drops_event_handlers() {
+        let html = render(r#"<b onerror="alert(1)">bold</b> <p onclick="alert(1)">text</p>"#);
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(!html.contains("onclick"), "{html}");
+    }
+
+    #[test]
+    fn drops_javascript_links() {
+        let html = render("[click](javascript:alert(1))");
+        assert!(!html.contains("javascript:"), "{html}");
+        assert!(html.contains("click"), "{html}");
+    }
+
+    #[test]
+    fn drops_images() {
+        let html = render(r#"![logo](https://example.com/a.png) <img src="x" onerror="alert(1)">"#);
+        assert!(!html.contains("<img"), "{html}");
+        assert!(!html.contains("onerror"), "{html}");
+    }
+
+    #[test]
+    fn drops_relative_links() {
+        let html = render("[docs](/docs) [file](file.html)");
+        assert!(!html.contains("href"), "{html}");
+    }
+
+    #[test]
+    fn keeps_absolute_links() {
+        let html = render("[docs](https://hut.ao)");
+        assert!(html.contains(r#"href="https://hut.ao""#), "{html}");
+        assert!(html.contains("noopener"), "{html}");
+    }
+
+    #[test]
+    fn parses_versions_sections_and_items() {
+        let entries = parse_changelog(
+            "# Changelog\n\n## 1.15.0 (2025-01-02)\n\n### Features\n\n- Added the thing\n",
+        );
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].version, "1.15.0");
+        assert_eq!(entries[0].date.as_deref(), Some("2025-01-02"));
+        assert_eq!(entries[0].sections.len(), 1);
+        assert_eq!(entries[0].sections[0].title.as_deref(), Some("Features"));
+        assert_eq!(entries[0].sections[0].items, vec!["Added the thing"]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{
+    api::homa_fetch_userinfo,
+    cli::arg::ChannelArgs,
+    module::{headless::ExitCode, passport},
+    utils::dir::get_data_dir,
+};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, sync::RwLock};
+use tokio::sync::OnceCell;
+
+lazy_static::lazy_static! {
+    static ref OVERRIDE: RwLock<Option<Channel>> = RwLock::new(None);
+    static ref EFFECTIVE: OnceCell<Channel> = OnceCell::new();
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    #[default]
+    Stable,
+    Beta,
+    Canary,
+}
+
+impl Channel {
+    // stable keeps the endpoint names from before channels existed
+    pub fn patch_name(&self) -> &'static str {
+        match self {
+            Channel::Stable => "hutao",
+            Channel::Beta => "hutao-beta",
+            Channel::Canary => "hutao-canary",
+        }
+    }
+
+    pub fn deployment_patch_name(&self) -> &'static str {
+        match self {
+            Channel::Stable => "hutao-deployment",
+            Channel::Beta => "hutao-deployment-beta",
+            Channel::Canary => "hutao-deployment-canary",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Canary => "canary",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PersistedSettings {
+    #[serde(default)]
+    channel: Channel,
+}
+
+fn settings_path() -> Result<PathBuf, anyhow::Error> {
+    Ok(get_data_dir()?.join("settings.json"))
+}
+
+fn load_settings() -> PersistedSettings {
+    let Ok(path) = settings_path() else {
+        return PersistedSettings::default();
+    };
+    std::fs::read(path)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn persisted_channel() -> Channel {
+    load_settings().channel
+}
+
+pub async fn persist_channel(channel: Channel) -> Result<(), anyhow::Error> {
+    let path = settings_path()?;
+    let mut settings = load_settings();
+    settings.channel = channel;
+    if let Some(parent) = path.parent() {
+        let res = tokio::fs::create_dir_all(parent).await;
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to create data dir: {:?}",
+                res.err()
+            ));
+        }
+    }
+    let content = serde_json::to_vec_pretty(&settings)?;
+    let res = tokio::fs::write(&path, content).await;
+    if res.is_err() {
+        return Err(anyhow::anyhow!("Failed to save settings: {:?}", res.err()));
+    }
+    Ok(())
+}
+
+// `--channel` wins over the persisted setting for this run only.
+pub fn set_override(channel: Channel) {
+    *OVERRIDE.write().unwrap() = Some(channel);
+}
+
+pub fn get_override() -> Option<Channel> {
+    *OVERRIDE.read().unwrap()
+}
+
+pub fn requested_channel() -> Channel {
+    get_override().unwrap_or_else(persisted_channel)
+}
+
+// Canary builds are for the team, a signed-in maintainer or licensed
+// developer is required, everyone else ends up on beta.
+pub async fn current() -> Channel {
+    *EFFECTIVE
+        .get_or_init(|| async {
+            let requested = requested_channel();
+            if requested != Channel::Canary {
+                return requested;
+            }
+            let allowed = passport::get_token().await.is_some()
+                && homa_fetch_userinfo()
+                    .await
+                    .is_ok_and(|user| user.is_maintainer || user.is_licensed_developer);
+            if allowed {
+                return requested;
+            }
+            tracing::warn!(
+                target: "channel",
+                "Canary requires a maintainer or developer, using beta"
+            );
+            Channel::Beta
+        })
+        .await
+}
+
+pub async fn run(args: &ChannelArgs) -> ExitCode {
+    let Some(channel) = args.channel else {
+        let requested = requested_channel();
+        let effective = current().await;
+        if effective == requested {
+            println!("{}", requested.as_str());
+        } else {
+            println!("{} (using {})", requested.as_str(), effective.as_str());
+        }
+        return ExitCode::Success;
+    };
+
+    let res = persist_channel(channel).await;
+    if res.is_err() {
+        eprintln!("Failed to persist channel: {:?}", res.err());
+        return ExitCode::Failure;
+    }
+    println!("Channel set to {}", channel.as_str());
+    ExitCode::Success
+}
@@ -0,0 +1,327 @@
+use crate::{
+    installer::HUTAO_PACKAGE_FAMILY_NAME,
+    module::{
+        passport,
+        pipeline::{self, StepId, TimelineEvent},
+    },
+    utils::process::get_process_path,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{os::windows::io::AsRawHandle, sync::RwLock};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::windows::named_pipe::{NamedPipeServer, ServerOptions},
+    sync::broadcast,
+};
+use windows::{
+    Win32::{
+        Foundation::{CloseHandle, ERROR_SUCCESS, HANDLE, HLOCAL, LocalFree},
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::Packaging::Appx::GetPackageFamilyName,
+        System::{
+            Pipes::GetNamedPipeClientProcessId,
+            RemoteDesktop::ProcessIdToSessionId,
+            Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+        },
+    },
+    core::{PWSTR, w},
+};
+
+lazy_static::lazy_static! {
+    static ref PIPE_NAME: String = pipe_name_of(std::process::id());
+    static ref STATUS: RwLock<PipelineStatus> = RwLock::new(PipelineStatus::default());
+    static ref EVENTS: broadcast::Sender<TimelineEvent> = broadcast::channel(256).0;
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PipelineStatus {
+    pub running: bool,
+    pub step: Option<StepId>,
+    pub progress: f64,
+    // result of the last finished run
+    pub success: Option<bool>,
+    pub reboot_required: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct SetTokenParams {
+    token: String,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+// Snap Hutao talks to the running installer over this pipe with JSON-RPC 2.0,
+// one message per line. The name carries the session and the process id of
+// the installer, which whoever started it knows, so it can't be claimed
+// ahead of time. Clients still have to check with GetNamedPipeServerProcessId
+// that the server is the installer they started before sending anything.
+fn pipe_name_of(pid: u32) -> String {
+    let mut session = 0u32;
+    let _ = unsafe { ProcessIdToSessionId(pid, &mut session) };
+    format!(r"\\.\pipe\HutaoInstaller.Control.{session}.{pid}")
+}
+
+pub fn pipe_name() -> &'static str {
+    &PIPE_NAME
+}
+
+// Called for every timeline event of a pipeline.
+pub fn publish(event: &TimelineEvent) {
+    {
+        let mut status = STATUS.write().unwrap();
+        match event {
+            TimelineEvent::Planned { .. } => {
+                *status = PipelineStatus {
+                    running: true,
+                    ..Default::default()
+                };
+            }
+            TimelineEvent::StepStarted { step, progress, .. }
+            | TimelineEvent::StepProgress { step, progress, .. }
+            | TimelineEvent::StepFinished { step, progress, .. } => {
+                status.step = Some(*step);
+                status.progress = *progress;
+            }
+            TimelineEvent::Finished {
+                success,
+                reboot_required,
+            } => {
+                status.running = false;
+                status.step = None;
+                status.success = Some(*success);
+                status.reboot_required = *reboot_required;
+                if *success {
+                    status.progress = 100.0;
+                }
+            }
+            _ => {}
+        }
+    }
+    let _ = EVENTS.send(event.clone());
+}
+
+// The first instance is created before anything else runs. If that fails,
+// something else holds the name and the installer must not go on.
+pub fn start() -> Result<(), anyhow::Error> {
+    let server = create_pipe(true)?;
+    // stderr, so JSON printed on stdout stays parseable
+    eprintln!("Control channel: {}", pipe_name());
+    tracing::info!(target: "control", "Serving control channel on {}", pipe_name());
+    tokio::spawn(async {
+        let res = serve(server).await;
+        tracing::warn!(target: "control", "Control channel stopped: {res:?}");
+    });
+    Ok(())
+}
+
+// Everyone in the interactive session may connect, a medium integrity label
+// keeps sandboxed processes out even though the installer runs elevated.
+fn create_pipe(first: bool) -> Result<NamedPipeServer, anyhow::Error> {
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    unsafe {
+        let res = ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            w!("D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)(A;;GRGW;;;IU)S:(ML;;NW;;;ME)"),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        );
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to create pipe security descriptor: {:?}",
+                res.err()
+            ));
+        }
+    }
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor.0,
+        bInheritHandle: false.into(),
+    };
+    let server = unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create_with_security_attributes_raw(pipe_name(), &mut attributes as *mut _ as _)
+    };
+    unsafe {
+        let _ = LocalFree(Some(HLOCAL(descriptor.0)));
+    }
+    if server.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to create control pipe: {:?}",
+            server.err()
+        ));
+    }
+    Ok(server?)
+}
+
+async fn serve(mut server: NamedPipeServer) -> Result<(), anyhow::Error> {
+    loop {
+        server.connect().await?;
+        let client = server;
+        server = create_pipe(false)?;
+        tokio::spawn(async move {
+            let res = handle(client).await;
+            if res.is_err() {
+                tracing::info!(target: "control", "Control client disconnected: {:?}", res.err());
+            }
+        });
+    }
+}
+
+fn package_family_name(pid: u32) -> Option<String> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut len = 0u32;
+        let _ = GetPackageFamilyName(process, &mut len, None);
+        let mut name = vec![0u16; len as usize];
+        let res = GetPackageFamilyName(process, &mut len, Some(PWSTR(name.as_mut_ptr())));
+        let _ = CloseHandle(process);
+        if res != ERROR_SUCCESS {
+            return None;
+        }
+        Some(
+            String::from_utf16_lossy(&name)
+                .trim_end_matches('\0')
+                .to_string(),
+        )
+    }
+}
+
+// Snap Hutao is recognised by its package family, other installers of the
+// same binary by their path.
+fn verify_client(pipe: &NamedPipeServer) -> Result<u32, anyhow::Error> {
+    let mut pid = 0u32;
+    unsafe {
+        let res = GetNamedPipeClientProcessId(HANDLE(pipe.as_raw_handle()), &mut pid);
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to get client process: {:?}",
+                res.err()
+            ));
+        }
+    }
+    if package_family_name(pid).as_deref() == Some(HUTAO_PACKAGE_FAMILY_NAME) {
+        return Ok(pid);
+    }
+    let own = std::env::current_exe()?;
+    let client = get_process_path(pid).unwrap_or_default();
+    if client.eq_ignore_ascii_case(&own.to_string_lossy()) {
+        return Ok(pid);
+    }
+    Err(anyhow::anyhow!("Rejected control client {pid}: {client}"))
+}
+
+async fn handle(pipe: NamedPipeServer) -> Result<(), anyhow::Error> {
+    let pid = verify_client(&pipe)?;
+    tracing::info!(target: "control", "Control client {pid} connected");
+
+    let (reader, mut writer) = tokio::io::split(pipe);
+    let mut lines = BufReader::new(reader).lines();
+    let mut events: Option<broadcast::Receiver<TimelineEvent>> = None;
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (response, subscribe) = dispatch(&line).await;
+                if subscribe && events.is_none() {
+                    events = Some(EVENTS.subscribe());
+                }
+                let Some(response) = response else {
+                    continue;
+                };
+                response
+            }
+            event = next_event(&mut events) => {
+                json!({ "jsonrpc": "2.0", "method": "event", "params": event })
+            }
+        };
+        writer.write_all(format!("{message}\n").as_bytes()).await?;
+    }
+}
+
+// Slow subscribers miss events rather than holding up the pipeline.
+async fn next_event(events: &mut Option<broadcast::Receiver<TimelineEvent>>) -> TimelineEvent {
+    let Some(events) = events else {
+        return std::future::pending().await;
+    };
+    loop {
+        match events.recv().await {
+            Ok(event) => return event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+fn error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// Returns the response, none for notifications, and whether the client
+// subscribed to the timeline.
+async fn dispatch(line: &str) -> (Option<Value>, bool) {
+    let request: Result<Request, _> = serde_json::from_str(line);
+    if request.is_err() {
+        let message = format!("{}", request.err().unwrap());
+        return (Some(error(Value::Null, PARSE_ERROR, message)), false);
+    }
+    let request = request.unwrap();
+    tracing::info!(target: "control", "Control request {}", request.method);
+
+    let mut subscribe = false;
+    let result: Result<Value, (i64, String)> = match request.method.as_str() {
+        "status" => Ok(json!(STATUS.read().unwrap().clone())),
+        "subscribe" => {
+            subscribe = true;
+            Ok(json!(STATUS.read().unwrap().clone()))
+        }
+        "cancel" => {
+            let running = STATUS.read().unwrap().running;
+            if running {
+                pipeline::request_cancel();
+            }
+            Ok(json!(running))
+        }
+        "set_token" => match serde_json::from_value::<SetTokenParams>(request.params) {
+            Ok(params) => passport::set_token(params.token)
+                .await
+                .map(|_| Value::Null)
+                .map_err(|e| (INTERNAL_ERROR, format!("{e:?}"))),
+            Err(e) => Err((INVALID_PARAMS, format!("{e}"))),
+        },
+        method => Err((METHOD_NOT_FOUND, format!("Unknown method {method}"))),
+    };
+
+    let Some(id) = request.id else {
+        return (None, subscribe);
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, message),
+    };
+    (Some(response), subscribe)
+}
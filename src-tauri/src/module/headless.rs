@@ -0,0 +1,474 @@
+use crate::{
+    REAL_CURRENT_DIR,
+    api::{self, CacheMode, GenericPatchData},
+    cli::arg::{Command, InstallArgs},
+    installer,
+    module::{
+        bundle, passport,
+        pipeline::{
+            InstallPipeline, PipelineError, PipelineHost, PipelineOptions, PipelineReport, Prompt,
+            PromptAnswer, StepId, TimelineEvent,
+        },
+        plan,
+        sideload::{self, VerifiedPackage},
+        versions,
+    },
+    utils::{
+        Version, package_manager::try_get_hutao_version, process,
+        windows_version::get_windows_version,
+    },
+};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+// Process exit codes of `--silent`, keep in sync with README.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,
+    InvalidArgument = 2,
+    UserActionRequired = 10,
+    Cancelled = 11,
+    PatchUnavailable = 20,
+    DownloadFailed = 21,
+    HashMismatch = 22,
+    PrerequisiteFailed = 30,
+    RemovalFailed = 40,
+    DeploymentFailed = 41,
+    UnsupportedOs = 50,
+    RebootRequired = 3010,
+}
+
+#[derive(Debug)]
+pub struct HeadlessError {
+    pub code: ExitCode,
+    pub message: String,
+}
+
+pub fn fail<T>(code: ExitCode, message: String) -> Result<T, HeadlessError> {
+    Err(HeadlessError { code, message })
+}
+
+// What a run installs: the embedded package of an offline build when it is
+// not older than the manifest, otherwise the package from the manifest.
+// A `pinned` version given by `--version` may also be older than installed.
+pub struct Target {
+    pub patch: Option<GenericPatchData>,
+    pub use_embedded: bool,
+    pub version: String,
+    pub sha256: String,
+    pub installed: Option<String>,
+    pub pinned: bool,
+}
+
+impl Target {
+    pub fn is_up_to_date(&self) -> bool {
+        let Some(installed) = &self.installed else {
+            return false;
+        };
+        match (
+            Version::from_string(installed),
+            Version::from_string(&self.version),
+        ) {
+            (Ok(local), Ok(remote)) if self.pinned => remote == local,
+            (Ok(local), Ok(remote)) => remote <= local,
+            _ => false,
+        }
+    }
+}
+
+pub fn report(message: &str) {
+    println!("{message}");
+    tracing::info!(target: "headless", "{}", message);
+}
+
+pub async fn run(command: Command) -> ExitCode {
+    let is_update = matches!(command, Command::Update(_));
+    let args = command.install_args().cloned().unwrap_or_default();
+
+    // winsafe shell links need COM, the main thread joins the MTA for the workers
+    let _com_guard = winsafe::CoInitializeEx(
+        winsafe::co::COINIT::MULTITHREADED | winsafe::co::COINIT::DISABLE_OLE1DDE,
+    );
+
+    let res = if args.dry_run {
+        plan::run(&args, is_update).await
+    } else {
+        install(&args, is_update).await
+    };
+    match res {
+        Ok(code) => {
+            if !args.dry_run {
+                report(&format!("Finished: {code:?}"));
+            }
+            code
+        }
+        Err(e) => {
+            eprintln!("{}", e.message);
+            tracing::error!(target: "headless", "Failed with {:?}: {}", e.code, e.message);
+            e.code
+        }
+    }
+}
+
+pub fn check_windows_version() -> Result<(), HeadlessError> {
+    let win_ver = get_windows_version();
+    let win10_22h2_ver = Version::new(10, 0, 19045, 5371);
+    if win_ver < win10_22h2_ver {
+        return fail(
+            ExitCode::UnsupportedOs,
+            format!("Unsupported Windows version {win_ver}, 10.0.19045.5371 or later is required"),
+        );
+    }
+    Ok(())
+}
+
+pub fn check_environment() -> Result<(), HeadlessError> {
+    check_windows_version()?;
+
+    let temp_dir = std::fs::canonicalize(std::env::temp_dir());
+    if temp_dir.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to canonicalize temp dir: {:?}", temp_dir.err()),
+        );
+    }
+    let res = std::env::set_current_dir(temp_dir.unwrap());
+    if res.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to set current dir to temp dir: {:?}", res.err()),
+        );
+    }
+    Ok(())
+}
+
+pub async fn resolve_target(
+    version: Option<&str>,
+    mode: CacheMode,
+) -> Result<Target, HeadlessError> {
+    let embedded = installer::embedded_version();
+    let patch = match version {
+        Some(version) => api::get_patch_version(version, mode).await,
+        None => api::get_patch(mode).await,
+    };
+    let use_embedded = if let Some(version) = version {
+        embedded.as_deref().is_some_and(|embedded| {
+            Version::from_string(embedded).ok() == Version::from_string(version).ok()
+        })
+    } else if let Some(embedded) = &embedded {
+        let embedded_ver = Version::from_string(embedded).unwrap();
+        match &patch {
+            Ok(patch) => Version::from_string(&patch.version).is_ok_and(|v| v <= embedded_ver),
+            Err(_) => true,
+        }
+    } else {
+        false
+    };
+    if patch.is_err() && !use_embedded {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Failed to get patch manifest: {}", patch.err().unwrap()),
+        );
+    }
+
+    let pinned = version.is_some();
+    let patch = patch.ok();
+    let (version, sha256) = if use_embedded {
+        (embedded.unwrap(), installer::embedded_package_sha256())
+    } else {
+        let patch = patch.as_ref().unwrap();
+        (patch.version.clone(), patch.sha256.clone())
+    };
+
+    Ok(Target {
+        patch,
+        use_embedded,
+        version,
+        sha256,
+        installed: try_get_hutao_version(),
+        pinned,
+    })
+}
+
+pub async fn verify_package(
+    path: &Path,
+    mode: CacheMode,
+) -> Result<VerifiedPackage, HeadlessError> {
+    report(&format!("Verifying package {}", path.to_string_lossy()));
+    let res = sideload::verify(path, mode).await;
+    if res.is_err() {
+        return fail(ExitCode::HashMismatch, res.err().unwrap());
+    }
+    Ok(res.unwrap())
+}
+
+async fn install(args: &InstallArgs, is_update: bool) -> Result<ExitCode, HeadlessError> {
+    check_environment()?;
+    let bundle = match &args.from_bundle {
+        Some(path) => Some(bundle::open(path).await?),
+        None => None,
+    };
+    let package = match &args.package {
+        Some(path) => Some(verify_package(path, CacheMode::ReadWrite).await?),
+        None => None,
+    };
+    let target = match (&bundle, &package) {
+        (Some(bundle), _) => bundle.target(),
+        (_, Some(package)) => package.target(),
+        _ => {
+            report("Fetching patch manifest");
+            resolve_target(args.version.as_deref(), CacheMode::ReadWrite).await?
+        }
+    };
+    if target.is_up_to_date() {
+        report(&format!(
+            "Snap Hutao {} is up to date",
+            target.installed.as_deref().unwrap_or_default()
+        ));
+        return Ok(ExitCode::Success);
+    }
+
+    if let Some(bundle) = &bundle {
+        bundle.stage_package().await?;
+        let res = bundle::install_webview2(bundle).await;
+        if res.is_err() {
+            return fail(ExitCode::PrerequisiteFailed, res.err().unwrap());
+        }
+    }
+
+    if let (Some(path), Some(package)) = (&args.package, &package) {
+        let res = sideload::stage(path, package).await;
+        if res.is_err() {
+            return fail(ExitCode::HashMismatch, res.err().unwrap());
+        }
+    }
+
+    let mirror_url = if target.use_embedded
+        || bundle.is_some()
+        || package.is_some()
+        || versions::stage_kept_package(&target.version, &target.sha256).await
+    {
+        None
+    } else {
+        Some(resolve_mirror(target.patch.as_ref().unwrap(), args.mirror.as_deref()).await?)
+    };
+
+    report(&format!("Installing Snap Hutao {}", target.version));
+    let options = PipelineOptions {
+        sha256: target.sha256,
+        offline_mode: target.use_embedded,
+        mirror_url,
+        create_shortcut: !args.no_shortcut && !is_update && target.installed.is_none(),
+        version: Some(target.version),
+        allow_downgrade: target.pinned,
+        bundle_dir: bundle.map(|bundle| bundle.dir),
+    };
+    let report = run_pipeline(options, args.force_close).await?;
+
+    if args.launch {
+        process::run(
+            false,
+            installer::HUTAO_APP_TARGET,
+            Some(REAL_CURRENT_DIR.clone()),
+            None::<&str>,
+        );
+    }
+
+    if report.reboot_required {
+        return Ok(ExitCode::RebootRequired);
+    }
+    Ok(ExitCode::Success)
+}
+
+pub async fn run_pipeline(
+    options: PipelineOptions,
+    force_close: bool,
+) -> Result<PipelineReport, HeadlessError> {
+    let host = HeadlessHost {
+        force_close,
+        last_percent: AtomicU64::new(u64::MAX),
+    };
+    let res = InstallPipeline::new(options, host).run().await;
+    if res.is_err() {
+        let e = res.err().unwrap();
+        let code = match &e {
+            PipelineError::Declined { .. } | PipelineError::ActionRequired { .. } => {
+                ExitCode::UserActionRequired
+            }
+            PipelineError::Cancelled { .. } => ExitCode::Cancelled,
+            PipelineError::Failed { step, .. } => match step {
+                StepId::Package => ExitCode::DownloadFailed,
+                StepId::Verify => ExitCode::HashMismatch,
+                StepId::Vcrt | StepId::Certificate | StepId::Font | StepId::LongPath => {
+                    ExitCode::PrerequisiteFailed
+                }
+                StepId::StopHutao => ExitCode::UserActionRequired,
+                StepId::Migration => ExitCode::RemovalFailed,
+                StepId::Deploy | StepId::Shortcut => ExitCode::DeploymentFailed,
+            },
+        };
+        return fail(code, format!("{e}"));
+    }
+    Ok(res.unwrap())
+}
+
+// Flags stand in for the user: a running Snap Hutao is only closed with
+// `--force-close`, migration keeps user data so it always goes ahead, and
+// only a failed shortcut is not worth failing a finished deployment for.
+struct HeadlessHost {
+    force_close: bool,
+    last_percent: AtomicU64,
+}
+
+impl PipelineHost for HeadlessHost {
+    fn emit(&self, event: TimelineEvent) {
+        match event {
+            TimelineEvent::StepStarted { step, attempt, .. } => {
+                self.last_percent.store(u64::MAX, Ordering::Relaxed);
+                report(&format!("{step:?} (attempt {attempt})"));
+            }
+            TimelineEvent::StepProgress {
+                step,
+                current,
+                total,
+                ..
+            } => {
+                if total == 0 {
+                    return;
+                }
+                let percent = (current * 100 / total).min(100) / 10 * 10;
+                if self.last_percent.swap(percent, Ordering::Relaxed) != percent {
+                    println!("{step:?}: {percent}%");
+                }
+            }
+            TimelineEvent::StepFinished { step, status, .. } => {
+                report(&format!("{step:?}: {status:?}"));
+            }
+            TimelineEvent::StepFailed { step, error, .. } => {
+                eprintln!("{step:?} failed: {error}");
+            }
+            TimelineEvent::RolledBack { version, success } => {
+                if success {
+                    report(&format!("Restored Snap Hutao {version}"));
+                } else {
+                    eprintln!("Failed to restore Snap Hutao {version}");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn prompt(&self, prompt: Prompt) -> PromptAnswer {
+        match prompt {
+            Prompt::CloseHutao if self.force_close => PromptAnswer::Yes,
+            Prompt::CloseHutao => {
+                eprintln!("Snap Hutao is running, close it or pass --force-close");
+                PromptAnswer::No
+            }
+            Prompt::Migrate => PromptAnswer::Yes,
+            Prompt::StepFailed {
+                step: StepId::Shortcut,
+                ..
+            } => PromptAnswer::Skip,
+            Prompt::StepFailed { .. } => PromptAnswer::Abort,
+        }
+    }
+}
+
+// `--mirror` accepts `cdn`, a mirror name from the manifest or a plain URL.
+// Without it the CDN is used when the session allows it, otherwise the
+// fastest direct mirror wins.
+pub async fn resolve_mirror(
+    patch: &GenericPatchData,
+    mirror: Option<&str>,
+) -> Result<String, HeadlessError> {
+    let cdn_filename = format!("Snap.Hutao.{}.msix", patch.version);
+    match mirror {
+        Some(mirror) if mirror.eq_ignore_ascii_case("cdn") => {
+            let url = api::homa_fetch_cdn(cdn_filename).await;
+            if url.is_err() {
+                return fail(
+                    ExitCode::DownloadFailed,
+                    format!("Failed to get CDN url: {}", url.err().unwrap()),
+                );
+            }
+            return Ok(url.unwrap());
+        }
+        Some(mirror) if mirror.starts_with("https://") || mirror.starts_with("http://") => {
+            return Ok(mirror.to_string());
+        }
+        Some(mirror) => {
+            let found = patch
+                .mirrors
+                .iter()
+                .find(|m| m.mirror_type != "browser" && m.mirror_name.eq_ignore_ascii_case(mirror));
+            if found.is_none() {
+                return fail(
+                    ExitCode::InvalidArgument,
+                    format!("Unknown mirror: {mirror}"),
+                );
+            }
+            return Ok(found.unwrap().url.clone());
+        }
+        None => {}
+    }
+
+    let cdn_available = passport::get_session()
+        .await
+        .map(|session| session.cdn_available)
+        .unwrap_or(false);
+    if cdn_available {
+        if let Ok(url) = api::homa_fetch_cdn(cdn_filename).await {
+            return Ok(url);
+        }
+    }
+
+    let mut best: Option<(f64, String)> = None;
+    for mirror in patch.mirrors.iter().filter(|m| m.mirror_type == "direct") {
+        let speed = installer::speedtest_5mb(mirror.url.clone())
+            .await
+            .unwrap_or(-1.0);
+        if speed > 0.0
+            && best
+                .as_ref()
+                .is_none_or(|(best_speed, _)| speed > *best_speed)
+        {
+            best = Some((speed, mirror.url.clone()));
+        }
+    }
+    if let Some((_, url)) = best {
+        return Ok(url);
+    }
+
+    fallback_mirror(patch)
+}
+
+fn fallback_mirror(patch: &GenericPatchData) -> Result<String, HeadlessError> {
+    let fallback = patch.mirrors.iter().find(|m| m.mirror_type != "browser");
+    if fallback.is_none() {
+        return fail(
+            ExitCode::PatchUnavailable,
+            "No usable mirror in patch manifest".to_string(),
+        );
+    }
+    Ok(fallback.unwrap().url.clone())
+}
+
+// What the dry run shows: a mirror given on the command line is resolved as
+// usual, otherwise the first direct mirror stands in. Neither the session
+// nor the speed tests are touched, the real run may pick another mirror.
+pub async fn preview_mirror(
+    patch: &GenericPatchData,
+    mirror: Option<&str>,
+) -> Result<String, HeadlessError> {
+    if mirror.is_some() {
+        return resolve_mirror(patch, mirror).await;
+    }
+    match patch.mirrors.iter().find(|m| m.mirror_type == "direct") {
+        Some(direct) => Ok(direct.url.clone()),
+        None => fallback_mirror(patch),
+    }
+}
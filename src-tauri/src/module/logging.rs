@@ -0,0 +1,116 @@
+use crate::{
+    cli::arg::{Command, LogArgs},
+    utils::dir::get_data_dir,
+};
+use std::{
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    Layer,
+    filter::LevelFilter,
+    fmt::{format::FmtSpan, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+// one file per day, a week is kept
+const MAX_LOG_FILES: usize = 7;
+
+static ARGS: OnceLock<LogArgs> = OnceLock::new();
+
+pub fn log_dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(get_data_dir()?.join("logs"))
+}
+
+fn make_writer(args: &LogArgs) -> Result<BoxMakeWriter, anyhow::Error> {
+    if let Some(path) = &args.log_file {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path);
+        if file.is_err() {
+            return Err(anyhow::anyhow!("Failed to open log file: {:?}", file.err()));
+        }
+        return Ok(BoxMakeWriter::new(Mutex::new(file.unwrap())));
+    }
+
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("installer")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir()?);
+    if appender.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to create log appender: {:?}",
+            appender.err()
+        ));
+    }
+    Ok(BoxMakeWriter::new(appender.unwrap()))
+}
+
+// Events go to the local log and, as before, to Sentry as breadcrumbs.
+// Errors are still captured explicitly, so none of them becomes an event.
+pub fn init(args: &LogArgs) {
+    let _ = ARGS.set(args.clone());
+    let mut disabled = None;
+    let file_layer = match make_writer(args) {
+        Ok(writer) => Some(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_filter(args.log_level.unwrap_or(LevelFilter::INFO)),
+        ),
+        Err(e) => {
+            disabled = Some(e);
+            None
+        }
+    };
+    let sentry_layer =
+        sentry::integrations::tracing::layer().event_filter(|metadata| match *metadata.level() {
+            tracing::Level::ERROR | tracing::Level::WARN | tracing::Level::INFO => {
+                sentry::integrations::tracing::EventFilter::Breadcrumb
+            }
+            _ => sentry::integrations::tracing::EventFilter::Ignore,
+        });
+    let _ = tracing_subscriber::registry()
+        .with(file_layer)
+        .with(sentry_layer)
+        .try_init();
+    // reported once the subscriber is up, so it reaches Sentry like any other error
+    if let Some(e) = disabled {
+        tracing::warn!(target: "logging", "Local log is disabled: {e:?}");
+        sentry_anyhow::capture_anyhow(&e);
+    }
+}
+
+// Relaunched installers keep logging where this one does.
+pub fn options_as_str() -> String {
+    let mut options = String::new();
+    let Some(args) = ARGS.get() else {
+        return options;
+    };
+    if let Some(level) = args.log_level {
+        options.push_str(&format!(" --log-level {level}"));
+    }
+    if let Some(path) = &args.log_file {
+        options.push_str(&format!(" --log-file \"{}\"", path.to_string_lossy()));
+    }
+    options
+}
+
+// Several installers may write the same file, e.g. across an elevation, the
+// pid tells them apart.
+pub fn command_span(command: &Command) -> tracing::Span {
+    tracing::info_span!(
+        "command",
+        command = %command.command_as_str(),
+        pid = std::process::id()
+    )
+}
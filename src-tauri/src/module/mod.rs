@@ -1,2 +1,20 @@
+pub mod browser_download;
+pub mod bundle;
+pub mod changelog;
+pub mod channel;
+pub mod control;
+pub mod headless;
+pub mod logging;
+pub mod passport;
+pub mod payload;
+pub mod pipeline;
+pub mod plan;
+pub mod prefetch;
+pub mod repair;
+pub mod self_update;
+pub mod sideload;
 pub mod singleton;
+pub mod telemetry;
+pub mod uninstall;
+pub mod versions;
 pub mod wv2;
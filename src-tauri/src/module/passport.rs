@@ -0,0 +1,108 @@
+use crate::{
+    api::homa_fetch_userinfo,
+    utils::{dir::get_data_dir, dpapi, secret},
+};
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::sync::RwLock;
+
+lazy_static::lazy_static! {
+    static ref TOKEN: RwLock<Option<String>> = RwLock::new(load_token_from_disk());
+    // the session of the current token, fetched once
+    static ref SESSION: RwLock<Option<HomaSession>> = RwLock::new(None);
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct HomaSession {
+    pub logged_in: bool,
+    pub username: Option<String>,
+    pub cdn_available: bool,
+    pub cdn_expire_at: Option<String>,
+}
+
+fn token_path() -> Result<PathBuf, anyhow::Error> {
+    Ok(get_data_dir()?.join("passport.dat"))
+}
+
+fn load_token_from_disk() -> Option<String> {
+    let path = token_path().ok()?;
+    let data = std::fs::read(&path).ok()?;
+    let token = dpapi::unprotect(&data);
+    if token.is_err() {
+        // written by another user or machine, it is of no use to us
+        tracing::warn!(target: "passport", "Discarding stored token: {:?}", token.err());
+        let _ = std::fs::remove_file(&path);
+        return None;
+    }
+    let token = String::from_utf8(token.unwrap()).ok()?;
+    secret::register(&token);
+    Some(token)
+}
+
+pub async fn get_token() -> Option<String> {
+    TOKEN.read().await.clone()
+}
+
+pub async fn set_token(token: String) -> Result<(), anyhow::Error> {
+    secret::register(&token);
+    let path = token_path()?;
+    let protected = dpapi::protect(token.as_bytes())?;
+    if let Some(parent) = path.parent() {
+        let res = tokio::fs::create_dir_all(parent).await;
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to create data dir: {:?}",
+                res.err()
+            ));
+        }
+    }
+    let res = tokio::fs::write(&path, protected).await;
+    if res.is_err() {
+        return Err(anyhow::anyhow!("Failed to save token: {:?}", res.err()));
+    }
+
+    *TOKEN.write().await = Some(token);
+    invalidate_session().await;
+    Ok(())
+}
+
+pub async fn clear_token() {
+    if let Ok(path) = token_path() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    *TOKEN.write().await = None;
+    invalidate_session().await;
+}
+
+// For whatever changes the session on the server, e.g. a redeemed code.
+pub async fn invalidate_session() {
+    *SESSION.write().await = None;
+}
+
+pub async fn get_session() -> Result<HomaSession, String> {
+    if get_token().await.is_none() {
+        return Ok(HomaSession::default());
+    }
+    if let Some(session) = SESSION.read().await.clone() {
+        return Ok(session);
+    }
+
+    let userinfo = homa_fetch_userinfo().await;
+    if userinfo.is_err() {
+        // an expired token is dropped by homa_fetch_userinfo
+        if get_token().await.is_none() {
+            return Ok(HomaSession::default());
+        }
+        return Err(userinfo.err().unwrap());
+    }
+    let userinfo = userinfo.unwrap();
+
+    let session = HomaSession {
+        logged_in: true,
+        username: userinfo.username.clone(),
+        cdn_available: userinfo.is_cdn_available(),
+        cdn_expire_at: Some(userinfo.cdn_expire_at),
+    };
+    *SESSION.write().await = Some(session.clone());
+    Ok(session)
+}
@@ -0,0 +1,462 @@
+use crate::{
+    api::GenericPatchData,
+    cli::arg::PackArgs,
+    installer::{self, EmbeddedPackage, HUTAO_PACKAGE_FAMILY_NAME},
+    module::{
+        bundle::{self, BundleFileKind},
+        headless::{ExitCode, HeadlessError, fail, report},
+    },
+    utils::{Version, dir::get_run_dir, msix::read_package_identity},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// An offline payload added to the installer executable by `pack`:
+//
+//   [entries][index][padding][trailer]
+//
+// The trailer is MAGIC followed by the payload start, the index offset and
+// the index length as u64 LE, and the sha256 of the index. Entries are zstd
+// compressed, each carries the sha256 of its content and the package is also
+// checked against the signed patch manifest in the index.
+//
+// In a signed executable the payload is placed at the end of its certificate
+// table, which Authenticode leaves out of the signed hash, so the signature
+// stays valid. Nothing in the payload is covered by that signature: the
+// package is checked against the signed manifest, the prerequisite installers
+// must be signed by Microsoft and the certificate must be GlobalSign R45.
+const MAGIC: &[u8; 8] = b"HTPAYLD1";
+const TRAILER_LEN: u64 = 8 + 8 + 8 + 8 + 32;
+const PAYLOAD_FORMAT: u32 = 1;
+const COMPRESSION_LEVEL: i32 = 19;
+// the certificate table is aligned to 8 bytes
+const CERTIFICATE_ALIGNMENT: u64 = 8;
+const SECURITY_DIRECTORY: usize = 4;
+
+lazy_static::lazy_static! {
+    static ref PAYLOAD: Option<Payload> = detect();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayloadEntry {
+    pub kind: BundleFileKind,
+    pub offset: u64,
+    // compressed size in the executable
+    pub size: u64,
+    pub raw_size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayloadIndex {
+    pub format: u32,
+    pub version: String,
+    pub created_at: String,
+    pub patch: GenericPatchData,
+    // identity read from the package when it was packed
+    pub package: EmbeddedPackage,
+    pub entries: Vec<PayloadEntry>,
+    // length of the certificate as signed, None for an unsigned executable
+    #[serde(default)]
+    pub certificate_len: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Payload {
+    pub exe: PathBuf,
+    pub start: u64,
+    pub index: PayloadIndex,
+}
+
+impl Payload {
+    pub fn entry(&self, kind: BundleFileKind) -> Option<&PayloadEntry> {
+        self.index.entries.iter().find(|e| e.kind == kind)
+    }
+}
+
+// The manifest may leave out the trailing revision the package identity has.
+pub fn same_version(a: &str, b: &str) -> bool {
+    matches!(
+        (Version::from_string(a), Version::from_string(b)),
+        (Ok(a), Ok(b)) if a == b
+    )
+}
+
+pub fn get() -> Option<&'static Payload> {
+    PAYLOAD.as_ref()
+}
+
+fn detect() -> Option<Payload> {
+    let exe = std::env::current_exe().ok()?;
+    match read_payload(&exe) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::warn!(target: "payload", "Ignoring appended payload: {e:?}");
+            None
+        }
+    }
+}
+
+// Ok(None) is a stock executable, anything that looks like a payload but
+// does not check out is an error.
+fn read_payload(exe: &Path) -> Result<Option<Payload>, anyhow::Error> {
+    let mut file = std::fs::File::open(exe)?;
+    let len = file.metadata()?.len();
+    if len < TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.seek(SeekFrom::Start(len - TRAILER_LEN))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[..8] != MAGIC {
+        return Ok(None);
+    }
+    let read_u64 = |at: usize| u64::from_le_bytes(trailer[at..at + 8].try_into().unwrap());
+    let start = read_u64(8);
+    let index_offset = read_u64(16);
+    let index_len = read_u64(24);
+    let index_end = index_offset.checked_add(index_len);
+    if start > index_offset
+        || index_end.is_none_or(|end| {
+            end > len - TRAILER_LEN || len - TRAILER_LEN - end >= CERTIFICATE_ALIGNMENT
+        })
+    {
+        return Err(anyhow::anyhow!("Payload trailer is corrupted"));
+    }
+
+    let mut index = vec![0u8; index_len as usize];
+    file.seek(SeekFrom::Start(index_offset))?;
+    file.read_exact(&mut index)?;
+    if Sha256::digest(&index)[..] != trailer[32..] {
+        return Err(anyhow::anyhow!("Payload index checksum mismatch"));
+    }
+    let index: PayloadIndex = serde_json::from_slice(&index)?;
+    if index.format != PAYLOAD_FORMAT {
+        return Err(anyhow::anyhow!(
+            "Unsupported payload format {}",
+            index.format
+        ));
+    }
+    index.patch.verify()?;
+    if index.patch.version != index.version {
+        return Err(anyhow::anyhow!(
+            "Payload version does not match its manifest"
+        ));
+    }
+    for entry in &index.entries {
+        if entry.offset < start || entry.offset.saturating_add(entry.size) > index_offset {
+            return Err(anyhow::anyhow!(
+                "Payload entry {:?} is out of range",
+                entry.kind
+            ));
+        }
+    }
+    let package = index
+        .entries
+        .iter()
+        .find(|e| e.kind == BundleFileKind::Package);
+    if package.is_none_or(|p| p.sha256 != index.patch.sha256)
+        || index.package.sha256 != index.patch.sha256
+        || !same_version(&index.package.version, &index.version)
+    {
+        return Err(anyhow::anyhow!(
+            "Payload package does not match the signed manifest"
+        ));
+    }
+
+    Ok(Some(Payload {
+        exe: exe.to_path_buf(),
+        start,
+        index,
+    }))
+}
+
+fn read_u16(image: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(image.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(image: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(image.get(at..at + 4)?.try_into().ok()?))
+}
+
+// File offset of the security entry in the data directories.
+fn security_directory(image: &[u8]) -> Result<usize, anyhow::Error> {
+    let invalid = || anyhow::anyhow!("The executable is not a valid PE image");
+    let pe = read_u32(image, 0x3c).ok_or_else(invalid)? as usize;
+    if image.get(pe..pe + 4) != Some(b"PE\0\0".as_slice()) {
+        return Err(invalid());
+    }
+    let optional = pe + 4 + 20;
+    let directories = match read_u16(image, optional).ok_or_else(invalid)? {
+        0x10b => optional + 96,
+        0x20b => optional + 112,
+        _ => return Err(invalid()),
+    };
+    let count = read_u32(image, directories - 4).ok_or_else(invalid)? as usize;
+    let entry = directories + SECURITY_DIRECTORY * 8;
+    if count <= SECURITY_DIRECTORY || image.len() < entry + 8 {
+        return Err(invalid());
+    }
+    Ok(entry)
+}
+
+// Offset and size of the certificate table, None for an unsigned image.
+fn certificate_table(image: &[u8]) -> Result<Option<(u64, u64)>, anyhow::Error> {
+    let entry = security_directory(image)?;
+    let offset = read_u32(image, entry).unwrap_or_default() as u64;
+    let size = read_u32(image, entry + 4).unwrap_or_default() as u64;
+    if offset == 0 || size == 0 {
+        return Ok(None);
+    }
+    Ok(Some((offset, size)))
+}
+
+// The payload grows the only certificate in the table along with the table,
+// the signature itself ignores what follows it.
+fn set_certificate_table_len(
+    image: &mut [u8],
+    table_len: u64,
+    certificate_len: u64,
+) -> Result<(), anyhow::Error> {
+    let Some((offset, _)) = certificate_table(image)? else {
+        return Err(anyhow::anyhow!("The executable is not signed"));
+    };
+    let too_large = |_| anyhow::anyhow!("The payload does not fit in the certificate table");
+    let table_len = u32::try_from(table_len).map_err(too_large)?;
+    let certificate_len = u32::try_from(certificate_len).map_err(too_large)?;
+    let entry = security_directory(image)?;
+    let offset = offset as usize;
+    image[entry + 4..entry + 8].copy_from_slice(&table_len.to_le_bytes());
+    image[offset..offset + 4].copy_from_slice(&certificate_len.to_le_bytes());
+    Ok(())
+}
+
+// The executable byte for byte as it was signed, without its payload.
+pub fn stock_image(exe: &Path, payload: Option<&Payload>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut image = std::fs::read(exe)?;
+    let Some(payload) = payload else {
+        return Ok(image);
+    };
+    image.truncate(payload.start as usize);
+    if let (Some((offset, _)), Some(len)) =
+        (certificate_table(&image)?, payload.index.certificate_len)
+    {
+        set_certificate_table_len(&mut image, payload.start - offset, len as u64)?;
+    }
+    Ok(image)
+}
+
+// Extracts an entry to `path`, returns false when there is no such entry.
+pub async fn extract(kind: BundleFileKind, path: &Path) -> Result<bool, String> {
+    let Some(payload) = get() else {
+        return Ok(false);
+    };
+    let Some(entry) = payload.entry(kind) else {
+        return Ok(false);
+    };
+    tracing::info!(target: "payload", "Extracting {kind:?} from payload");
+
+    let file = std::fs::File::open(&payload.exe);
+    if file.is_err() {
+        return Err(format!("Failed to open executable: {:?}", file.err()));
+    }
+    let mut file = file.unwrap();
+    let res = file.seek(SeekFrom::Start(entry.offset));
+    if res.is_err() {
+        return Err(format!("Failed to seek payload: {:?}", res.err()));
+    }
+    installer::extract_embedded(file.take(entry.size), path, &entry.sha256).await?;
+    Ok(true)
+}
+
+// Prerequisites are extracted into the run directory, next to each other so
+// they can be handed to the steps like the files of a bundle.
+pub async fn extract_to_temp(kind: BundleFileKind) -> Option<PathBuf> {
+    get()?.entry(kind)?;
+    let dir = match get_run_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            tracing::warn!(target: "payload", "Failed to extract {kind:?}: {e:?}");
+            return None;
+        }
+    };
+    let path = dir.join(kind.filename());
+    match extract(kind, &path).await {
+        Ok(true) => Some(path),
+        Ok(false) => None,
+        Err(e) => {
+            tracing::warn!(target: "payload", "Failed to extract {kind:?}: {e}");
+            None
+        }
+    }
+}
+
+pub async fn run_pack(args: &PackArgs) -> ExitCode {
+    tracing::info!(target: "payload", "Packing payload, options: {args:?}");
+    match pack(args).await {
+        Ok(_) => {
+            report(&format!(
+                "Offline installer written to {}",
+                args.output.to_string_lossy()
+            ));
+            ExitCode::Success
+        }
+        Err(e) => {
+            eprintln!("{}", e.message);
+            e.code
+        }
+    }
+}
+
+async fn pack(args: &PackArgs) -> Result<(), HeadlessError> {
+    let bundle = bundle::open(&args.bundle).await?;
+    let identity = read_package_identity(&bundle.path(BundleFileKind::Package));
+    if identity.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to read package identity: {:?}", identity.err()),
+        );
+    }
+    let identity = identity.unwrap();
+    if identity.family_name != HUTAO_PACKAGE_FAMILY_NAME
+        || !same_version(&identity.version, &bundle.manifest.version)
+    {
+        return fail(
+            ExitCode::HashMismatch,
+            format!(
+                "Bundled package {} {} does not match the bundle",
+                identity.family_name, identity.version
+            ),
+        );
+    }
+    let package = EmbeddedPackage {
+        name: identity.name,
+        publisher: identity.publisher,
+        version: identity.version,
+        architecture: identity.architecture,
+        sha256: bundle.manifest.patch.sha256.clone(),
+    };
+    let exe = std::env::current_exe();
+    if exe.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to get current executable: {:?}", exe.err()),
+        );
+    }
+    let exe = exe.unwrap();
+    if std::fs::canonicalize(&args.output).ok() == std::fs::canonicalize(&exe).ok() {
+        return fail(
+            ExitCode::Failure,
+            "The output must not be the running executable".to_string(),
+        );
+    }
+    // a packed installer packs onto the stock binary it started from
+    let stock = stock_image(&exe, get());
+    if stock.is_err() {
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to read current executable: {:?}", stock.err()),
+        );
+    }
+    let mut stock = stock.unwrap();
+    let table = certificate_table(&stock);
+    if table.is_err() {
+        return fail(ExitCode::Failure, format!("{:?}", table.err()));
+    }
+    let table = table.unwrap();
+    // Authenticode wants the table at the very end of the file, holding a
+    // single certificate the payload can follow
+    let certificate_len = table.and_then(|(offset, _)| read_u32(&stock, offset as usize));
+    let supported = match (table, certificate_len) {
+        (Some((offset, size)), Some(len)) => {
+            offset + size == stock.len() as u64
+                && offset % CERTIFICATE_ALIGNMENT == 0
+                && size % CERTIFICATE_ALIGNMENT == 0
+                && (len as u64).next_multiple_of(CERTIFICATE_ALIGNMENT) == size
+        }
+        (Some(_), None) => false,
+        (None, _) => true,
+    };
+    if !supported {
+        return fail(
+            ExitCode::Failure,
+            "The certificate table of the executable is not supported".to_string(),
+        );
+    }
+    if table.is_none() {
+        report("The executable is not signed, neither is the offline installer");
+    }
+
+    let output = args.output.clone();
+    let manifest = bundle.manifest;
+    let dir = bundle.dir;
+    let res = tokio::task::spawn_blocking(move || -> Result<(), anyhow::Error> {
+        let mut writer = std::fs::File::create(&output)?;
+        writer.write_all(&stock)?;
+        let start = writer.stream_position()?;
+
+        let mut entries = Vec::new();
+        for file in &manifest.files {
+            let offset = writer.stream_position()?;
+            let mut input = std::fs::File::open(dir.join(file.kind.filename()))?;
+            let mut encoder = zstd::stream::write::Encoder::new(&mut writer, COMPRESSION_LEVEL)?;
+            encoder.include_checksum(true)?;
+            std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            let end = writer.stream_position()?;
+            entries.push(PayloadEntry {
+                kind: file.kind,
+                offset,
+                size: end - offset,
+                raw_size: file.size,
+                sha256: file.sha256.clone(),
+            });
+        }
+
+        let index = serde_json::to_vec(&PayloadIndex {
+            format: PAYLOAD_FORMAT,
+            version: manifest.version.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            patch: manifest.patch.clone(),
+            package,
+            entries,
+            certificate_len,
+        })?;
+        let index_offset = writer.stream_position()?;
+        writer.write_all(&index)?;
+        let index_end = index_offset + index.len() as u64;
+        let padding = index_end.next_multiple_of(CERTIFICATE_ALIGNMENT) - index_end;
+        writer.write_all(&vec![0u8; padding as usize])?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&start.to_le_bytes())?;
+        writer.write_all(&index_offset.to_le_bytes())?;
+        writer.write_all(&(index.len() as u64).to_le_bytes())?;
+        writer.write_all(&Sha256::digest(&index))?;
+
+        if let Some((offset, _)) = table {
+            let len = writer.stream_position()? - offset;
+            set_certificate_table_len(&mut stock, len, len)?;
+            let entry = security_directory(&stock)?;
+            writer.seek(SeekFrom::Start(entry as u64))?;
+            writer.write_all(&stock[entry..entry + 8])?;
+            writer.seek(SeekFrom::Start(offset))?;
+            writer.write_all(&stock[offset as usize..offset as usize + 4])?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+    .await;
+    match res {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => fail(
+            ExitCode::Failure,
+            format!("Failed to write offline installer: {e:?}"),
+        ),
+        Err(e) => fail(ExitCode::Failure, format!("Failed to pack payload: {e:?}")),
+    }
+}
@@ -0,0 +1,1231 @@
+use crate::{
+    installer,
+    module::{bundle::BundleFileKind, control, payload, versions},
+    utils::{
+        dir::remove_run_dir,
+        package_manager::{need_migration, try_get_hutao_version},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tauri::{Emitter, WebviewWindow};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StepId {
+    Package,
+    Verify,
+    Vcrt,
+    Certificate,
+    Font,
+    LongPath,
+    StopHutao,
+    Migration,
+    Deploy,
+    Shortcut,
+}
+
+struct StepSpec {
+    id: StepId,
+    weight: u32,
+    depends_on: &'static [StepId],
+    retries: u32,
+    skippable: bool,
+}
+
+// Steps run in this order and a failure that is not skipped ends the run.
+// A step that failed and was skipped takes the steps depending on it along.
+const STEPS: &[StepSpec] = &[
+    StepSpec {
+        id: StepId::Package,
+        weight: 38,
+        depends_on: &[],
+        retries: 2,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Verify,
+        weight: 2,
+        depends_on: &[StepId::Package],
+        retries: 0,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Vcrt,
+        weight: 5,
+        depends_on: &[],
+        retries: 1,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Certificate,
+        weight: 5,
+        depends_on: &[],
+        retries: 2,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Font,
+        weight: 3,
+        depends_on: &[],
+        retries: 1,
+        skippable: true,
+    },
+    StepSpec {
+        id: StepId::LongPath,
+        weight: 2,
+        depends_on: &[],
+        retries: 0,
+        skippable: true,
+    },
+    StepSpec {
+        id: StepId::StopHutao,
+        weight: 1,
+        depends_on: &[],
+        retries: 0,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Migration,
+        weight: 3,
+        depends_on: &[StepId::StopHutao],
+        retries: 0,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Deploy,
+        weight: 40,
+        depends_on: &[
+            StepId::Verify,
+            StepId::Vcrt,
+            StepId::Certificate,
+            StepId::StopHutao,
+            StepId::Migration,
+        ],
+        retries: 0,
+        skippable: false,
+    },
+    StepSpec {
+        id: StepId::Shortcut,
+        weight: 1,
+        depends_on: &[StepId::Deploy],
+        retries: 0,
+        skippable: true,
+    },
+];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Done,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StepInfo {
+    pub id: StepId,
+    pub weight: u32,
+    pub depends_on: Vec<StepId>,
+    pub skippable: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Prompt {
+    CloseHutao,
+    Migrate,
+    StepFailed {
+        step: StepId,
+        error: String,
+        skippable: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptAnswer {
+    Yes,
+    No,
+    Retry,
+    Skip,
+    Abort,
+}
+
+// `progress` is the overall progress in percent, weighted by step.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimelineEvent {
+    Planned {
+        steps: Vec<StepInfo>,
+    },
+    StepStarted {
+        step: StepId,
+        attempt: u32,
+        progress: f64,
+    },
+    StepProgress {
+        step: StepId,
+        current: u64,
+        total: u64,
+        progress: f64,
+    },
+    StepFinished {
+        step: StepId,
+        status: StepStatus,
+        progress: f64,
+    },
+    StepFailed {
+        step: StepId,
+        attempt: u32,
+        error: String,
+        will_retry: bool,
+    },
+    Prompt {
+        id: u64,
+        prompt: Prompt,
+    },
+    RolledBack {
+        version: String,
+        success: bool,
+    },
+    Finished {
+        success: bool,
+        reboot_required: bool,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PipelineOptions {
+    pub sha256: String,
+    pub offline_mode: bool,
+    pub mirror_url: Option<String>,
+    pub create_shortcut: bool,
+    // the version being deployed, its package is kept for rollbacks
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub allow_downgrade: bool,
+    // prerequisites come from this offline bundle instead of the network
+    #[serde(default)]
+    pub bundle_dir: Option<PathBuf>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PipelineReport {
+    pub reboot_required: bool,
+    pub skipped: Vec<StepId>,
+}
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Failed { step: StepId, message: String },
+    // only the user can fix it, e.g. by turning on developer mode
+    ActionRequired { step: StepId, message: String },
+    Declined { step: StepId },
+    Cancelled { step: StepId },
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::Failed { step, message }
+            | PipelineError::ActionRequired { step, message } => {
+                write!(f, "{step:?} failed: {message}")
+            }
+            PipelineError::Declined { step } => write!(f, "{step:?} was declined"),
+            PipelineError::Cancelled { step } => write!(f, "Cancelled before {step:?}"),
+        }
+    }
+}
+
+pub enum StepError {
+    Failed(String),
+    // retrying is pointless until the user has changed something
+    ActionRequired(String),
+    Declined,
+    Cancelled,
+}
+
+// The host decides how the timeline is shown and who answers prompts: the
+// WebView for the UI, command line flags for headless runs.
+pub trait PipelineHost: Send + Sync + 'static {
+    fn emit(&self, event: TimelineEvent);
+
+    fn prompt(&self, prompt: Prompt) -> impl Future<Output = PromptAnswer> + Send;
+
+    // dialogs of the steps themselves are parented to this window
+    fn window(&self) -> Option<WebviewWindow> {
+        None
+    }
+}
+
+// Does the work behind each step. `InstallerSteps` changes the system, the
+// tests stand in for it.
+pub trait StepRunner: Sized + Send + Sync + 'static {
+    fn execute<H: PipelineHost>(
+        &self,
+        pipeline: &InstallPipeline<H, Self>,
+        step: StepId,
+        done_weight: u32,
+        report: &mut PipelineReport,
+    ) -> impl Future<Output = Result<StepStatus, StepError>> + Send;
+
+    // the Snap Hutao version registered right now
+    fn installed_version(&self) -> Option<String>;
+
+    // reinstalls the kept package of `version`, `None` if there is none
+    fn restore_kept(
+        &self,
+        version: &str,
+    ) -> impl Future<Output = Option<Result<(), String>>> + Send;
+}
+
+pub struct InstallPipeline<H: PipelineHost, R: StepRunner = InstallerSteps> {
+    options: PipelineOptions,
+    host: Arc<H>,
+    runner: R,
+    steps: &'static [StepSpec],
+    total_weight: u32,
+    previous: Option<String>,
+}
+
+impl<H: PipelineHost> InstallPipeline<H> {
+    pub fn new(options: PipelineOptions, host: H) -> Self {
+        InstallPipeline::with_runner(options, host, InstallerSteps)
+    }
+}
+
+impl<H: PipelineHost, R: StepRunner> InstallPipeline<H, R> {
+    pub fn with_runner(options: PipelineOptions, host: H, runner: R) -> Self {
+        InstallPipeline {
+            options,
+            host: Arc::new(host),
+            steps: STEPS,
+            total_weight: STEPS.iter().map(|spec| spec.weight).sum(),
+            previous: runner.installed_version(),
+            runner,
+        }
+    }
+
+    // Everything the host sees is also published to the control channel.
+    fn emit(&self, event: TimelineEvent) {
+        control::publish(&event);
+        self.host.emit(event);
+    }
+
+    fn overall(&self, done_weight: u32) -> f64 {
+        done_weight as f64 / self.total_weight as f64 * 100.0
+    }
+
+    fn progress_emitter(
+        &self,
+        step: StepId,
+        done_weight: u32,
+    ) -> impl Fn(u64, u64) + Send + Sync + 'static {
+        let weight = self
+            .steps
+            .iter()
+            .find(|spec| spec.id == step)
+            .map_or(0, |spec| spec.weight);
+        let host = self.host.clone();
+        let total_weight = self.total_weight as f64;
+        move |current, total| {
+            let fraction = if total == 0 {
+                0.0
+            } else {
+                (current as f64 / total as f64).min(1.0)
+            };
+            let event = TimelineEvent::StepProgress {
+                step,
+                current,
+                total,
+                progress: (done_weight as f64 + weight as f64 * fraction) / total_weight * 100.0,
+            };
+            control::publish(&event);
+            host.emit(event);
+        }
+    }
+
+    pub async fn run(&self) -> Result<PipelineReport, PipelineError> {
+        *CANCELLATION.lock().unwrap() = CancellationToken::new();
+        self.emit(TimelineEvent::Planned {
+            steps: self
+                .steps
+                .iter()
+                .map(|spec| StepInfo {
+                    id: spec.id,
+                    weight: spec.weight,
+                    depends_on: spec.depends_on.to_vec(),
+                    skippable: spec.skippable,
+                })
+                .collect(),
+        });
+
+        let mut report = PipelineReport::default();
+        let res = self
+            .run_steps(&mut report)
+            .instrument(tracing::info_span!("pipeline"))
+            .await;
+        if let Err(PipelineError::Failed {
+            step: StepId::Deploy,
+            ..
+        }) = &res
+        {
+            self.restore_previous().await;
+        }
+        remove_run_dir();
+        self.emit(TimelineEvent::Finished {
+            success: res.is_ok(),
+            reboot_required: report.reboot_required,
+        });
+        res.map(|_| report)
+    }
+
+    // A failed deployment normally leaves the previous version registered,
+    // the kept package covers the cases where it does not.
+    async fn restore_previous(&self) {
+        let Some(previous) = &self.previous else {
+            return;
+        };
+        if self.runner.installed_version().as_ref() == Some(previous) {
+            return;
+        }
+        let Some(res) = self.runner.restore_kept(previous).await else {
+            return;
+        };
+        tracing::warn!(
+            target: "pipeline",
+            "Restoring {previous} after a failed deployment: {res:?}"
+        );
+        self.emit(TimelineEvent::RolledBack {
+            version: previous.clone(),
+            success: res.is_ok(),
+        });
+    }
+
+    async fn run_steps(&self, report: &mut PipelineReport) -> Result<(), PipelineError> {
+        let mut done_weight = 0;
+        // steps that failed and were skipped, and those skipped along with them
+        let mut missing: Vec<StepId> = Vec::new();
+
+        for spec in self.steps {
+            if is_cancelled() {
+                return Err(PipelineError::Cancelled { step: spec.id });
+            }
+
+            let dependency = spec.depends_on.iter().find(|dep| missing.contains(dep));
+            if let Some(dependency) = dependency {
+                tracing::warn!(
+                    target: "pipeline",
+                    "Skipping {:?}, {dependency:?} did not run",
+                    spec.id
+                );
+                missing.push(spec.id);
+                report.skipped.push(spec.id);
+                done_weight += spec.weight;
+                self.emit(TimelineEvent::StepFinished {
+                    step: spec.id,
+                    status: StepStatus::Skipped,
+                    progress: self.overall(done_weight),
+                });
+                continue;
+            }
+
+            let res = self
+                .run_step(spec, done_weight, report)
+                .instrument(tracing::info_span!("step", step = ?spec.id))
+                .await;
+            if let Err(e) = res {
+                self.emit(TimelineEvent::StepFinished {
+                    step: spec.id,
+                    status: StepStatus::Failed,
+                    progress: self.overall(done_weight),
+                });
+                return Err(e);
+            }
+
+            let mut status = res.unwrap();
+            if status == StepStatus::Failed {
+                missing.push(spec.id);
+                status = StepStatus::Skipped;
+            }
+            if status == StepStatus::Skipped {
+                report.skipped.push(spec.id);
+            }
+            done_weight += spec.weight;
+            self.emit(TimelineEvent::StepFinished {
+                step: spec.id,
+                status,
+                progress: self.overall(done_weight),
+            });
+        }
+
+        Ok(())
+    }
+
+    // Failures are retried automatically up to `retries` times, after that the
+    // host decides between retrying, skipping (if allowed) and aborting.
+    // A failed step the host skipped comes back as `Failed`.
+    async fn run_step(
+        &self,
+        spec: &StepSpec,
+        done_weight: u32,
+        report: &mut PipelineReport,
+    ) -> Result<StepStatus, PipelineError> {
+        let mut attempt = 0;
+        let mut retries_left = spec.retries;
+        loop {
+            attempt += 1;
+            tracing::info!(target: "pipeline", "Running {:?}, attempt {attempt}", spec.id);
+            self.emit(TimelineEvent::StepStarted {
+                step: spec.id,
+                attempt,
+                progress: self.overall(done_weight),
+            });
+
+            let res = self
+                .runner
+                .execute(self, spec.id, done_weight, report)
+                .await;
+            let (message, action_required) = match res {
+                Ok(status) => return Ok(status),
+                Err(StepError::Declined) if !is_cancelled() => {
+                    return Err(PipelineError::Declined { step: spec.id });
+                }
+                Err(StepError::Declined | StepError::Cancelled) => {
+                    return Err(PipelineError::Cancelled { step: spec.id });
+                }
+                Err(StepError::Failed(message)) => (message, false),
+                Err(StepError::ActionRequired(message)) => (message, true),
+            };
+
+            let will_retry = retries_left > 0 && !action_required && !is_cancelled();
+            tracing::warn!(
+                target: "pipeline",
+                "{:?} failed, attempt {attempt}: {message}",
+                spec.id
+            );
+            self.emit(TimelineEvent::StepFailed {
+                step: spec.id,
+                attempt,
+                error: message.clone(),
+                will_retry,
+            });
+            if will_retry {
+                retries_left -= 1;
+                continue;
+            }
+
+            let answer = self
+                .host
+                .prompt(Prompt::StepFailed {
+                    step: spec.id,
+                    error: message.clone(),
+                    skippable: spec.skippable,
+                })
+                .await;
+            if is_cancelled() {
+                return Err(PipelineError::Cancelled { step: spec.id });
+            }
+            match answer {
+                PromptAnswer::Retry => continue,
+                PromptAnswer::Skip if spec.skippable => return Ok(StepStatus::Failed),
+                _ if action_required => {
+                    return Err(PipelineError::ActionRequired {
+                        step: spec.id,
+                        message,
+                    });
+                }
+                _ => {
+                    return Err(PipelineError::Failed {
+                        step: spec.id,
+                        message,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// The real steps, each one calls into `installer`.
+pub struct InstallerSteps;
+
+impl StepRunner for InstallerSteps {
+    async fn execute<H: PipelineHost>(
+        &self,
+        pipeline: &InstallPipeline<H, Self>,
+        step: StepId,
+        done_weight: u32,
+        report: &mut PipelineReport,
+    ) -> Result<StepStatus, StepError> {
+        let options = &pipeline.options;
+        match step {
+            StepId::Package => {
+                if options.offline_mode {
+                    installer::extract_package()
+                        .await
+                        .map_err(StepError::Failed)?;
+                    return Ok(StepStatus::Done);
+                }
+                if installer::check_temp_package_valid(options.sha256.clone())
+                    .await
+                    .unwrap_or(false)
+                {
+                    return Ok(StepStatus::Skipped);
+                }
+                let Some(mirror_url) = &options.mirror_url else {
+                    return Err(StepError::Failed("No mirror selected".to_string()));
+                };
+                let emit = pipeline.progress_emitter(step, done_weight);
+                let cancellation = CANCELLATION.lock().unwrap().clone();
+                // the download is the only step that is abandoned halfway
+                tokio::select! {
+                    res = installer::download_package(mirror_url, move |downloaded, total_size| {
+                        emit(downloaded as u64, total_size)
+                    }) => res.map_err(StepError::Failed)?,
+                    _ = cancellation.cancelled() => return Err(StepError::Cancelled),
+                }
+                Ok(StepStatus::Done)
+            }
+            StepId::Verify => {
+                // the embedded package is checked against its build-time hash
+                let sha256 = if options.offline_mode {
+                    installer::embedded_package_sha256()
+                } else {
+                    options.sha256.clone()
+                };
+                let valid = installer::check_temp_package_valid(sha256)
+                    .await
+                    .map_err(StepError::Failed)?;
+                if !valid {
+                    return Err(StepError::Failed(
+                        "Package hash does not match the manifest".to_string(),
+                    ));
+                }
+                Ok(StepStatus::Done)
+            }
+            StepId::Vcrt => {
+                if installer::check_vcrt().await.unwrap_or(false) {
+                    return Ok(StepStatus::Skipped);
+                }
+                let emit = pipeline.progress_emitter(step, done_weight);
+                let bundled = match &options.bundle_dir {
+                    Some(dir) => Some(dir.join(BundleFileKind::Vcrt.filename())),
+                    None => payload::extract_to_temp(BundleFileKind::Vcrt).await,
+                };
+                let reboot =
+                    installer::install_vcrt(bundled.as_deref(), move |downloaded, total_size| {
+                        emit(downloaded as u64, total_size)
+                    })
+                    .await
+                    .map_err(StepError::Failed)?;
+                report.reboot_required |= reboot;
+                Ok(StepStatus::Done)
+            }
+            StepId::Certificate => {
+                let bundled = match &options.bundle_dir {
+                    Some(dir) => Some(dir.join(BundleFileKind::Certificate.filename())),
+                    None => payload::extract_to_temp(BundleFileKind::Certificate).await,
+                };
+                installer::check_globalsign_r45(pipeline.host.window(), bundled.as_deref())
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+            StepId::Font => {
+                if installer::check_segoe_fluent_icons_font()
+                    .await
+                    .unwrap_or(false)
+                {
+                    return Ok(StepStatus::Skipped);
+                }
+                installer::install_segoe_fluent_icons_font()
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+            StepId::LongPath => {
+                installer::check_win32_long_path_support()
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+            StepId::StopHutao => {
+                let (running, pid) = installer::is_hutao_running()
+                    .await
+                    .map_err(StepError::Failed)?;
+                if !running {
+                    return Ok(StepStatus::Skipped);
+                }
+                if pipeline.host.prompt(Prompt::CloseHutao).await != PromptAnswer::Yes {
+                    return Err(StepError::Declined);
+                }
+                installer::kill_process(pid.unwrap_or_default())
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+            StepId::Migration => {
+                if !need_migration() {
+                    return Ok(StepStatus::Skipped);
+                }
+                if pipeline.host.prompt(Prompt::Migrate).await != PromptAnswer::Yes {
+                    return Err(StepError::Declined);
+                }
+                installer::remove_outdated_package()
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+            StepId::Deploy => {
+                let emit = pipeline.progress_emitter(step, done_weight);
+                let deployed = installer::install_package(
+                    options.sha256.clone(),
+                    options.offline_mode,
+                    pipeline.host.window().is_some(),
+                    options.allow_downgrade,
+                    move |opr| emit(opr.as_u64().unwrap_or_default(), 100),
+                )
+                .await
+                .map_err(StepError::Failed)?;
+                if !deployed {
+                    return Err(StepError::ActionRequired(
+                        "Package deployment was rejected, developer mode is required".to_string(),
+                    ));
+                }
+                versions::record_upgrade(pipeline.previous.as_deref(), options.version.as_deref())
+                    .await;
+                versions::keep_deployed_package(options.version.as_deref(), &options.sha256).await;
+                Ok(StepStatus::Done)
+            }
+            StepId::Shortcut => {
+                if !options.create_shortcut {
+                    return Ok(StepStatus::Skipped);
+                }
+                installer::create_desktop_lnk()
+                    .await
+                    .map_err(StepError::Failed)?;
+                Ok(StepStatus::Done)
+            }
+        }
+    }
+
+    fn installed_version(&self) -> Option<String> {
+        try_get_hutao_version()
+    }
+
+    async fn restore_kept(&self, version: &str) -> Option<Result<(), String>> {
+        if !versions::has_kept_package(version).await {
+            return None;
+        }
+        Some(versions::restore_kept_package(version).await)
+    }
+}
+
+lazy_static::lazy_static! {
+    // replaced by every run, cancelling only affects the running pipeline
+    static ref CANCELLATION: Mutex<CancellationToken> = Mutex::new(CancellationToken::new());
+    static ref PENDING_PROMPTS: Mutex<HashMap<u64, oneshot::Sender<PromptAnswer>>> =
+        Mutex::new(HashMap::new());
+    static ref NEXT_PROMPT_ID: AtomicU64 = AtomicU64::new(1);
+}
+
+// Emits the timeline to the window under `id`, prompts are answered through
+// `answer_prompt`.
+pub struct WindowHost {
+    pub window: WebviewWindow,
+    pub id: String,
+}
+
+impl PipelineHost for WindowHost {
+    fn emit(&self, event: TimelineEvent) {
+        let _ = self.window.emit(&self.id, event);
+    }
+
+    async fn prompt(&self, prompt: Prompt) -> PromptAnswer {
+        let id = NEXT_PROMPT_ID.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        PENDING_PROMPTS.lock().unwrap().insert(id, tx);
+        self.emit(TimelineEvent::Prompt { id, prompt });
+        rx.await.unwrap_or(PromptAnswer::Abort)
+    }
+
+    fn window(&self) -> Option<WebviewWindow> {
+        Some(self.window.clone())
+    }
+}
+
+pub fn answer_prompt(id: u64, answer: PromptAnswer) -> Result<(), String> {
+    let tx = PENDING_PROMPTS.lock().unwrap().remove(&id);
+    if tx.is_none() {
+        return Err(format!("No pending prompt {id}"));
+    }
+    let _ = tx.unwrap().send(answer);
+    Ok(())
+}
+
+fn is_cancelled() -> bool {
+    CANCELLATION.lock().unwrap().is_cancelled()
+}
+
+// The pipeline stops before its next step, a package download in progress is
+// abandoned. Deployment is never interrupted.
+pub fn request_cancel() {
+    CANCELLATION.lock().unwrap().cancel();
+    // a pending prompt would keep the pipeline waiting for an answer
+    for (_, tx) in PENDING_PROMPTS.lock().unwrap().drain() {
+        let _ = tx.send(PromptAnswer::Abort);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // the cancellation token is shared by every pipeline in the process
+    static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    // answers prompts in order, `Abort` once the script runs out
+    #[derive(Default)]
+    struct ScriptedHost {
+        answers: Mutex<VecDeque<PromptAnswer>>,
+        prompts: Mutex<Vec<Prompt>>,
+        events: Mutex<Vec<TimelineEvent>>,
+        cancel_on_prompt: bool,
+    }
+
+    impl PipelineHost for ScriptedHost {
+        fn emit(&self, event: TimelineEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+
+        async fn prompt(&self, prompt: Prompt) -> PromptAnswer {
+            self.prompts.lock().unwrap().push(prompt);
+            if self.cancel_on_prompt {
+                request_cancel();
+            }
+            let answer = self.answers.lock().unwrap().pop_front();
+            answer.unwrap_or(PromptAnswer::Abort)
+        }
+    }
+
+    // steps fail as many times as scripted and succeed after that
+    #[derive(Default)]
+    struct ScriptedSteps {
+        failures: Mutex<HashMap<StepId, u32>>,
+        cancel_after: Option<StepId>,
+        needs_user: Option<StepId>,
+        executed: Mutex<Vec<StepId>>,
+        installed: Mutex<Option<String>>,
+        kept: bool,
+        restored: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedSteps {
+        fn failing(step: StepId, times: u32) -> Self {
+            ScriptedSteps {
+                failures: Mutex::new(HashMap::from([(step, times)])),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl StepRunner for ScriptedSteps {
+        async fn execute<H: PipelineHost>(
+            &self,
+            _pipeline: &InstallPipeline<H, Self>,
+            step: StepId,
+            _done_weight: u32,
+            _report: &mut PipelineReport,
+        ) -> Result<StepStatus, StepError> {
+            self.executed.lock().unwrap().push(step);
+            if self.cancel_after == Some(step) {
+                request_cancel();
+            }
+            if self.needs_user == Some(step) {
+                return Err(StepError::ActionRequired(format!(
+                    "{step:?} needs the user"
+                )));
+            }
+            let mut failures = self.failures.lock().unwrap();
+            match failures.get_mut(&step) {
+                Some(left) if *left > 0 => {
+                    *left -= 1;
+                    // a failed deployment takes the previous version with it
+                    if step == StepId::Deploy {
+                        *self.installed.lock().unwrap() = None;
+                    }
+                    Err(StepError::Failed(format!("{step:?} broke")))
+                }
+                _ => Ok(StepStatus::Done),
+            }
+        }
+
+        fn installed_version(&self) -> Option<String> {
+            self.installed.lock().unwrap().clone()
+        }
+
+        async fn restore_kept(&self, version: &str) -> Option<Result<(), String>> {
+            if !self.kept {
+                return None;
+            }
+            self.restored.lock().unwrap().push(version.to_string());
+            Some(Ok(()))
+        }
+    }
+
+    fn pipeline(
+        steps: ScriptedSteps,
+        host: ScriptedHost,
+        answers: &[PromptAnswer],
+    ) -> InstallPipeline<ScriptedHost, ScriptedSteps> {
+        let options = PipelineOptions {
+            sha256: String::new(),
+            offline_mode: false,
+            mirror_url: None,
+            create_shortcut: false,
+            version: None,
+            allow_downgrade: false,
+            bundle_dir: None,
+        };
+        host.answers.lock().unwrap().extend(answers);
+        InstallPipeline::with_runner(options, host, steps)
+    }
+
+    fn executed(pipeline: &InstallPipeline<ScriptedHost, ScriptedSteps>) -> Vec<StepId> {
+        pipeline.runner.executed.lock().unwrap().clone()
+    }
+
+    fn all_steps() -> Vec<StepId> {
+        STEPS.iter().map(|spec| spec.id).collect()
+    }
+
+    #[tokio::test]
+    async fn runs_every_step_in_order() {
+        let _serial = SERIAL.lock().await;
+        let pipeline = pipeline(ScriptedSteps::default(), ScriptedHost::default(), &[]);
+        let report = pipeline.run().await.unwrap();
+
+        assert_eq!(executed(&pipeline), all_steps());
+        assert!(report.skipped.is_empty());
+        let events = pipeline.host.events.lock().unwrap();
+        let finished: Vec<StepId> = events
+            .iter()
+            .filter_map(|event| match event {
+                TimelineEvent::StepFinished {
+                    step,
+                    status: StepStatus::Done,
+                    ..
+                } => Some(*step),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(finished, all_steps());
+        assert!(matches!(
+            events.last(),
+            Some(TimelineEvent::Finished { success: true, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn retries_without_asking_while_retries_are_left() {
+        let _serial = SERIAL.lock().await;
+        // the package step is retried twice on its own
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Package, 2),
+            ScriptedHost::default(),
+            &[],
+        );
+        assert!(pipeline.run().await.is_ok());
+
+        let package_runs = executed(&pipeline)
+            .iter()
+            .filter(|step| **step == StepId::Package)
+            .count();
+        assert_eq!(package_runs, 3);
+        assert!(pipeline.host.prompts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_when_asked_after_retries_run_out() {
+        let _serial = SERIAL.lock().await;
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Verify, 1),
+            ScriptedHost::default(),
+            &[PromptAnswer::Retry],
+        );
+        assert!(pipeline.run().await.is_ok());
+
+        let verify_runs = executed(&pipeline)
+            .iter()
+            .filter(|step| **step == StepId::Verify)
+            .count();
+        assert_eq!(verify_runs, 2);
+        assert!(matches!(
+            pipeline.host.prompts.lock().unwrap().as_slice(),
+            [Prompt::StepFailed {
+                step: StepId::Verify,
+                skippable: false,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn skips_a_skippable_step_and_goes_on() {
+        let _serial = SERIAL.lock().await;
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Font, 2),
+            ScriptedHost::default(),
+            &[PromptAnswer::Skip],
+        );
+        let report = pipeline.run().await.unwrap();
+
+        assert_eq!(report.skipped, vec![StepId::Font]);
+        assert_eq!(executed(&pipeline).last(), Some(&StepId::Shortcut));
+    }
+
+    #[tokio::test]
+    async fn skips_the_dependents_of_a_skipped_step() {
+        let _serial = SERIAL.lock().await;
+        // nothing in `STEPS` depends on a skippable step yet
+        const STEPS_WITH_DEPENDENTS: &[StepSpec] = &[
+            StepSpec {
+                id: StepId::Font,
+                weight: 1,
+                depends_on: &[],
+                retries: 0,
+                skippable: true,
+            },
+            StepSpec {
+                id: StepId::LongPath,
+                weight: 1,
+                depends_on: &[StepId::Font],
+                retries: 0,
+                skippable: true,
+            },
+            StepSpec {
+                id: StepId::Shortcut,
+                weight: 1,
+                depends_on: &[StepId::LongPath],
+                retries: 0,
+                skippable: true,
+            },
+            StepSpec {
+                id: StepId::Deploy,
+                weight: 1,
+                depends_on: &[],
+                retries: 0,
+                skippable: false,
+            },
+        ];
+        let mut pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Font, 1),
+            ScriptedHost::default(),
+            &[PromptAnswer::Skip],
+        );
+        pipeline.steps = STEPS_WITH_DEPENDENTS;
+        pipeline.total_weight = 4;
+        let report = pipeline.run().await.unwrap();
+
+        assert_eq!(executed(&pipeline), vec![StepId::Font, StepId::Deploy]);
+        assert_eq!(
+            report.skipped,
+            vec![StepId::Font, StepId::LongPath, StepId::Shortcut]
+        );
+        let events = pipeline.host.events.lock().unwrap();
+        let skipped = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    TimelineEvent::StepFinished {
+                        status: StepStatus::Skipped,
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(skipped, 3);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_skip_a_required_step() {
+        let _serial = SERIAL.lock().await;
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Certificate, 3),
+            ScriptedHost::default(),
+            &[PromptAnswer::Skip],
+        );
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::Failed {
+                step: StepId::Certificate,
+                ..
+            })
+        ));
+        assert!(!executed(&pipeline).contains(&StepId::Font));
+    }
+
+    #[tokio::test]
+    async fn aborts_when_asked() {
+        let _serial = SERIAL.lock().await;
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Vcrt, 2),
+            ScriptedHost::default(),
+            &[PromptAnswer::Abort],
+        );
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::Failed {
+                step: StepId::Vcrt,
+                ..
+            })
+        ));
+        assert!(!executed(&pipeline).contains(&StepId::Certificate));
+        assert!(matches!(
+            pipeline.host.events.lock().unwrap().last(),
+            Some(TimelineEvent::Finished { success: false, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn asks_at_once_when_only_the_user_can_help() {
+        let _serial = SERIAL.lock().await;
+        // the package step would otherwise be retried twice on its own
+        let steps = ScriptedSteps {
+            needs_user: Some(StepId::Package),
+            ..Default::default()
+        };
+        let pipeline = pipeline(steps, ScriptedHost::default(), &[PromptAnswer::Abort]);
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::ActionRequired {
+                step: StepId::Package,
+                ..
+            })
+        ));
+        assert_eq!(executed(&pipeline), vec![StepId::Package]);
+        assert_eq!(pipeline.host.prompts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_before_the_next_step_when_cancelled() {
+        let _serial = SERIAL.lock().await;
+        let steps = ScriptedSteps {
+            cancel_after: Some(StepId::Vcrt),
+            ..Default::default()
+        };
+        let pipeline = pipeline(steps, ScriptedHost::default(), &[]);
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::Cancelled {
+                step: StepId::Certificate
+            })
+        ));
+        assert_eq!(executed(&pipeline).last(), Some(&StepId::Vcrt));
+    }
+
+    #[tokio::test]
+    async fn ignores_the_answer_of_a_prompt_cancelled_meanwhile() {
+        let _serial = SERIAL.lock().await;
+        let host = ScriptedHost {
+            cancel_on_prompt: true,
+            ..Default::default()
+        };
+        let pipeline = pipeline(
+            ScriptedSteps::failing(StepId::Verify, 1),
+            host,
+            &[PromptAnswer::Retry],
+        );
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::Cancelled {
+                step: StepId::Verify
+            })
+        ));
+        let verify_runs = executed(&pipeline)
+            .iter()
+            .filter(|step| **step == StepId::Verify)
+            .count();
+        assert_eq!(verify_runs, 1);
+    }
+
+    #[tokio::test]
+    async fn a_new_run_is_not_cancelled_by_the_previous_one() {
+        let _serial = SERIAL.lock().await;
+        request_cancel();
+        let pipeline = pipeline(ScriptedSteps::default(), ScriptedHost::default(), &[]);
+        assert!(pipeline.run().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn restores_the_previous_version_after_a_failed_deployment() {
+        let _serial = SERIAL.lock().await;
+        let steps = ScriptedSteps {
+            installed: Mutex::new(Some("1.0.0".to_string())),
+            kept: true,
+            ..ScriptedSteps::failing(StepId::Deploy, 1)
+        };
+        let pipeline = pipeline(steps, ScriptedHost::default(), &[PromptAnswer::Abort]);
+        let res = pipeline.run().await;
+
+        assert!(matches!(
+            res,
+            Err(PipelineError::Failed {
+                step: StepId::Deploy,
+                ..
+            })
+        ));
+        assert_eq!(
+            *pipeline.runner.restored.lock().unwrap(),
+            vec!["1.0.0".to_string()]
+        );
+        assert!(
+            pipeline
+                .host
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|event| matches!(event, TimelineEvent::RolledBack { success: true, .. }))
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_the_installed_version_alone_after_other_failures() {
+        let _serial = SERIAL.lock().await;
+        let steps = ScriptedSteps {
+            installed: Mutex::new(Some("1.0.0".to_string())),
+            kept: true,
+            ..ScriptedSteps::failing(StepId::Migration, 1)
+        };
+        let pipeline = pipeline(steps, ScriptedHost::default(), &[PromptAnswer::Abort]);
+        assert!(pipeline.run().await.is_err());
+
+        assert!(pipeline.runner.restored.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,592 @@
+use crate::{
+    api::CacheMode,
+    cli::arg::InstallArgs,
+    installer::{
+        self, EMBEDDED_SEGOE_FLUENT_ICON_FILENAME, EMBEDDED_SEGOE_FLUENT_ICON_NAME,
+        GLOBALSIGN_R45_SUBJECT, GLOBALSIGN_R45_URL, LONG_PATHS_KEY, OUTDATED_PACKAGE_FAMILY_NAME,
+        VCRT_DOWNLOAD_URL, VCRT_INSTALLER_NAME,
+    },
+    module::{
+        bundle::{self, Bundle, BundleFileKind},
+        headless::{self, ExitCode, HeadlessError},
+        versions,
+    },
+    utils::{
+        cert::find_certificate,
+        dir::{get_desktop, get_free_space, get_program_files},
+        package_manager::need_migration,
+    },
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+// An unpacked package takes roughly twice the size of the msix.
+const DEPLOYED_SIZE_FACTOR: u64 = 2;
+
+#[derive(Serialize, Debug)]
+pub struct PlanCheck {
+    pub name: &'static str,
+    pub satisfied: bool,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlannedAction {
+    Download {
+        what: String,
+        url: String,
+        size: Option<u64>,
+        destination: String,
+    },
+    Extract {
+        what: String,
+        size: u64,
+        destination: String,
+    },
+    RunInstaller {
+        what: String,
+        command: String,
+    },
+    RegistryWrite {
+        key: String,
+        value: String,
+        data: String,
+    },
+    CertificateAdd {
+        store: String,
+        subject: String,
+    },
+    FontInstall {
+        name: String,
+        destination: String,
+    },
+    StopProcess {
+        name: String,
+        pid: Option<u32>,
+    },
+    PackageRemove {
+        family_name: String,
+    },
+    PackageAdd {
+        version: String,
+        source: String,
+    },
+    Shortcut {
+        path: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiskRequirement {
+    pub path: String,
+    pub required: u64,
+    pub available: Option<u64>,
+    pub sufficient: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Plan {
+    pub installed_version: Option<String>,
+    pub target_version: String,
+    pub up_to_date: bool,
+    pub checks: Vec<PlanCheck>,
+    pub actions: Vec<PlannedAction>,
+    pub disk: Vec<DiskRequirement>,
+    // what the real run would exit with, as far as the checks can tell
+    #[serde(skip)]
+    pub exit_code: ExitCode,
+}
+
+pub async fn run(args: &InstallArgs, is_update: bool) -> Result<ExitCode, HeadlessError> {
+    let plan = build_plan(args, is_update).await?;
+    if args.json {
+        let json = serde_json::to_string_pretty(&plan);
+        if json.is_err() {
+            return headless::fail(
+                ExitCode::Failure,
+                format!("Failed to serialize plan: {:?}", json.err()),
+            );
+        }
+        println!("{}", json.unwrap());
+    } else {
+        print_plan(&plan);
+    }
+    Ok(plan.exit_code)
+}
+
+fn bundle_file_size(bundle: &Bundle, kind: BundleFileKind) -> u64 {
+    bundle
+        .manifest
+        .files
+        .iter()
+        .find(|f| f.kind == kind)
+        .map(|f| f.size)
+        .unwrap_or_default()
+}
+
+// Every check here only reads state: the patch cache is read but not
+// written, the session is left alone and no mirror is speed tested. The only
+// network traffic is the patch manifest and HEAD requests for download
+// sizes. A zipped bundle is unpacked to the temp dir to be verified.
+pub async fn build_plan(args: &InstallArgs, is_update: bool) -> Result<Plan, HeadlessError> {
+    headless::check_windows_version()?;
+    let bundle = match &args.from_bundle {
+        Some(path) => Some(bundle::open(path).await?),
+        None => None,
+    };
+    let package = match &args.package {
+        Some(path) => Some(headless::verify_package(path, CacheMode::ReadOnly).await?),
+        None => None,
+    };
+    let target = match (&bundle, &package) {
+        (Some(bundle), _) => bundle.target(),
+        (_, Some(package)) => package.target(),
+        _ => headless::resolve_target(args.version.as_deref(), CacheMode::ReadOnly).await?,
+    };
+    let up_to_date = target.is_up_to_date();
+
+    let temp_dir = std::env::temp_dir();
+    let package_path = temp_dir.join("Snap.Hutao.msix");
+    let mut checks = Vec::new();
+    let mut actions = Vec::new();
+    let mut temp_required = 0;
+
+    checks.push(PlanCheck {
+        name: "package_version",
+        satisfied: up_to_date,
+        detail: format!(
+            "installed {}, target {}",
+            target.installed.as_deref().unwrap_or("none"),
+            target.version
+        ),
+    });
+
+    let package_size = if let Some(bundle) = &bundle {
+        let size = bundle_file_size(bundle, BundleFileKind::Package);
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: true,
+            detail: format!("offline bundle at {}", bundle.dir.to_string_lossy()),
+        });
+        actions.push(PlannedAction::Extract {
+            what: "Snap Hutao package".to_string(),
+            size,
+            destination: package_path.to_string_lossy().to_string(),
+        });
+        temp_required += size;
+        Some(size)
+    } else if let Some(path) = &args.package {
+        let size = tokio::fs::metadata(path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or_default();
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: true,
+            detail: format!("verified package at {}", path.to_string_lossy()),
+        });
+        actions.push(PlannedAction::Extract {
+            what: "Snap Hutao package".to_string(),
+            size,
+            destination: package_path.to_string_lossy().to_string(),
+        });
+        temp_required += size;
+        Some(size)
+    } else if target.use_embedded {
+        let size = installer::embedded_package_size();
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: true,
+            detail: "embedded offline package".to_string(),
+        });
+        actions.push(PlannedAction::Extract {
+            what: "Snap Hutao package".to_string(),
+            size,
+            destination: package_path.to_string_lossy().to_string(),
+        });
+        temp_required += size;
+        Some(size)
+    } else if installer::check_temp_package_valid(target.sha256.clone())
+        .await
+        .unwrap_or(false)
+    {
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: true,
+            detail: "verified package already downloaded".to_string(),
+        });
+        tokio::fs::metadata(&package_path)
+            .await
+            .ok()
+            .map(|m| m.len())
+    } else if let Some(size) = versions::kept_package_size(&target.version).await {
+        // verified against the manifest once it is copied back
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: true,
+            detail: format!("kept package of {}", target.version),
+        });
+        temp_required += size;
+        Some(size)
+    } else {
+        let mirror_url =
+            headless::preview_mirror(target.patch.as_ref().unwrap(), args.mirror.as_deref())
+                .await?;
+        let size = installer::head_package(mirror_url.clone()).await.ok();
+        checks.push(PlanCheck {
+            name: "package_source",
+            satisfied: false,
+            detail: "package has to be downloaded".to_string(),
+        });
+        actions.push(PlannedAction::Download {
+            what: "Snap Hutao package".to_string(),
+            url: mirror_url,
+            size,
+            destination: package_path.to_string_lossy().to_string(),
+        });
+        temp_required += size.unwrap_or_default();
+        size
+    };
+
+    let vcrt_installed = installer::check_vcrt().await.unwrap_or(false);
+    checks.push(PlanCheck {
+        name: "vcrt",
+        satisfied: vcrt_installed,
+        detail: if vcrt_installed {
+            "MSVC runtime x64 and x86 installed".to_string()
+        } else {
+            "MSVC runtime missing".to_string()
+        },
+    });
+    if !vcrt_installed {
+        let vcrt_path = temp_dir.join(VCRT_INSTALLER_NAME);
+        let size = if let Some(bundle) = &bundle {
+            let size = bundle_file_size(bundle, BundleFileKind::Vcrt);
+            actions.push(PlannedAction::Extract {
+                what: "MSVC runtime".to_string(),
+                size,
+                destination: vcrt_path.to_string_lossy().to_string(),
+            });
+            Some(size)
+        } else {
+            let size = crate::fs::get_content_length(VCRT_DOWNLOAD_URL).await.ok();
+            actions.push(PlannedAction::Download {
+                what: "MSVC runtime".to_string(),
+                url: VCRT_DOWNLOAD_URL.to_string(),
+                size,
+                destination: vcrt_path.to_string_lossy().to_string(),
+            });
+            size
+        };
+        actions.push(PlannedAction::RunInstaller {
+            what: "MSVC runtime".to_string(),
+            command: format!("{} /install /quiet /norestart", vcrt_path.to_string_lossy()),
+        });
+        temp_required += size.unwrap_or_default();
+    }
+
+    let cert_found = find_certificate(GLOBALSIGN_R45_SUBJECT).await;
+    checks.push(PlanCheck {
+        name: "globalsign_r45",
+        satisfied: cert_found.as_ref().is_ok_and(|found| *found),
+        detail: match &cert_found {
+            Ok(true) => "certificate present in LocalMachine\\Root".to_string(),
+            Ok(false) => "certificate missing from LocalMachine\\Root".to_string(),
+            Err(e) => format!("failed to read certificate store: {e}"),
+        },
+    });
+    if !cert_found.is_ok_and(|found| found) {
+        if bundle.is_none() {
+            actions.push(PlannedAction::Download {
+                what: "GlobalSign Code Signing Root R45".to_string(),
+                url: GLOBALSIGN_R45_URL.to_string(),
+                size: None,
+                destination: "memory".to_string(),
+            });
+        }
+        actions.push(PlannedAction::CertificateAdd {
+            store: "LocalMachine\\Root".to_string(),
+            subject: GLOBALSIGN_R45_SUBJECT.to_string(),
+        });
+    }
+
+    let font_installed = installer::check_segoe_fluent_icons_font()
+        .await
+        .unwrap_or(false);
+    checks.push(PlanCheck {
+        name: "segoe_fluent_icons",
+        satisfied: font_installed,
+        detail: if font_installed {
+            "font present and up to date".to_string()
+        } else {
+            "font missing or outdated".to_string()
+        },
+    });
+    if !font_installed {
+        let win_dir = std::env::var("WINDIR").unwrap_or_else(|_| "C:\\Windows".into());
+        let font_path = Path::new(&win_dir)
+            .join("Fonts")
+            .join(EMBEDDED_SEGOE_FLUENT_ICON_FILENAME);
+        actions.push(PlannedAction::FontInstall {
+            name: EMBEDDED_SEGOE_FLUENT_ICON_NAME.to_string(),
+            destination: font_path.to_string_lossy().to_string(),
+        });
+        actions.push(PlannedAction::RegistryWrite {
+            key: r#"HKLM\SOFTWARE\Microsoft\Windows NT\CurrentVersion\Fonts"#.to_string(),
+            value: EMBEDDED_SEGOE_FLUENT_ICON_NAME.to_string(),
+            data: EMBEDDED_SEGOE_FLUENT_ICON_FILENAME.to_string(),
+        });
+    }
+
+    if let Some(bundle) = &bundle {
+        let webview2 = tauri::webview_version();
+        checks.push(PlanCheck {
+            name: "webview2",
+            satisfied: webview2.is_ok(),
+            detail: match &webview2 {
+                Ok(version) => format!("WebView2 runtime {version} installed"),
+                Err(_) => "WebView2 runtime missing".to_string(),
+            },
+        });
+        if webview2.is_err() {
+            actions.push(PlannedAction::RunInstaller {
+                what: "WebView2 runtime".to_string(),
+                command: format!(
+                    "{} /silent /install",
+                    bundle.path(BundleFileKind::WebView2).to_string_lossy()
+                ),
+            });
+        }
+    }
+
+    let long_paths = installer::is_win32_long_path_enabled();
+    checks.push(PlanCheck {
+        name: "long_paths",
+        satisfied: long_paths.as_ref().is_ok_and(|enabled| *enabled),
+        detail: match &long_paths {
+            Ok(true) => "LongPathsEnabled is 1".to_string(),
+            Ok(false) => "LongPathsEnabled is 0".to_string(),
+            Err(e) => e.clone(),
+        },
+    });
+    if !long_paths.is_ok_and(|enabled| enabled) {
+        actions.push(PlannedAction::RegistryWrite {
+            key: format!("HKLM\\{LONG_PATHS_KEY}"),
+            value: "LongPathsEnabled".to_string(),
+            data: "1".to_string(),
+        });
+    }
+
+    let (running, pid) = installer::is_hutao_running().await.unwrap_or((false, None));
+    checks.push(PlanCheck {
+        name: "hutao_running",
+        satisfied: !running,
+        detail: match (running, args.force_close) {
+            (false, _) => "Snap Hutao is not running".to_string(),
+            (true, true) => "Snap Hutao is running and will be closed".to_string(),
+            (true, false) => "Snap Hutao is running, --force-close is required".to_string(),
+        },
+    });
+    if running {
+        actions.push(PlannedAction::StopProcess {
+            name: "Snap.Hutao.exe".to_string(),
+            pid,
+        });
+    }
+
+    let migration = need_migration();
+    checks.push(PlanCheck {
+        name: "legacy_package",
+        satisfied: !migration,
+        detail: if migration {
+            format!("{OUTDATED_PACKAGE_FAMILY_NAME} is installed")
+        } else {
+            "no incompatible package installed".to_string()
+        },
+    });
+    if migration {
+        actions.push(PlannedAction::PackageRemove {
+            family_name: OUTDATED_PACKAGE_FAMILY_NAME.to_string(),
+        });
+    }
+
+    actions.push(PlannedAction::PackageAdd {
+        version: target.version.clone(),
+        source: package_path.to_string_lossy().to_string(),
+    });
+
+    if !args.no_shortcut && !is_update && target.installed.is_none() {
+        if let Ok(desktop) = get_desktop() {
+            actions.push(PlannedAction::Shortcut {
+                path: installer::get_desktop_lnk_path(&desktop),
+            });
+        }
+    }
+
+    let deploy_dir = get_program_files()
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("C:\\Program Files"));
+    let deploy_required = package_size.unwrap_or_default() * DEPLOYED_SIZE_FACTOR;
+    let disk = disk_requirements(&[
+        (temp_dir.clone(), temp_required),
+        (deploy_dir, deploy_required),
+    ]);
+    for requirement in &disk {
+        checks.push(PlanCheck {
+            name: "disk_space",
+            satisfied: requirement.sufficient,
+            detail: format!(
+                "{} needs {}, {} available",
+                requirement.path,
+                format_size(requirement.required),
+                requirement
+                    .available
+                    .map(format_size)
+                    .unwrap_or_else(|| "unknown".to_string())
+            ),
+        });
+    }
+
+    // nothing is changed when the installed version is already current
+    let exit_code = if up_to_date {
+        actions.clear();
+        ExitCode::Success
+    } else {
+        expected_exit_code(&disk, &temp_dir, running && !args.force_close)
+    };
+
+    Ok(Plan {
+        installed_version: target.installed,
+        target_version: target.version,
+        up_to_date,
+        checks,
+        actions,
+        disk,
+        exit_code,
+    })
+}
+
+// The pipeline downloads into the temp dir first, stops Snap Hutao later and
+// deploys last, the first check to fail decides the exit code.
+fn expected_exit_code(disk: &[DiskRequirement], temp_dir: &Path, blocked: bool) -> ExitCode {
+    let temp_volume = volume_root(temp_dir).to_string_lossy().to_string();
+    let temp_short = disk
+        .iter()
+        .any(|d| !d.sufficient && d.path.eq_ignore_ascii_case(&temp_volume));
+    if temp_short {
+        return ExitCode::DownloadFailed;
+    }
+    if blocked {
+        return ExitCode::UserActionRequired;
+    }
+    if disk.iter().any(|d| !d.sufficient) {
+        return ExitCode::DeploymentFailed;
+    }
+    ExitCode::Success
+}
+
+fn volume_root(path: &Path) -> PathBuf {
+    path.components()
+        .next()
+        .map(|c| PathBuf::from(format!("{}\\", c.as_os_str().to_string_lossy())))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// Requirements on the same volume are added up before comparing.
+fn disk_requirements(paths: &[(PathBuf, u64)]) -> Vec<DiskRequirement> {
+    let mut volumes: Vec<(PathBuf, u64)> = Vec::new();
+    for (path, required) in paths {
+        let root = volume_root(path);
+        match volumes.iter_mut().find(|(volume, _)| *volume == root) {
+            Some((_, total)) => *total += required,
+            None => volumes.push((root, *required)),
+        }
+    }
+
+    volumes
+        .into_iter()
+        .map(|(volume, required)| {
+            let available = get_free_space(&volume).ok();
+            DiskRequirement {
+                path: volume.to_string_lossy().to_string(),
+                required,
+                available,
+                sufficient: available.is_none_or(|available| available >= required),
+            }
+        })
+        .collect()
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn print_plan(plan: &Plan) {
+    println!(
+        "Snap Hutao {} -> {}",
+        plan.installed_version.as_deref().unwrap_or("none"),
+        plan.target_version
+    );
+
+    println!("\nChecks:");
+    for check in &plan.checks {
+        let mark = if check.satisfied { "ok" } else { "!!" };
+        println!("  [{mark}] {}: {}", check.name, check.detail);
+    }
+
+    println!("\nPlan:");
+    if plan.actions.is_empty() {
+        println!("  Nothing to do");
+    }
+    for (i, action) in plan.actions.iter().enumerate() {
+        let line = match action {
+            PlannedAction::Download {
+                what,
+                url,
+                size,
+                destination,
+            } => format!(
+                "Download {what} ({}) from {url} to {destination}",
+                size.map(format_size)
+                    .unwrap_or_else(|| "unknown size".to_string())
+            ),
+            PlannedAction::Extract {
+                what,
+                size,
+                destination,
+            } => format!("Extract {what} ({}) to {destination}", format_size(*size)),
+            PlannedAction::RunInstaller { what, command } => {
+                format!("Run {what} installer: {command}")
+            }
+            PlannedAction::RegistryWrite { key, value, data } => {
+                format!("Set registry value {key}\\{value} = {data}")
+            }
+            PlannedAction::CertificateAdd { store, subject } => {
+                format!("Add certificate \"{subject}\" to {store}")
+            }
+            PlannedAction::FontInstall { name, destination } => {
+                format!("Install font {name} to {destination}")
+            }
+            PlannedAction::StopProcess { name, pid } => match pid {
+                Some(pid) => format!("Stop {name} (pid {pid})"),
+                None => format!("Stop {name}"),
+            },
+            PlannedAction::PackageRemove { family_name } => {
+                format!("Remove package {family_name} for the current user")
+            }
+            PlannedAction::PackageAdd { version, source } => {
+                format!("Deploy Snap Hutao {version} from {source}")
+            }
+            PlannedAction::Shortcut { path } => format!("Create shortcut {path}"),
+        };
+        println!("  {}. {line}", i + 1);
+    }
+}
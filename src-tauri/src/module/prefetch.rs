@@ -0,0 +1,203 @@
+use crate::{
+    api::{self, GenericPatchData},
+    cli::arg::{CheckUpdateArgs, PrefetchArgs},
+    module::{
+        changelog,
+        channel::{self, Channel},
+        headless::{self, ExitCode, HeadlessError, fail, report},
+        versions,
+    },
+    utils::{
+        Version, dir::get_cache_dir, hash::run_sha256_file_hash_async,
+        package_manager::try_get_hutao_version,
+    },
+};
+use serde::Serialize;
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, PROCESS_MODE_BACKGROUND_BEGIN, SetPriorityClass,
+};
+
+// Snap Hutao checks for updates and prefetches the package on its own, the
+// `update` it starts later finds the package among the kept ones.
+#[derive(Serialize, Debug)]
+pub struct UpdateInfo {
+    pub installed: Option<String>,
+    pub latest: String,
+    // also true when Snap Hutao is not installed
+    pub update_available: bool,
+    pub size: Option<u64>,
+    pub changelog_url: String,
+    pub prefetched: bool,
+    pub channel: Channel,
+}
+
+fn needs_update(installed: Option<&str>, latest: &str) -> bool {
+    let Some(installed) = installed else {
+        return true;
+    };
+    match (
+        Version::from_string(installed),
+        Version::from_string(latest),
+    ) {
+        (Ok(installed), Ok(latest)) => installed < latest,
+        _ => installed != latest,
+    }
+}
+
+async fn is_prefetched(patch: &GenericPatchData) -> bool {
+    let Some(kept) = versions::kept_package_path(&patch.version) else {
+        return false;
+    };
+    if !tokio::fs::try_exists(&kept).await.unwrap_or(false) {
+        return false;
+    }
+    run_sha256_file_hash_async(&kept.to_string_lossy())
+        .await
+        .is_ok_and(|hash| hash == patch.sha256)
+}
+
+pub async fn run_check(args: &CheckUpdateArgs) -> ExitCode {
+    tracing::info!(target: "prefetch", "Checking update, options: {args:?}");
+    let patch = api::generic_get_patch().await;
+    if patch.is_err() {
+        eprintln!("Failed to get patch manifest: {}", patch.err().unwrap());
+        return ExitCode::PatchUnavailable;
+    }
+    let patch = patch.unwrap();
+
+    let installed = try_get_hutao_version();
+    let size = match patch.size {
+        Some(size) => Some(size),
+        None => match patch.urls.first() {
+            Some(url) => crate::fs::get_content_length(url)
+                .await
+                .ok()
+                .filter(|size| *size > 0),
+            None => None,
+        },
+    };
+    let info = UpdateInfo {
+        update_available: needs_update(installed.as_deref(), &patch.version),
+        changelog_url: changelog::changelog_url(
+            args.lang.as_deref().unwrap_or("en"),
+            installed.as_deref().unwrap_or(&patch.version),
+        ),
+        prefetched: is_prefetched(&patch).await,
+        channel: channel::current().await,
+        latest: patch.version,
+        installed,
+        size,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        return ExitCode::Success;
+    }
+    println!(
+        "Installed: {}",
+        info.installed.as_deref().unwrap_or("not installed")
+    );
+    println!("Latest: {} ({})", info.latest, info.channel.as_str());
+    if let Some(size) = info.size {
+        println!("Size: {size} bytes");
+    }
+    println!("Changelog: {}", info.changelog_url);
+    if info.update_available {
+        println!(
+            "Update available{}",
+            if info.prefetched { ", prefetched" } else { "" }
+        );
+    } else {
+        println!("Up to date");
+    }
+    ExitCode::Success
+}
+
+pub async fn run_prefetch(args: &PrefetchArgs) -> ExitCode {
+    tracing::info!(target: "prefetch", "Prefetching, options: {args:?}");
+    // lowers CPU and I/O priority so Snap Hutao and games are not disturbed
+    unsafe {
+        let _ = SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN);
+    }
+    match prefetch(args).await {
+        Ok(_) => ExitCode::Success,
+        Err(e) => {
+            eprintln!("{}", e.message);
+            e.code
+        }
+    }
+}
+
+async fn prefetch(args: &PrefetchArgs) -> Result<(), HeadlessError> {
+    let patch = api::generic_get_patch().await;
+    if patch.is_err() {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Failed to get patch manifest: {}", patch.err().unwrap()),
+        );
+    }
+    let patch = patch.unwrap();
+
+    let installed = try_get_hutao_version();
+    if !needs_update(installed.as_deref(), &patch.version) {
+        report(&format!("Snap Hutao {} is up to date", patch.version));
+        return Ok(());
+    }
+    if is_prefetched(&patch).await {
+        report(&format!(
+            "Snap Hutao {} is already prefetched",
+            patch.version
+        ));
+        return Ok(());
+    }
+
+    let url = headless::resolve_mirror(&patch, args.mirror.as_deref()).await?;
+    let Some(kept) = versions::kept_package_path(&patch.version) else {
+        return fail(
+            ExitCode::PatchUnavailable,
+            format!("Invalid version {} in the manifest", patch.version),
+        );
+    };
+    // kept packages are pruned by name, the partial download stays outside
+    let mut part = get_cache_dir().join(kept.file_name().unwrap());
+    part.set_extension("msix.part");
+    if let Some(parent) = kept.parent() {
+        let _ = tokio::fs::create_dir_all(parent).await;
+    }
+
+    let res = crate::fs::single_threaded_download(&url, &part.to_string_lossy(), |_| {}).await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+        return fail(
+            ExitCode::DownloadFailed,
+            format!("Failed to download package: {:?}", res.err()),
+        );
+    }
+    let hash = run_sha256_file_hash_async(&part.to_string_lossy()).await;
+    if !hash.as_ref().is_ok_and(|hash| *hash == patch.sha256) {
+        let _ = tokio::fs::remove_file(&part).await;
+        return fail(
+            ExitCode::HashMismatch,
+            format!("Package hash does not match the manifest: {hash:?}"),
+        );
+    }
+    let res = tokio::fs::rename(&part, &kept).await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+        return fail(
+            ExitCode::Failure,
+            format!("Failed to keep package: {:?}", res.err()),
+        );
+    }
+    versions::record_kept_package(&patch.version, &patch.sha256).await;
+    versions::prune_kept_packages().await;
+    report(&format!("Prefetched Snap Hutao {}", patch.version));
+    Ok(())
+}
+
+// The window stages a prefetched package before it picks a mirror, so an
+// update started by Snap Hutao skips the download.
+#[tauri::command]
+pub async fn stage_prefetched_package(version: String, sha256: String) -> bool {
+    versions::stage_kept_package(&version, &sha256).await
+}
@@ -0,0 +1,335 @@
+use crate::{
+    api::CacheMode,
+    cli::arg::RepairArgs,
+    installer::{self, GLOBALSIGN_R45_SUBJECT, HUTAO_PACKAGE_FAMILY_NAME},
+    module::{
+        headless::{self, ExitCode},
+        pipeline::PipelineOptions,
+    },
+    utils::{
+        Version,
+        cert::find_certificate,
+        dir::get_desktop,
+        package_manager::{
+            PackageRecord, find_package_records, register_package_by_full_name,
+            remove_package_by_full_name,
+        },
+    },
+};
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct Finding {
+    pub problem: String,
+    // what was done about it, or why it could not be fixed
+    pub outcome: Result<String, String>,
+    pub code: ExitCode,
+}
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub findings: Vec<Finding>,
+    pub reboot_required: bool,
+}
+
+impl RepairReport {
+    fn fixed(&mut self, problem: impl Into<String>, fix: impl Into<String>) {
+        self.findings.push(Finding {
+            problem: problem.into(),
+            outcome: Ok(fix.into()),
+            code: ExitCode::Success,
+        });
+    }
+
+    fn failed(&mut self, problem: impl Into<String>, error: impl Into<String>, code: ExitCode) {
+        self.findings.push(Finding {
+            problem: problem.into(),
+            outcome: Err(error.into()),
+            code,
+        });
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        if let Some(finding) = self.findings.iter().find(|f| f.outcome.is_err()) {
+            return finding.code;
+        }
+        if self.reboot_required {
+            return ExitCode::RebootRequired;
+        }
+        ExitCode::Success
+    }
+
+    fn to_text(&self) -> String {
+        if self.findings.is_empty() {
+            return "未发现问题".to_string();
+        }
+        let mut lines = Vec::new();
+        for finding in &self.findings {
+            match &finding.outcome {
+                Ok(fix) => lines.push(format!("{}: 已修复，{fix}", finding.problem)),
+                Err(error) => lines.push(format!("{}: 修复失败，{error}", finding.problem)),
+            }
+        }
+        if self.reboot_required {
+            lines.push("需要重启计算机以完成修复".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+pub async fn run(args: &RepairArgs) -> ExitCode {
+    tracing::info!(target: "repair", "Repairing, options: {args:?}");
+
+    if !args.silent {
+        let confirmed = rfd::MessageDialog::new()
+            .set_title("修复 Snap Hutao")
+            .set_description("确定要修复 Snap Hutao 吗？\n\n正在运行的 Snap Hutao 将被结束")
+            .set_level(rfd::MessageLevel::Warning)
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show();
+        if confirmed != rfd::MessageDialogResult::Yes {
+            return ExitCode::UserActionRequired;
+        }
+    }
+
+    if let Err(e) = headless::check_environment() {
+        eprintln!("{}", e.message);
+        return e.code;
+    }
+
+    // shell links need COM, see headless::run
+    let _com_guard = winsafe::CoInitializeEx(
+        winsafe::co::COINIT::MULTITHREADED | winsafe::co::COINIT::DISABLE_OLE1DDE,
+    );
+
+    let mut report = RepairReport::default();
+    repair_prerequisites(&mut report).await;
+    let healthy = repair_package(args, &mut report).await;
+    if healthy {
+        repair_shortcut(&mut report).await;
+    }
+
+    let text = report.to_text();
+    println!("{text}");
+    if !args.silent {
+        rfd::MessageDialog::new()
+            .set_title("修复 Snap Hutao")
+            .set_description(&text)
+            .set_level(if report.exit_code() == ExitCode::Success {
+                rfd::MessageLevel::Info
+            } else {
+                rfd::MessageLevel::Warning
+            })
+            .show();
+    }
+    report.exit_code()
+}
+
+async fn repair_prerequisites(report: &mut RepairReport) {
+    if !installer::check_vcrt().await.unwrap_or(false) {
+        let problem = "未安装 Visual C++ 运行库";
+        match installer::install_vcrt(None, |_, _| {}).await {
+            Ok(reboot_required) => {
+                report.reboot_required |= reboot_required;
+                report.fixed(problem, "已重新安装");
+            }
+            Err(e) => report.failed(problem, e, ExitCode::PrerequisiteFailed),
+        }
+    }
+
+    if !find_certificate(GLOBALSIGN_R45_SUBJECT)
+        .await
+        .unwrap_or(false)
+    {
+        let problem = "缺少 GlobalSign Code Signing Root R45 证书";
+        match installer::check_globalsign_r45(None, None).await {
+            Ok(_) => report.fixed(problem, "已导入证书"),
+            Err(e) => report.failed(problem, e, ExitCode::PrerequisiteFailed),
+        }
+    }
+
+    if !installer::check_segoe_fluent_icons_font()
+        .await
+        .unwrap_or(false)
+    {
+        let problem = "缺少 Segoe Fluent Icons 字体";
+        match installer::install_segoe_fluent_icons_font().await {
+            Ok(_) => report.fixed(problem, "已安装字体"),
+            Err(e) => report.failed(problem, e, ExitCode::PrerequisiteFailed),
+        }
+    }
+
+    if !installer::is_win32_long_path_enabled().unwrap_or(false) {
+        let problem = "未启用 Win32 长路径支持";
+        match installer::check_win32_long_path_support().await {
+            Ok(_) => report.fixed(problem, "已启用"),
+            Err(e) => report.failed(problem, e, ExitCode::PrerequisiteFailed),
+        }
+    }
+}
+
+fn is_healthy(record: &PackageRecord) -> bool {
+    record.current_user && record.installed && record.problems.is_empty()
+}
+
+fn find_records(report: &mut RepairReport) -> Option<Vec<PackageRecord>> {
+    let records = find_package_records(HUTAO_PACKAGE_FAMILY_NAME);
+    if records.is_err() {
+        report.failed(
+            "无法读取 Snap Hutao 的安装状态",
+            format!("{:?}", records.err()),
+            ExitCode::Failure,
+        );
+        return None;
+    }
+    Some(records.unwrap())
+}
+
+// Returns whether a working package is registered for the current user
+// once done. Nothing is installed when Snap Hutao was never there at all.
+async fn repair_package(args: &RepairArgs, report: &mut RepairReport) -> bool {
+    let Some(records) = find_records(report) else {
+        return false;
+    };
+    if records.is_empty() {
+        report.failed(
+            "未安装 Snap Hutao",
+            "请先安装 Snap Hutao",
+            ExitCode::UserActionRequired,
+        );
+        return false;
+    }
+
+    // staging left behind by failed deployments is not installed for anyone
+    for record in records.iter().filter(|r| !r.current_user && !r.installed) {
+        let problem = format!("残留的暂存包 {}", record.full_name);
+        match remove_package_by_full_name(&record.full_name, true) {
+            Ok(_) => report.fixed(problem, "已移除"),
+            Err(e) => report.failed(problem, format!("{e:?}"), ExitCode::RemovalFailed),
+        }
+    }
+
+    if records.iter().any(is_healthy) {
+        return true;
+    }
+
+    // half-registered packages get their registration redone first
+    let broken = records.iter().find(|r| r.current_user || r.installed);
+    if let Some(record) = broken {
+        let problem = if record.current_user {
+            format!(
+                "包 {} 已损坏: {}",
+                record.full_name,
+                record.problems.join(", ")
+            )
+        } else {
+            format!("包 {} 未注册到当前用户", record.full_name)
+        };
+        let res = register_package_by_full_name(&record.full_name);
+        let healthy = res.is_ok()
+            && find_records(report).is_some_and(|records| records.iter().any(is_healthy));
+        if healthy {
+            report.fixed(problem, "已重新注册");
+            return true;
+        }
+        tracing::warn!(
+            target: "repair",
+            "Re-registering {} did not help: {:?}",
+            record.full_name,
+            res.err()
+        );
+        if record.current_user {
+            let res = remove_package_by_full_name(&record.full_name, false);
+            if res.is_err() {
+                report.failed(problem, format!("{:?}", res.err()), ExitCode::RemovalFailed);
+                return false;
+            }
+        }
+    }
+
+    let problem = "Snap Hutao 无法通过重新注册修复";
+    let installed = broken.unwrap_or(&records[0]).version.clone();
+    match reinstall(args, &installed).await {
+        Ok((reboot_required, version)) => {
+            report.reboot_required |= reboot_required;
+            if Version::from_string(&version).ok() == Version::from_string(&installed).ok() {
+                report.fixed(problem, format!("已重新安装 {version}"));
+            } else {
+                report.fixed(
+                    problem,
+                    format!("无法获取 {installed} 的安装包，已重新安装最新版本 {version}"),
+                );
+            }
+            true
+        }
+        Err(e) => {
+            report.failed(problem, e.message, e.code);
+            false
+        }
+    }
+}
+
+// The installed version is put back, the latest one only when that version
+// can't be fetched any more. Returns the version that was installed.
+async fn reinstall(
+    args: &RepairArgs,
+    installed: &str,
+) -> Result<(bool, String), headless::HeadlessError> {
+    let target = match headless::resolve_target(Some(installed), CacheMode::ReadWrite).await {
+        Ok(target) => target,
+        Err(e) => {
+            headless::report(&format!(
+                "Snap Hutao {installed} is unavailable, reinstalling the latest version: {}",
+                e.message
+            ));
+            headless::resolve_target(None, CacheMode::ReadWrite).await?
+        }
+    };
+
+    // a cached package is only reused when it matches the manifest
+    let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+    let cached = !target.use_embedded
+        && installer::check_temp_package_valid(target.sha256.clone())
+            .await
+            .unwrap_or(false);
+    if !target.use_embedded && !cached {
+        let _ = tokio::fs::remove_file(&temp_package).await;
+    }
+
+    let mirror_url = if target.use_embedded || cached {
+        None
+    } else {
+        Some(
+            headless::resolve_mirror(target.patch.as_ref().unwrap(), args.mirror.as_deref())
+                .await?,
+        )
+    };
+
+    let options = PipelineOptions {
+        sha256: target.sha256,
+        offline_mode: target.use_embedded,
+        mirror_url,
+        create_shortcut: false,
+        version: Some(target.version.clone()),
+        allow_downgrade: false,
+        bundle_dir: None,
+    };
+    let report = headless::run_pipeline(options, true).await?;
+    Ok((report.reboot_required, target.version))
+}
+
+async fn repair_shortcut(report: &mut RepairReport) {
+    let Ok(desktop) = get_desktop() else {
+        return;
+    };
+    let lnk = installer::get_desktop_lnk_path(&desktop);
+    if tokio::fs::try_exists(Path::new(&lnk)).await.unwrap_or(true) {
+        return;
+    }
+
+    let problem = "桌面快捷方式丢失";
+    match installer::create_desktop_lnk().await {
+        Ok(_) => report.fixed(problem, "已重新创建"),
+        Err(e) => report.failed(problem, e, ExitCode::DeploymentFailed),
+    }
+}
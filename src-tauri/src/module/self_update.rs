@@ -0,0 +1,369 @@
+use crate::{
+    REAL_CURRENT_DIR,
+    api::GenericPatchData,
+    capture_and_return_err_message_string,
+    cli::arg::Command,
+    installer::{fetch_deployment_patch, kill_process},
+    module::{
+        channel::{self, Channel},
+        logging, payload, singleton, telemetry,
+    },
+    utils::{Version, authenticode::get_trusted_signer, hash::run_sha256_file_hash_async, process},
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Runtime, State, WebviewWindow};
+use tokio::time::Duration;
+use windows::{
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+        System::Threading::{
+            CreateEventW, EVENT_MODIFY_STATE, OpenEventW, SetEvent, WaitForSingleObject,
+        },
+    },
+    core::HSTRING,
+};
+
+// only stable is mirrored on the CDN, every channel falls back to the urls
+// of its manifest, all of them are checked against the manifest sha256
+const STABLE_CDN_URL: &str = "https://api.qhy04.com/hutaocdn/deployment";
+const HANDSHAKE_EVENT: &str = "HUTAO-INSTALLER-SELF-UPDATE";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+// patches are made with `zstd --patch-from --long=31`
+const PATCH_WINDOW_LOG_MAX: u32 = 31;
+
+// Installers are only ever published by DGP Studio. The key is not pinned
+// yet, it is logged on every check so it can be taken from a release.
+const TRUSTED_SIGNERS: &[&str] = &["DGP Studio"];
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SelfUpdateEvent {
+    Downloading { current: u64, total: u64 },
+    Patching,
+    Verifying,
+    Restarting,
+}
+
+// Called by the new installer once the frontend is up, the previous one is
+// waiting for it before it exits.
+pub fn complete_handshake() {
+    unsafe {
+        let Ok(event) = OpenEventW(EVENT_MODIFY_STATE, false, &HSTRING::from(HANDSHAKE_EVENT))
+        else {
+            return;
+        };
+        let _ = SetEvent(event);
+        let _ = CloseHandle(event);
+    }
+    tracing::info!(target: "self_update", "Self update handshake completed");
+}
+
+async fn download_with_progress(
+    url: &str,
+    target: &Path,
+    window: &WebviewWindow,
+    id: &str,
+) -> Result<(), String> {
+    tracing::info!(target: "self_update", "Downloading {url}");
+    let total = crate::fs::get_content_length(url).await.unwrap_or(0);
+    let window = window.clone();
+    let id = id.to_string();
+    let res = crate::fs::multi_threaded_download(
+        url,
+        target.to_string_lossy().as_ref(),
+        move |current| {
+            let _ = window.emit(
+                &id,
+                SelfUpdateEvent::Downloading {
+                    current: current as u64,
+                    total,
+                },
+            );
+        },
+    )
+    .await;
+    if res.is_err() {
+        return Err(format!("Failed to download {url}: {:?}", res.err()));
+    }
+    Ok(())
+}
+
+async fn check_hash(path: &Path, sha256: &str) -> Result<(), String> {
+    let hash = run_sha256_file_hash_async(&path.to_string_lossy()).await;
+    if hash.is_err() {
+        return Err(format!("Failed to hash new installer: {:?}", hash.err()));
+    }
+    let hash = hash.unwrap();
+    if hash != sha256 {
+        return Err(format!(
+            "New installer hash {hash} does not match the manifest"
+        ));
+    }
+    Ok(())
+}
+
+async fn download(
+    urls: &[String],
+    sha256: &str,
+    target: &Path,
+    window: &WebviewWindow,
+    id: &str,
+) -> Result<(), String> {
+    let mut last_error = "No installer url in deployment manifest".to_string();
+    for url in urls {
+        let res = download_with_progress(url, target, window, id).await;
+        let res = match res {
+            Ok(_) => check_hash(target, sha256).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = e,
+        }
+        tracing::warn!(target: "self_update", "{}", last_error);
+    }
+    Err(last_error)
+}
+
+// Rebuilds the new installer from the running one when the manifest has a
+// patch from its version. The result is checked like a full download.
+async fn download_patch(
+    data: &GenericPatchData,
+    current: &Version,
+    exe_path: &Path,
+    target: &Path,
+    window: &WebviewWindow,
+    id: &str,
+) -> Result<(), String> {
+    let patch = data.patches.iter().find(|patch| {
+        patch.format == "zstd" && Version::from_string(&patch.from).is_ok_and(|v| v == *current)
+    });
+    let Some(patch) = patch else {
+        return Err(format!("No patch from {current}"));
+    };
+
+    let patch_path = target.with_extension("patch");
+    let mut last_error = "No url for the patch".to_string();
+    let mut downloaded = false;
+    for url in &patch.urls {
+        match download_with_progress(url, &patch_path, window, id).await {
+            Ok(_) => {
+                downloaded = true;
+                break;
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    if !downloaded {
+        return Err(last_error);
+    }
+
+    let _ = window.emit(id, SelfUpdateEvent::Patching);
+    // a packed installer is patched from the stock binary it started from
+    let exe_path = exe_path.to_path_buf();
+    let target_ = target.to_path_buf();
+    let patch_path_ = patch_path.clone();
+    let res = tokio::task::spawn_blocking(move || -> Result<(), anyhow::Error> {
+        let reference = payload::stock_image(&exe_path, payload::get())?;
+        let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(
+            std::io::BufReader::new(std::fs::File::open(&patch_path_)?),
+            &reference,
+        )?;
+        decoder.window_log_max(PATCH_WINDOW_LOG_MAX)?;
+        let mut output = std::fs::File::create(&target_)?;
+        std::io::copy(&mut decoder, &mut output)?;
+        Ok(())
+    })
+    .await;
+    let _ = tokio::fs::remove_file(&patch_path).await;
+    match res {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(format!("Failed to apply patch: {e:?}")),
+        Err(e) => return Err(format!("Failed to apply patch: {e:?}")),
+    }
+    check_hash(target, &data.sha256).await
+}
+
+// Whoever signed the running installer does not matter, a packed or
+// development build may not carry a valid signature of its own.
+fn verify_signer(new_installer: &Path) -> Result<(), String> {
+    let signer = get_trusted_signer(new_installer);
+    if signer.is_err() {
+        return Err(format!(
+            "New installer signature verification failed: {:?}",
+            signer.err()
+        ));
+    }
+    let signer = signer.unwrap();
+    tracing::info!(
+        target: "self_update",
+        "New installer is signed by {}, key sha256 {}",
+        signer.name,
+        signer.public_key_sha256
+    );
+    let trusted = TRUSTED_SIGNERS.iter().any(|name| signer.name == *name);
+    if !trusted {
+        return Err(format!(
+            "New installer is signed by {}, which is not a trusted publisher",
+            signer.name
+        ));
+    }
+    Ok(())
+}
+
+async fn swap(exe_path: &Path, outdated: &Path, new_installer: &Path) -> Result<(), String> {
+    let mut last_error = None;
+    for attempt in 1..=5 {
+        if attempt > 1 {
+            tokio::time::sleep(Duration::from_millis(100 * attempt as u64)).await;
+        }
+
+        match tokio::fs::rename(exe_path, outdated).await {
+            Ok(_) => {
+                last_error = None;
+                break;
+            }
+            Err(e) => {
+                tracing::warn!(target: "self_update", "Rename attempt {} failed: {:?}", attempt, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_error {
+        capture_and_return_err_message_string!(format!("Failed to rename executable: {:?}", e));
+    }
+
+    // temp may live on another volume, so the new installer is copied
+    let res = tokio::fs::copy(new_installer, exe_path).await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(exe_path).await;
+        let _ = tokio::fs::rename(outdated, exe_path).await;
+        capture_and_return_err_message_string!(format!(
+            "Failed to write new installer: {:?}",
+            res.err()
+        ));
+    }
+    let _ = tokio::fs::remove_file(new_installer).await;
+    Ok(())
+}
+
+// The running executable can't be deleted, so the failed one is moved aside
+// and cleaned up by the next `need_self_update`.
+async fn restore(exe_path: &Path, outdated: &Path, pid: Option<u32>) -> Result<(), String> {
+    tracing::warn!(
+        target: "self_update",
+        "New installer did not start, restoring the previous one"
+    );
+    if let Some(pid) = pid {
+        let _ = kill_process(pid).await;
+    }
+    let failed = exe_path.with_extension("failed");
+    let _ = tokio::fs::remove_file(&failed).await;
+    let res = tokio::fs::rename(exe_path, &failed).await;
+    if res.is_err() {
+        capture_and_return_err_message_string!(format!(
+            "Failed to move new installer aside: {:?}",
+            res.err()
+        ));
+    }
+    let res = tokio::fs::rename(outdated, exe_path).await;
+    if res.is_err() {
+        capture_and_return_err_message_string!(format!(
+            "Failed to restore previous installer: {:?}",
+            res.err()
+        ));
+    }
+    Ok(())
+}
+
+async fn wait_for_handshake(event: isize) -> bool {
+    tokio::task::spawn_blocking(move || unsafe {
+        WaitForSingleObject(HANDLE(event as _), HANDSHAKE_TIMEOUT.as_millis() as u32)
+            == WAIT_OBJECT_0
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[tauri::command]
+pub async fn self_update<R: Runtime>(
+    id: String,
+    args: State<'_, Command>,
+    app: AppHandle<R>,
+    window: WebviewWindow,
+) -> Result<(), String> {
+    tracing::info!(target: "self_update", "Self-updating");
+    let exe_path = std::env::current_exe().unwrap();
+    let outdated = exe_path.with_extension("old");
+    let _ = tokio::fs::remove_file(&outdated).await;
+
+    // the manifest and the urls have to come from the same channel
+    let channel = channel::current().await;
+    let data = fetch_deployment_patch(channel).await?;
+    let mut urls = Vec::new();
+    if channel == Channel::Stable {
+        urls.push(STABLE_CDN_URL.to_string());
+    }
+    urls.extend(data.urls.iter().cloned());
+
+    let dir: PathBuf = std::env::temp_dir().join("HutaoInstaller").join("Update");
+    let _ = tokio::fs::create_dir_all(&dir).await;
+    let new_installer = dir.join(exe_path.file_name().unwrap());
+    let current = app.package_info().version.clone();
+    let current = Version::new(current.major, current.minor, current.patch, 0);
+    let patched = download_patch(&data, &current, &exe_path, &new_installer, &window, &id).await;
+    if patched.is_err() {
+        tracing::info!(
+            target: "self_update",
+            "Downloading the full installer: {}",
+            patched.err().unwrap()
+        );
+        download(&urls, &data.sha256, &new_installer, &window, &id).await?;
+    }
+
+    let _ = window.emit(&id, SelfUpdateEvent::Verifying);
+    verify_signer(&new_installer)?;
+    swap(&exe_path, &outdated, &new_installer).await?;
+
+    // kept as an integer, a HANDLE can't be held across an await
+    let event = unsafe { CreateEventW(None, true, false, &HSTRING::from(HANDSHAKE_EVENT)) }
+        .map(|event| event.0 as isize);
+    if event.is_err() {
+        let _ = restore(&exe_path, &outdated, None).await;
+        capture_and_return_err_message_string!(format!(
+            "Failed to create handshake event: {:?}",
+            event.err()
+        ));
+    }
+    let event = event.unwrap();
+
+    let _ = window.emit(&id, SelfUpdateEvent::Restarting);
+    singleton::destroy_plugin(&app);
+    let mut command = args.inner().clone().command_as_str();
+    if let Some(channel) = channel::get_override() {
+        command.push_str(&format!(" --channel {}", channel.as_str()));
+    }
+    command.push_str(&telemetry::policy().options_as_str());
+    command.push_str(&logging::options_as_str());
+    let pid = process::run(
+        true,
+        &exe_path,
+        REAL_CURRENT_DIR.clone().into(),
+        Some(command),
+    );
+
+    let started = pid.is_some() && wait_for_handshake(event).await;
+    let _ = unsafe { CloseHandle(HANDLE(event as _)) };
+    if started {
+        app.exit(0);
+        return Ok(());
+    }
+
+    restore(&exe_path, &outdated, pid).await?;
+    capture_and_return_err_message_string!(format!(
+        "New installer {} did not start within {}s, the previous one was restored",
+        data.version,
+        HANDSHAKE_TIMEOUT.as_secs()
+    ));
+}
@@ -0,0 +1,149 @@
+use crate::{
+    api::{self, CacheMode, PatchError},
+    installer::{self, HUTAO_PACKAGE_FAMILY_NAME},
+    module::{headless::Target, payload::same_version},
+    utils::{
+        Version,
+        hash::run_sha256_file_hash_async,
+        msix::{read_package_identity, verify_package_signature},
+        package_manager::try_get_hutao_version,
+    },
+};
+use serde::Serialize;
+use std::path::Path;
+use tauri::WebviewWindow;
+
+// A package the user brought along, from a browser mirror or someone else.
+#[derive(Serialize, Debug, Clone)]
+pub struct VerifiedPackage {
+    pub version: String,
+    pub sha256: String,
+}
+
+impl VerifiedPackage {
+    pub fn target(&self) -> Target {
+        Target {
+            patch: None,
+            use_embedded: false,
+            version: self.version.clone(),
+            sha256: self.sha256.clone(),
+            installed: try_get_hutao_version(),
+            pinned: false,
+        }
+    }
+}
+
+// The package has to be the one the manifest lists for its version. Only
+// when no manifest can be fetched, its identity and signature are enough.
+pub async fn verify(path: &Path, mode: CacheMode) -> Result<VerifiedPackage, String> {
+    tracing::info!(target: "sideload", "Verifying package {}", path.to_string_lossy());
+    if !tokio::fs::try_exists(path).await.unwrap_or(false) {
+        return Err(format!("Package {} does not exist", path.to_string_lossy()));
+    }
+
+    let identity = read_package_identity(path);
+    if identity.is_err() {
+        return Err(format!("Not a valid msix package: {:?}", identity.err()));
+    }
+    let identity = identity.unwrap();
+    if identity.family_name != HUTAO_PACKAGE_FAMILY_NAME {
+        return Err(format!(
+            "Package {} is not Snap Hutao",
+            identity.family_name
+        ));
+    }
+
+    let sha256 = run_sha256_file_hash_async(&path.to_string_lossy()).await;
+    if sha256.is_err() {
+        return Err(format!("Failed to hash package: {:?}", sha256.err()));
+    }
+    let sha256 = sha256.unwrap();
+
+    let latest = api::get_patch(mode).await;
+    if let Err(PatchError::Unavailable(e)) = &latest {
+        // only an unreachable manifest may be replaced by the signature, a
+        // manifest that fails verification rejects the package
+        tracing::warn!(
+            target: "sideload",
+            "No manifest, checking signature instead: {e}"
+        );
+        let res = verify_package_signature(path);
+        if res.is_err() {
+            return Err(format!("{:?}", res.err()));
+        }
+        return Ok(VerifiedPackage {
+            version: identity.version,
+            sha256,
+        });
+    }
+    if latest.is_err() {
+        return Err(format!(
+            "Failed to get patch manifest: {}",
+            latest.err().unwrap()
+        ));
+    }
+
+    let latest = latest.unwrap();
+    let expected = if same_version(&latest.version, &identity.version) {
+        latest.sha256
+    } else {
+        // manifests are named without the revision the identity carries
+        let version = Version::from_string(&identity.version)
+            .map_or_else(|_| identity.version.clone(), |v| v.to_string());
+        let patch = api::get_patch_version(&version, mode).await;
+        if patch.is_err() {
+            return Err(format!(
+                "Failed to get patch manifest of {version}: {}",
+                patch.err().unwrap()
+            ));
+        }
+        patch.unwrap().sha256
+    };
+    if expected != sha256 {
+        return Err(format!(
+            "Package hash {sha256} does not match the manifest of {}",
+            identity.version
+        ));
+    }
+    Ok(VerifiedPackage {
+        version: identity.version,
+        sha256,
+    })
+}
+
+// Copies the package to where the pipeline expects it.
+pub async fn stage(path: &Path, package: &VerifiedPackage) -> Result<(), String> {
+    let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+    if tokio::fs::canonicalize(path).await.ok() != tokio::fs::canonicalize(&temp_package).await.ok()
+    {
+        let res = tokio::fs::copy(path, &temp_package).await;
+        if res.is_err() {
+            return Err(format!("Failed to copy package: {:?}", res.err()));
+        }
+    }
+    if !installer::check_temp_package_valid(package.sha256.clone())
+        .await
+        .unwrap_or(false)
+    {
+        return Err("Staged package does not match the verified one".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn pick_package(window: WebviewWindow) -> Result<Option<VerifiedPackage>, String> {
+    let picked = rfd::AsyncFileDialog::new()
+        .set_title("选择 Snap Hutao 安装包")
+        .add_filter("MSIX", &["msix"])
+        .set_parent(&window)
+        .pick_file()
+        .await;
+    let Some(picked) = picked else {
+        return Ok(None);
+    };
+
+    let path = picked.path().to_path_buf();
+    let package = verify(&path, CacheMode::ReadWrite).await?;
+    stage(&path, &package).await?;
+    Ok(Some(package))
+}
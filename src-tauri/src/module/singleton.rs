@@ -65,12 +65,7 @@ pub fn init<R: Runtime>(userdata: UserData<R>) -> (bool, SingletonState) {
             let hwnd = FindWindowW(PCWSTR(clz_name.as_ptr()), PCWSTR(wnd_name.as_ptr()));
             if let Ok(hwnd) = hwnd {
                 if !hwnd.is_invalid() {
-                    sentry::add_breadcrumb(sentry::Breadcrumb {
-                        category: Some("singleton".into()),
-                        message: Some("Another instance is running".into()),
-                        level: sentry::Level::Warning,
-                        ..Default::default()
-                    });
+                    tracing::warn!(target: "singleton", "Another instance is running");
 
                     let data = COPYDATASTRUCT {
                         dwData: WMCOPYDATA_SINGLE_INSTANCE_DATA,
@@ -88,20 +83,10 @@ pub fn init<R: Runtime>(userdata: UserData<R>) -> (bool, SingletonState) {
                 }
             }
 
-            sentry::add_breadcrumb(sentry::Breadcrumb {
-                category: Some("singleton".into()),
-                message: Some("Mutex found, but window not found".into()),
-                level: sentry::Level::Warning,
-                ..Default::default()
-            });
+            tracing::warn!(target: "singleton", "Mutex found, but window not found");
             (true, SingletonState::default())
         } else {
-            sentry::add_breadcrumb(sentry::Breadcrumb {
-                category: Some("singleton".into()),
-                message: Some("Mutex created".into()),
-                level: sentry::Level::Info,
-                ..Default::default()
-            });
+            tracing::info!(target: "singleton", "Mutex created");
             let userdata = Box::into_raw(Box::new(userdata));
             let hwnd = create_event_target_window(&clz_name, &wnd_name, userdata);
             (
@@ -123,21 +108,11 @@ pub fn init_as_plugin<R: Runtime>() -> TauriPlugin<R> {
                 hwnd: std::ptr::null_mut(),
             });
             if !res {
-                sentry::add_breadcrumb(sentry::Breadcrumb {
-                    category: Some("singleton_as_plugin".into()),
-                    message: Some("Another instance is running".into()),
-                    level: sentry::Level::Warning,
-                    ..Default::default()
-                });
+                tracing::warn!(target: "singleton_as_plugin", "Another instance is running");
                 app.cleanup_before_exit();
                 std::process::exit(0);
             } else {
-                sentry::add_breadcrumb(sentry::Breadcrumb {
-                    category: Some("singleton_as_plugin".into()),
-                    message: Some("Singleton instance created".into()),
-                    level: sentry::Level::Info,
-                    ..Default::default()
-                });
+                tracing::info!(target: "singleton_as_plugin", "Singleton instance created");
                 app.manage(state);
             }
 
@@ -145,12 +120,7 @@ pub fn init_as_plugin<R: Runtime>() -> TauriPlugin<R> {
         })
         .on_event(|app, event| {
             if let RunEvent::Exit = event {
-                sentry::add_breadcrumb(sentry::Breadcrumb {
-                    category: Some("singleton_as_plugin".into()),
-                    message: Some("Singleton instance destroy invoked".into()),
-                    level: sentry::Level::Info,
-                    ..Default::default()
-                });
+                tracing::info!(target: "singleton_as_plugin", "Singleton instance destroy invoked");
                 destroy_plugin(app);
             }
         })
@@ -169,12 +139,7 @@ pub fn destroy(state: &SingletonState) {
             let _ = DestroyWindow(HWND(hwnd as _));
         }
     }
-    sentry::add_breadcrumb(sentry::Breadcrumb {
-        category: Some("singleton".into()),
-        message: Some("Singleton instance destroyed".into()),
-        level: sentry::Level::Info,
-        ..Default::default()
-    });
+    tracing::info!(target: "singleton", "Singleton instance destroyed");
 }
 
 pub fn destroy_plugin<R: Runtime, M: Manager<R>>(manager: &M) {
@@ -194,12 +159,7 @@ unsafe extern "system" fn singleton_window_proc<R: Runtime>(
             let create_struct = &*(lparam.0 as *const CREATESTRUCTW);
             let userdata = create_struct.lpCreateParams as *const UserData<R>;
             SetWindowLongPtrW(hwnd, GWLP_USERDATA, userdata as _);
-            sentry::add_breadcrumb(sentry::Breadcrumb {
-                category: Some("singleton".into()),
-                message: Some("Singleton window created".into()),
-                level: sentry::Level::Info,
-                ..Default::default()
-            });
+            tracing::info!(target: "singleton", "Singleton window created");
             LRESULT(0)
         }
 
@@ -213,32 +173,21 @@ unsafe extern "system" fn singleton_window_proc<R: Runtime>(
                         match window.hwnd() {
                             Ok(hwnd) => {
                                 switch_to(HWND(hwnd.0 as _));
-                                sentry::add_breadcrumb(sentry::Breadcrumb {
-                                    category: Some("singleton".into()),
-                                    message: Some("Switch to webview window".into()),
-                                    level: sentry::Level::Info,
-                                    ..Default::default()
-                                });
+                                tracing::info!(target: "singleton", "Switch to webview window");
                             }
                             Err(e) => {
-                                sentry::add_breadcrumb(sentry::Breadcrumb {
-                                    category: Some("singleton".into()),
-                                    message: Some(format!("Failed to get window handle: {}", e)),
-                                    level: sentry::Level::Error,
-                                    ..Default::default()
-                                });
+                                tracing::error!(
+                                    target: "singleton",
+                                    "Failed to get window handle: {}",
+                                    e
+                                );
                                 let _ = window.set_focus();
                             }
                         }
                     }
                 } else if let Some(hwnd) = *userdata.hwnd {
                     switch_to(HWND(hwnd.0 as _));
-                    sentry::add_breadcrumb(sentry::Breadcrumb {
-                        category: Some("singleton".into()),
-                        message: Some("Switch to wv2 install window".into()),
-                        level: sentry::Level::Info,
-                        ..Default::default()
-                    });
+                    tracing::info!(target: "singleton", "Switch to wv2 install window");
                 }
             }
             LRESULT(1)
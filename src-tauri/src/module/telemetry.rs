@@ -0,0 +1,219 @@
+use crate::{
+    cli::arg::TelemetryArgs,
+    utils::{dir::get_data_dir, secret},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use winreg::{RegKey, enums::HKEY_LOCAL_MACHINE};
+
+pub const DEFAULT_DSN: &str = "https://59ff148bff0f509baf01516d1f075d11@sentry.snapgenshin.com/10";
+
+// set by administrators, wins over everything the user chooses
+const POLICY_KEY: &str = r"SOFTWARE\Policies\DGP Studio\HutaoInstaller";
+
+const LEVEL_ENV: &str = "HUTAO_INSTALLER_TELEMETRY";
+const DSN_ENV: &str = "HUTAO_INSTALLER_TELEMETRY_DSN";
+const COLLECT_IP_ENV: &str = "HUTAO_INSTALLER_COLLECT_IP";
+const SEND_DEVICE_ID_ENV: &str = "HUTAO_INSTALLER_SEND_DEVICE_ID";
+
+static POLICY: OnceLock<TelemetryPolicy> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TelemetryLevel {
+    Off,
+    ErrorsOnly,
+    #[default]
+    Full,
+}
+
+impl TelemetryLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TelemetryLevel::Off => "off",
+            TelemetryLevel::ErrorsOnly => "errors-only",
+            TelemetryLevel::Full => "full",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        <Self as clap::ValueEnum>::from_str(value.trim(), true).ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetryPolicy {
+    pub level: TelemetryLevel,
+    pub dsn: String,
+    // reports the public IP address as the Sentry user
+    pub collect_ip: bool,
+    // sends `x-hutao-device-id` and reports the device id as the Sentry user
+    pub send_device_id: bool,
+}
+
+// What a single source sets, unset fields fall through to the next one.
+#[derive(Deserialize, Debug, Default)]
+struct PartialPolicy {
+    #[serde(default)]
+    level: Option<TelemetryLevel>,
+    #[serde(default)]
+    dsn: Option<String>,
+    #[serde(default)]
+    collect_ip: Option<bool>,
+    #[serde(default)]
+    send_device_id: Option<bool>,
+}
+
+impl PartialPolicy {
+    fn or(self, other: PartialPolicy) -> PartialPolicy {
+        PartialPolicy {
+            level: self.level.or(other.level),
+            dsn: self.dsn.or(other.dsn),
+            collect_ip: self.collect_ip.or(other.collect_ip),
+            send_device_id: self.send_device_id.or(other.send_device_id),
+        }
+    }
+}
+
+fn from_registry() -> PartialPolicy {
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(POLICY_KEY);
+    let Ok(key) = key else {
+        return PartialPolicy::default();
+    };
+    PartialPolicy {
+        level: key
+            .get_value::<String, _>("TelemetryLevel")
+            .ok()
+            .and_then(|level| TelemetryLevel::parse(&level)),
+        dsn: key.get_value::<String, _>("TelemetryDsn").ok(),
+        collect_ip: key.get_value::<u32, _>("CollectIp").ok().map(|v| v != 0),
+        send_device_id: key.get_value::<u32, _>("SendDeviceId").ok().map(|v| v != 0),
+    }
+}
+
+// the switches only ever turn collection off
+fn from_args(args: &TelemetryArgs) -> PartialPolicy {
+    PartialPolicy {
+        level: args.telemetry,
+        dsn: args.telemetry_dsn.clone(),
+        collect_ip: args.no_collect_ip.then_some(false),
+        send_device_id: args.no_device_id.then_some(false),
+    }
+}
+
+fn env_flag(name: &str) -> Option<bool> {
+    let value = std::env::var(name).ok()?;
+    match value.trim().to_ascii_lowercase().as_str() {
+        "0" | "false" | "off" | "no" => Some(false),
+        "1" | "true" | "on" | "yes" => Some(true),
+        _ => None,
+    }
+}
+
+fn from_env() -> PartialPolicy {
+    PartialPolicy {
+        level: std::env::var(LEVEL_ENV)
+            .ok()
+            .and_then(|level| TelemetryLevel::parse(&level)),
+        dsn: std::env::var(DSN_ENV).ok(),
+        collect_ip: env_flag(COLLECT_IP_ENV),
+        send_device_id: env_flag(SEND_DEVICE_ID_ENV),
+    }
+}
+
+fn from_file() -> PartialPolicy {
+    let Ok(data_dir) = get_data_dir() else {
+        return PartialPolicy::default();
+    };
+    std::fs::read(data_dir.join("telemetry.json"))
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default()
+}
+
+fn resolve(args: &TelemetryArgs) -> TelemetryPolicy {
+    let policy = from_registry()
+        .or(from_args(args))
+        .or(from_env())
+        .or(from_file());
+    TelemetryPolicy {
+        level: policy.level.unwrap_or_default(),
+        dsn: policy
+            .dsn
+            .map(|dsn| dsn.trim().to_string())
+            .filter(|dsn| !dsn.is_empty())
+            .unwrap_or_else(|| DEFAULT_DSN.to_string()),
+        collect_ip: policy.collect_ip.unwrap_or(true),
+        send_device_id: policy.send_device_id.unwrap_or(true),
+    }
+}
+
+// Must run before Sentry and the request client are set up.
+pub fn init(args: &TelemetryArgs) -> &'static TelemetryPolicy {
+    POLICY.get_or_init(|| resolve(args))
+}
+
+pub fn policy() -> &'static TelemetryPolicy {
+    POLICY.get_or_init(|| resolve(&TelemetryArgs::default()))
+}
+
+impl TelemetryPolicy {
+    pub fn client_options(&self) -> sentry::ClientOptions {
+        let full = self.level == TelemetryLevel::Full;
+        let dsn = match self.level {
+            TelemetryLevel::Off => None,
+            _ => {
+                let dsn = self.dsn.parse::<sentry::types::Dsn>();
+                if dsn.is_err() {
+                    eprintln!("Invalid telemetry DSN, telemetry is off: {:?}", dsn.err());
+                    None
+                } else {
+                    dsn.ok()
+                }
+            }
+        };
+        sentry::ClientOptions {
+            dsn,
+            release: sentry::release_name!(),
+            debug: cfg!(debug_assertions),
+            auto_session_tracking: full,
+            max_breadcrumbs: 1000,
+            sample_rate: 1.0,
+            traces_sample_rate: if full { 1.0 } else { 0.0 },
+            before_send: Some(std::sync::Arc::new(before_send)),
+            before_breadcrumb: Some(std::sync::Arc::new(secret::redact_breadcrumb)),
+            ..Default::default()
+        }
+    }
+
+    // An elevated relaunch does not see the caller's environment, so the
+    // resolved policy is passed on the command line instead.
+    pub fn options_as_str(&self) -> String {
+        let mut options = String::new();
+        if self.level != TelemetryLevel::Full {
+            options.push_str(&format!(" --telemetry {}", self.level.as_str()));
+        }
+        if self.dsn != DEFAULT_DSN {
+            options.push_str(&format!(" --telemetry-dsn \"{}\"", self.dsn));
+        }
+        if !self.collect_ip {
+            options.push_str(" --no-collect-ip");
+        }
+        if !self.send_device_id {
+            options.push_str(" --no-device-id");
+        }
+        options
+    }
+}
+
+fn before_send(
+    event: sentry::protocol::Event<'static>,
+) -> Option<sentry::protocol::Event<'static>> {
+    if policy().level == TelemetryLevel::ErrorsOnly
+        && event.exception.values.is_empty()
+        && !matches!(event.level, sentry::Level::Error | sentry::Level::Fatal)
+    {
+        return None;
+    }
+    secret::redact_event(event)
+}
@@ -0,0 +1,160 @@
+use crate::{
+    cli::arg::UninstallArgs,
+    installer::{self, HUTAO_PACKAGE_FAMILY_NAME, VCRT_INSTALLER_NAME},
+    module::headless::ExitCode,
+    utils::{
+        dir::{get_cache_dir, get_data_dir, get_desktop, get_local_app_data},
+        package_manager::remove_package,
+    },
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct UninstallSummary {
+    pub stopped: Vec<u32>,
+    pub removed_package: Option<String>,
+    pub removed_paths: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl UninstallSummary {
+    fn to_text(&self) -> String {
+        let mut lines = Vec::new();
+        for pid in &self.stopped {
+            lines.push(format!("已结束 Snap Hutao 进程 {pid}"));
+        }
+        match &self.removed_package {
+            Some(full_name) => lines.push(format!("已卸载包 {full_name}")),
+            None => lines.push("未安装 Snap Hutao".to_string()),
+        }
+        for path in &self.removed_paths {
+            lines.push(format!("已删除 {path}"));
+        }
+        for error in &self.errors {
+            lines.push(format!("失败: {error}"));
+        }
+        lines.join("\n")
+    }
+}
+
+pub async fn run(args: &UninstallArgs) -> ExitCode {
+    tracing::info!(target: "uninstall", "Uninstalling, options: {args:?}");
+
+    if !args.silent {
+        let mut message =
+            "确定要卸载 Snap Hutao 吗？\n\n正在运行的 Snap Hutao 将被结束".to_string();
+        if args.remove_data {
+            message.push_str("，本地数据将被删除");
+        }
+        let confirmed = rfd::MessageDialog::new()
+            .set_title("卸载 Snap Hutao")
+            .set_description(&message)
+            .set_level(rfd::MessageLevel::Warning)
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show();
+        if confirmed != rfd::MessageDialogResult::Yes {
+            return ExitCode::UserActionRequired;
+        }
+    }
+
+    let (summary, code) = uninstall(args).await;
+    let text = summary.to_text();
+    println!("{text}");
+    if !args.silent {
+        rfd::MessageDialog::new()
+            .set_title("卸载 Snap Hutao")
+            .set_description(&text)
+            .set_level(if summary.errors.is_empty() {
+                rfd::MessageLevel::Info
+            } else {
+                rfd::MessageLevel::Error
+            })
+            .show();
+    }
+    code
+}
+
+async fn uninstall(args: &UninstallArgs) -> (UninstallSummary, ExitCode) {
+    let mut summary = UninstallSummary::default();
+
+    // every running instance has to go before the package can be removed
+    while let Ok((true, Some(pid))) = installer::is_hutao_running().await {
+        if summary.stopped.contains(&pid) {
+            summary
+                .errors
+                .push(format!("Snap Hutao process {pid} did not exit"));
+            return (summary, ExitCode::UserActionRequired);
+        }
+        let res = installer::kill_process(pid).await;
+        if res.is_err() {
+            summary
+                .errors
+                .push(format!("Failed to stop Snap Hutao: {}", res.err().unwrap()));
+            return (summary, ExitCode::UserActionRequired);
+        }
+        summary.stopped.push(pid);
+    }
+
+    let res = remove_package(HUTAO_PACKAGE_FAMILY_NAME.to_string(), args.all_users);
+    if res.is_err() {
+        summary
+            .errors
+            .push(format!("Failed to remove package: {:?}", res.err()));
+        return (summary, ExitCode::RemovalFailed);
+    }
+    summary.removed_package = res.unwrap();
+
+    if let Ok(desktop) = get_desktop() {
+        remove_path(
+            Path::new(&installer::get_desktop_lnk_path(&desktop)),
+            &mut summary,
+        )
+        .await;
+    }
+
+    if args.remove_data {
+        // the package folder survives removal when files were left behind
+        if let Ok(local_app_data) = get_local_app_data() {
+            let package_data = PathBuf::from(local_app_data)
+                .join("Packages")
+                .join(HUTAO_PACKAGE_FAMILY_NAME);
+            remove_path(&package_data, &mut summary).await;
+        }
+        if let Ok(data_dir) = get_data_dir() {
+            remove_path(&data_dir, &mut summary).await;
+        }
+    }
+
+    if args.remove_cache {
+        let temp_dir = std::env::temp_dir();
+        remove_path(&temp_dir.join("Snap.Hutao.msix"), &mut summary).await;
+        remove_path(&temp_dir.join(VCRT_INSTALLER_NAME), &mut summary).await;
+        remove_path(&get_cache_dir(), &mut summary).await;
+    }
+
+    (summary, ExitCode::Success)
+}
+
+async fn remove_path(path: &Path, summary: &mut UninstallSummary) {
+    let metadata = tokio::fs::symlink_metadata(path).await;
+    if metadata.is_err() {
+        return;
+    }
+
+    let res = if metadata.unwrap().is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    };
+    if res.is_err() {
+        summary.errors.push(format!(
+            "Failed to remove {}: {:?}",
+            path.to_string_lossy(),
+            res.err()
+        ));
+        return;
+    }
+    summary
+        .removed_paths
+        .push(path.to_string_lossy().to_string());
+}
@@ -0,0 +1,384 @@
+use crate::{
+    api,
+    cli::arg::{ListVersionsArgs, RollbackArgs},
+    installer,
+    module::{
+        headless::{self, ExitCode, HeadlessError, fail},
+        pipeline::PipelineOptions,
+    },
+    utils::{
+        Version,
+        dir::get_cache_dir,
+        hash::run_sha256_file_hash_async,
+        package_manager::{add_package, try_get_hutao_version},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+// Deployed packages are kept by version so an upgrade can be undone without
+// downloading the previous release again.
+const KEPT_PACKAGES: usize = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RollbackPoint {
+    // the version that was replaced and can be restored
+    pub version: String,
+    pub replaced_by: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VersionEntry {
+    pub version: String,
+    pub installed: bool,
+    pub kept: bool,
+    pub rollback_target: bool,
+}
+
+fn packages_dir() -> PathBuf {
+    get_cache_dir().join("packages")
+}
+
+fn rollback_point_path() -> PathBuf {
+    get_cache_dir().join("rollback.json")
+}
+
+// sha256 of every kept package by version, recorded when it was kept
+fn kept_hashes_path() -> PathBuf {
+    get_cache_dir().join("packages.json")
+}
+
+// Versions come from the command line and from files in the cache, only
+// the parsed form is ever put into a path.
+fn normalize(version: &str) -> Option<String> {
+    Version::from_string(version).ok().map(|v| v.to_string())
+}
+
+pub fn kept_package_path(version: &str) -> Option<PathBuf> {
+    let version = normalize(version)?;
+    Some(packages_dir().join(format!("Snap.Hutao.{version}.msix")))
+}
+
+pub async fn kept_package_size(version: &str) -> Option<u64> {
+    let metadata = tokio::fs::metadata(kept_package_path(version)?).await;
+    metadata.ok().map(|m| m.len())
+}
+
+pub async fn has_kept_package(version: &str) -> bool {
+    match kept_package_path(version) {
+        Some(kept) => tokio::fs::try_exists(kept).await.unwrap_or(false),
+        None => false,
+    }
+}
+
+async fn load_kept_hashes() -> HashMap<String, String> {
+    let Ok(content) = tokio::fs::read(kept_hashes_path()).await else {
+        return HashMap::new();
+    };
+    serde_json::from_slice(&content).unwrap_or_default()
+}
+
+async fn save_kept_hashes(hashes: &HashMap<String, String>) {
+    let _ = tokio::fs::create_dir_all(get_cache_dir()).await;
+    if let Ok(content) = serde_json::to_vec(hashes) {
+        let _ = tokio::fs::write(kept_hashes_path(), content).await;
+    }
+}
+
+// Called once a package checked against the manifest is kept.
+pub async fn record_kept_package(version: &str, sha256: &str) {
+    let Some(version) = normalize(version) else {
+        return;
+    };
+    let mut hashes = load_kept_hashes().await;
+    hashes.insert(version, sha256.to_string());
+    save_kept_hashes(&hashes).await;
+}
+
+pub async fn load_rollback_point() -> Option<RollbackPoint> {
+    let content = tokio::fs::read(rollback_point_path()).await.ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+async fn save_rollback_point(point: &RollbackPoint) {
+    let _ = tokio::fs::create_dir_all(get_cache_dir()).await;
+    if let Ok(content) = serde_json::to_vec(point) {
+        let _ = tokio::fs::write(rollback_point_path(), content).await;
+    }
+}
+
+async fn clear_rollback_point() {
+    let _ = tokio::fs::remove_file(rollback_point_path()).await;
+}
+
+// Moves the package that was just deployed out of the temp dir into the kept
+// packages, only the newest ones and the rollback target stay around.
+pub async fn keep_deployed_package(version: Option<&str>, sha256: &str) {
+    let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+    let Some((version, kept)) = version.and_then(|v| Some((v, kept_package_path(v)?))) else {
+        let _ = tokio::fs::remove_file(temp_package).await;
+        return;
+    };
+
+    let _ = tokio::fs::create_dir_all(packages_dir()).await;
+    // temp and cache may live on different volumes, rename can't cross them
+    if tokio::fs::rename(&temp_package, &kept).await.is_err()
+        && tokio::fs::copy(&temp_package, &kept).await.is_ok()
+    {
+        let _ = tokio::fs::remove_file(&temp_package).await;
+    }
+    record_kept_package(version, sha256).await;
+    prune_kept_packages().await;
+}
+
+pub async fn prune_kept_packages() {
+    let Ok(mut entries) = tokio::fs::read_dir(packages_dir()).await else {
+        return;
+    };
+    let mut kept = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let version = name
+            .strip_prefix("Snap.Hutao.")
+            .and_then(|v| v.strip_suffix(".msix"))
+            .and_then(|v| Version::from_string(v).ok());
+        match version {
+            Some(version) => kept.push((version, entry.path())),
+            None => {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+
+    let rollback_target = load_rollback_point()
+        .await
+        .and_then(|p| kept_package_path(&p.version));
+    kept.sort_by_key(|k| std::cmp::Reverse(k.0));
+    let mut remaining = Vec::new();
+    for (index, (_, path)) in kept.into_iter().enumerate() {
+        if index < KEPT_PACKAGES || rollback_target.as_ref() == Some(&path) {
+            remaining.push(path);
+        } else {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    let mut hashes = load_kept_hashes().await;
+    hashes.retain(|version, _| kept_package_path(version).is_some_and(|p| remaining.contains(&p)));
+    save_kept_hashes(&hashes).await;
+}
+
+// Only upgrades are recorded, going back is not something to undo later.
+pub async fn record_upgrade(previous: Option<&str>, version: Option<&str>) {
+    let (Some(previous), Some(version)) = (previous, version) else {
+        return;
+    };
+    match (
+        Version::from_string(previous),
+        Version::from_string(version),
+    ) {
+        (Ok(old), Ok(new)) if new > old => {}
+        _ => return,
+    }
+    tracing::info!(target: "versions", "Recording rollback point {previous} -> {version}");
+    save_rollback_point(&RollbackPoint {
+        version: previous.to_string(),
+        replaced_by: version.to_string(),
+    })
+    .await;
+}
+
+// Copies the kept package of `version` to where the pipeline expects it,
+// returns whether the temp package now matches `sha256`.
+pub async fn stage_kept_package(version: &str, sha256: &str) -> bool {
+    if installer::check_temp_package_valid(sha256.to_string())
+        .await
+        .unwrap_or(false)
+    {
+        return true;
+    }
+    let Some(kept) = kept_package_path(version) else {
+        return false;
+    };
+    if !tokio::fs::try_exists(&kept).await.unwrap_or(false) {
+        return false;
+    }
+    let temp_package = std::env::temp_dir().join("Snap.Hutao.msix");
+    if tokio::fs::copy(&kept, &temp_package).await.is_err() {
+        return false;
+    }
+    installer::check_temp_package_valid(sha256.to_string())
+        .await
+        .unwrap_or(false)
+}
+
+// Deploys a kept package for when the manifest can't be fetched, checked
+// against the sha256 recorded when it was kept.
+pub async fn restore_kept_package(version: &str) -> Result<(), String> {
+    tracing::info!(target: "versions", "Restoring kept package {version}");
+    let (Some(kept), Some(normalized)) = (kept_package_path(version), normalize(version)) else {
+        return Err(format!("Invalid version {version}"));
+    };
+    if !tokio::fs::try_exists(&kept).await.unwrap_or(false) {
+        return Err(format!("No kept package for version {version}"));
+    }
+    let Some(sha256) = load_kept_hashes().await.remove(&normalized) else {
+        return Err(format!("No recorded hash for kept package {version}"));
+    };
+    let hash = run_sha256_file_hash_async(&kept.to_string_lossy()).await;
+    if hash.is_err() {
+        return Err(format!("Failed to hash kept package: {:?}", hash.err()));
+    }
+    if !hash.unwrap().eq_ignore_ascii_case(&sha256) {
+        let _ = tokio::fs::remove_file(&kept).await;
+        return Err(format!(
+            "Kept package {version} does not match its recorded hash"
+        ));
+    }
+    let res = add_package(kept.to_string_lossy().to_string(), false, true, |_| {});
+    if res.is_err() {
+        return Err(format!("Failed to add package: {:?}", res.err()));
+    }
+    if !res.unwrap() {
+        return Err("Package deployment was rejected".to_string());
+    }
+    Ok(())
+}
+
+pub async fn run_list(args: &ListVersionsArgs) -> ExitCode {
+    let versions = api::generic_list_versions().await;
+    if versions.is_err() {
+        eprintln!("{}", versions.err().unwrap());
+        return ExitCode::PatchUnavailable;
+    }
+
+    let installed = try_get_hutao_version();
+    let rollback_target = load_rollback_point().await.map(|p| p.version);
+    let mut entries = Vec::new();
+    for version in versions.unwrap() {
+        entries.push(VersionEntry {
+            installed: installed.as_ref() == Some(&version),
+            kept: has_kept_package(&version).await,
+            rollback_target: rollback_target.as_ref() == Some(&version),
+            version,
+        });
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        return ExitCode::Success;
+    }
+    for entry in &entries {
+        let mut line = entry.version.clone();
+        if entry.installed {
+            line.push_str(" (installed)");
+        }
+        if entry.kept {
+            line.push_str(" (kept)");
+        }
+        if entry.rollback_target {
+            line.push_str(" (rollback)");
+        }
+        println!("{line}");
+    }
+    ExitCode::Success
+}
+
+pub async fn run_rollback(args: &RollbackArgs) -> ExitCode {
+    tracing::info!(target: "versions", "Rolling back, options: {args:?}");
+
+    let Some(point) = load_rollback_point().await else {
+        eprintln!("No previous version to roll back to");
+        if !args.silent {
+            rfd::MessageDialog::new()
+                .set_title("回退 Snap Hutao")
+                .set_description("没有可以回退到的版本")
+                .set_level(rfd::MessageLevel::Info)
+                .show();
+        }
+        return ExitCode::PatchUnavailable;
+    };
+
+    if !args.silent {
+        let confirmed = rfd::MessageDialog::new()
+            .set_title("回退 Snap Hutao")
+            .set_description(format!(
+                "确定要将 Snap Hutao 从 {} 回退到 {} 吗？\n\n正在运行的 Snap Hutao 将被结束",
+                point.replaced_by, point.version
+            ))
+            .set_level(rfd::MessageLevel::Warning)
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show();
+        if confirmed != rfd::MessageDialogResult::Yes {
+            return ExitCode::UserActionRequired;
+        }
+    }
+
+    let res = rollback(&point, args).await;
+    let (code, text) = match res {
+        Ok(code) => (code, format!("已回退到 Snap Hutao {}", point.version)),
+        Err(e) => (e.code, format!("回退失败: {}", e.message)),
+    };
+    println!("{text}");
+    if !args.silent {
+        rfd::MessageDialog::new()
+            .set_title("回退 Snap Hutao")
+            .set_description(&text)
+            .set_level(
+                if code == ExitCode::Success || code == ExitCode::RebootRequired {
+                    rfd::MessageLevel::Info
+                } else {
+                    rfd::MessageLevel::Error
+                },
+            )
+            .show();
+    }
+    code
+}
+
+async fn rollback(point: &RollbackPoint, args: &RollbackArgs) -> Result<ExitCode, HeadlessError> {
+    headless::check_environment()?;
+
+    let patch = api::generic_get_patch_version(&point.version).await;
+    if patch.is_err() {
+        // without the manifest the kept package is all there is
+        let message = patch.err().unwrap();
+        if !has_kept_package(&point.version).await {
+            return fail(
+                ExitCode::PatchUnavailable,
+                format!(
+                    "Failed to get patch manifest of {}: {message}",
+                    point.version
+                ),
+            );
+        }
+        let res = restore_kept_package(&point.version).await;
+        if res.is_err() {
+            return fail(ExitCode::DeploymentFailed, res.err().unwrap());
+        }
+        clear_rollback_point().await;
+        return Ok(ExitCode::Success);
+    }
+
+    let patch = patch.unwrap();
+    let mirror_url = if stage_kept_package(&point.version, &patch.sha256).await {
+        None
+    } else {
+        Some(headless::resolve_mirror(&patch, args.mirror.as_deref()).await?)
+    };
+    let options = PipelineOptions {
+        sha256: patch.sha256.clone(),
+        offline_mode: false,
+        mirror_url,
+        create_shortcut: false,
+        version: Some(point.version.clone()),
+        allow_downgrade: true,
+        bundle_dir: None,
+    };
+    let report = headless::run_pipeline(options, true).await?;
+    clear_rollback_point().await;
+    if report.reboot_required {
+        return Ok(ExitCode::RebootRequired);
+    }
+    Ok(ExitCode::Success)
+}
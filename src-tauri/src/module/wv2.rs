@@ -1,5 +1,9 @@
 use crate::{
-    module::singleton::{self, SingletonState, UserData},
+    module::{
+        bundle::BundleFileKind,
+        payload,
+        singleton::{self, SingletonState, UserData},
+    },
     utils::process::{is_process_running, wait_for_pid},
 };
 use std::ptr::null_mut;
@@ -80,12 +84,7 @@ pub async fn install_webview2(command: String) {
         match msg {
             TDN_CREATED => {
                 (*conf).replace(hwnd);
-                sentry::add_breadcrumb(sentry::Breadcrumb {
-                    category: Some("wv2_installer".to_string()),
-                    message: Some("Task dialog created".to_string()),
-                    level: sentry::Level::Info,
-                    ..Default::default()
-                });
+                tracing::info!(target: "wv2_installer", "Task dialog created");
                 SendMessageW(
                     hwnd,
                     TDM_SET_PROGRESS_BAR_MARQUEE.0 as u32,
@@ -139,13 +138,17 @@ pub async fn install_webview2(command: String) {
     let installer_path = temp_dir.join("MicrosoftEdgeWebview2Setup.exe");
     let webview_installer_running_info =
         is_process_running("MicrosoftEdgeWebview2Setup.exe".to_string(), None).unwrap_or_default();
-    if !webview_installer_running_info.0 {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("wv2_installer".to_string()),
-            message: Some("WebView2 installer is not running, start downloading".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+    // an offline installer may carry the standalone installer, which takes
+    // the same arguments
+    let extracted = !webview_installer_running_info.0
+        && payload::extract(BundleFileKind::WebView2, &installer_path)
+            .await
+            .unwrap_or(false);
+    if !webview_installer_running_info.0 && !extracted {
+        tracing::info!(
+            target: "wv2_installer",
+            "WebView2 installer is not running, start downloading"
+        );
 
         // 使用多线程下载 WebView2 运行时，自动根据CPU线程数设置
         let url = "https://go.microsoft.com/fwlink/p/?LinkId=2124703";
@@ -189,20 +192,16 @@ pub async fn install_webview2(command: String) {
     }
 
     let id = if webview_installer_running_info.0 {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("wv2_installer".to_string()),
-            message: Some("WebView2 installer is already running, wait for it".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+        tracing::info!(
+            target: "wv2_installer",
+            "WebView2 installer is already running, wait for it"
+        );
         webview_installer_running_info.1.unwrap()
     } else {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("wv2_installer".to_string()),
-            message: Some("WebView2 installer is not running, start installing".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+        tracing::info!(
+            target: "wv2_installer",
+            "WebView2 installer is not running, start installing"
+        );
         tokio::process::Command::new(installer_path.clone())
             .arg("/install")
             .spawn()
@@ -225,12 +224,7 @@ pub async fn install_webview2(command: String) {
 
     let _ = tokio::fs::remove_file(installer_path).await;
     if status.success() {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("wv2_installer".to_string()),
-            message: Some("WebView2 installer finished successfully".to_string()),
-            level: sentry::Level::Info,
-            ..Default::default()
-        });
+        tracing::info!(target: "wv2_installer", "WebView2 installer finished successfully");
         // close the dialog
         let hwnd = dialog_hwnd.take();
         unsafe {
@@ -241,12 +235,7 @@ pub async fn install_webview2(command: String) {
             .spawn();
         exit_and_release_mutex(0, &singleton_state);
     } else {
-        sentry::add_breadcrumb(sentry::Breadcrumb {
-            category: Some("wv2_installer".to_string()),
-            message: Some(format!("WebView2 installer failed: {:?}", status.code()).to_string()),
-            level: sentry::Level::Error,
-            ..Default::default()
-        });
+        tracing::error!(target: "wv2_installer", "WebView2 installer failed: {:?}", status.code());
         let hwnd = dialog_hwnd.take();
         unsafe {
             SendMessageW(hwnd.unwrap(), WM_CLOSE, Some(WPARAM(0)), Some(LPARAM(0)));
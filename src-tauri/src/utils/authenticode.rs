@@ -0,0 +1,129 @@
+use crate::utils::{cert::thumbprint, hash::to_hex};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use windows::{
+    Win32::{
+        Foundation::{HANDLE, HWND},
+        Security::{
+            Cryptography::{CERT_CONTEXT, CERT_NAME_SIMPLE_DISPLAY_TYPE, CertGetNameStringW},
+            WinTrust::{
+                WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_DATA_0,
+                WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE,
+                WTD_STATEACTION_VERIFY, WTD_UI_NONE, WTHelperGetProvSignerFromChain,
+                WTHelperProvDataFromStateData, WinVerifyTrust,
+            },
+        },
+    },
+    core::{HSTRING, PCWSTR},
+};
+
+#[derive(Debug, Clone)]
+pub struct Signer {
+    // display name of the signer certificate, for messages only
+    pub name: String,
+    // sha256 of the signer certificate's public key
+    pub public_key_sha256: String,
+    // sha1 thumbprint of the root the chain ends in
+    pub root_thumbprint: String,
+}
+
+unsafe fn display_name(cert: *const CERT_CONTEXT) -> String {
+    unsafe {
+        let len = CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, None, None);
+        let mut name = vec![0u16; len as usize];
+        CertGetNameStringW(
+            cert,
+            CERT_NAME_SIMPLE_DISPLAY_TYPE,
+            0,
+            None,
+            Some(&mut name),
+        );
+        String::from_utf16_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+unsafe fn public_key_sha256(cert: *const CERT_CONTEXT) -> String {
+    unsafe {
+        let key = &(*(*cert).pCertInfo).SubjectPublicKeyInfo.PublicKey;
+        let bits = std::slice::from_raw_parts(key.pbData, key.cbData as usize);
+        to_hex(&Sha256::digest(bits))
+    }
+}
+
+unsafe fn signer(state: HANDLE) -> Result<Signer, anyhow::Error> {
+    unsafe {
+        let provider = WTHelperProvDataFromStateData(state);
+        if provider.is_null() {
+            return Err(anyhow::anyhow!("No provider data for the signature"));
+        }
+        let signer = WTHelperGetProvSignerFromChain(provider, 0, false, 0);
+        if signer.is_null() || (*signer).csCertChain == 0 {
+            return Err(anyhow::anyhow!("No signer certificate in the signature"));
+        }
+        // the chain runs from the signer itself up to the root
+        let chain =
+            std::slice::from_raw_parts((*signer).pasCertChain, (*signer).csCertChain as usize);
+        let cert = chain[0].pCert;
+        let root = chain[chain.len() - 1].pCert;
+        Ok(Signer {
+            name: display_name(cert),
+            public_key_sha256: public_key_sha256(cert),
+            root_thumbprint: thumbprint(root)?,
+        })
+    }
+}
+
+// Runs WinVerifyTrust on `path` and hands the verified state to `inspect`.
+// Revocation is not checked, so this also works without network access.
+fn verify<T>(
+    path: &Path,
+    inspect: impl FnOnce(HANDLE) -> Result<T, anyhow::Error>,
+) -> Result<T, anyhow::Error> {
+    let path = HSTRING::from(path);
+    let mut file_info = WINTRUST_FILE_INFO {
+        cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR(path.as_ptr()),
+        ..Default::default()
+    };
+    let mut data = WINTRUST_DATA {
+        cbStruct: size_of::<WINTRUST_DATA>() as u32,
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_NONE,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 {
+            pFile: &mut file_info,
+        },
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        ..Default::default()
+    };
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+
+    unsafe {
+        let status = WinVerifyTrust(HWND::default(), &mut action, &mut data as *mut _ as _);
+        let res = if status == 0 {
+            inspect(data.hWVTStateData)
+        } else {
+            Err(anyhow::anyhow!(
+                "Signature is not trusted: {:?}",
+                windows::core::HRESULT(status)
+            ))
+        };
+        data.dwStateAction = WTD_STATEACTION_CLOSE;
+        let _ = WinVerifyTrust(HWND::default(), &mut action, &mut data as *mut _ as _);
+        res
+    }
+}
+
+// Checks the signature of `path` chains to a trusted root.
+pub fn verify_trust(path: &Path) -> Result<(), anyhow::Error> {
+    verify(path, |_| Ok(()))
+}
+
+// Checks the signature of `path` chains to a trusted root and returns who
+// signed it. Callers pin the signer, a trusted chain alone says nothing
+// about who the file is from.
+pub fn get_trusted_signer(path: &Path) -> Result<Signer, anyhow::Error> {
+    verify(path, |state| unsafe { signer(state) })
+}
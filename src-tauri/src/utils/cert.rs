@@ -1,8 +1,46 @@
-use crate::capture_and_return_err;
+use crate::{capture_and_return_err, utils::hash::to_hex};
 use std::ffi::CString;
 use tokio_util::bytes::Bytes;
 use windows::{Win32::Security::Cryptography::*, core::s};
 
+// SHA-1 thumbprint of a certificate, as certmgr shows it but lowercase.
+/// # Safety
+/// `cert` must point to a valid certificate context.
+pub unsafe fn thumbprint(cert: *const CERT_CONTEXT) -> Result<String, anyhow::Error> {
+    unsafe {
+        let mut hash = [0u8; 20];
+        let mut len = hash.len() as u32;
+        let res = CertGetCertificateContextProperty(
+            cert,
+            CERT_SHA1_HASH_PROP_ID,
+            Some(hash.as_mut_ptr() as _),
+            &mut len,
+        );
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to get certificate thumbprint: {:?}",
+                res.err()
+            ));
+        }
+        Ok(to_hex(&hash[..len as usize]))
+    }
+}
+
+pub fn certificate_thumbprint(content: &[u8]) -> Result<String, anyhow::Error> {
+    unsafe {
+        let cert = CertCreateCertificateContext(X509_ASN_ENCODING, content);
+        if cert.is_null() {
+            return Err(anyhow::anyhow!(
+                "Failed to parse certificate: {:?}",
+                windows::core::Error::from_thread()
+            ));
+        }
+        let res = thumbprint(cert);
+        let _ = CertFreeCertificateContext(Some(cert));
+        res
+    }
+}
+
 pub async fn find_certificate(subject: &str) -> Result<bool, anyhow::Error> {
     unsafe {
         let store_name = s!("Root").as_ptr();
@@ -59,7 +97,7 @@ pub async fn find_certificate(subject: &str) -> Result<bool, anyhow::Error> {
 
 pub async fn install_certificate(
     content: Bytes,
-    window: tauri::WebviewWindow,
+    window: Option<tauri::WebviewWindow>,
 ) -> Result<bool, anyhow::Error> {
     unsafe {
         let store_name = s!("ROOT").as_ptr();
@@ -103,12 +141,15 @@ For more security information, please visit the url down below
 https://support.globalsign.com/ca-certificates/root-certificates/globalsign-root-certificates"#
             .to_string();
 
-        rfd::MessageDialog::new()
-            .set_title(&title)
-            .set_description(&message)
-            .set_level(rfd::MessageLevel::Info)
-            .set_parent(&window)
-            .show();
+        // nobody is there to read it in silent mode
+        if let Some(window) = window {
+            rfd::MessageDialog::new()
+                .set_title(&title)
+                .set_description(&message)
+                .set_level(rfd::MessageLevel::Info)
+                .set_parent(&window)
+                .show();
+        }
 
         let add_res = CertAddCertificateContextToStore(
             Some(h_store),
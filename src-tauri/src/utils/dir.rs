@@ -1,8 +1,51 @@
-use windows::Win32::UI::Shell::{FOLDERID_Desktop, SHGetKnownFolderPath, KF_FLAG_DEFAULT};
+use std::{
+    os::windows::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use windows::{
+    Win32::{
+        Foundation::{HLOCAL, LocalFree},
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::{
+            CreateDirectoryW, FILE_FLAG_BACKUP_SEMANTICS, FILE_SHARE_READ, FILE_SHARE_WRITE,
+            GetDiskFreeSpaceExW,
+        },
+        UI::Shell::{
+            FOLDERID_Desktop, FOLDERID_Downloads, FOLDERID_LocalAppData, FOLDERID_ProgramFiles,
+            IsUserAnAdmin, KF_FLAG_DEFAULT, SHGetKnownFolderPath,
+        },
+    },
+    core::{GUID, HSTRING, w},
+};
+
+lazy_static::lazy_static! {
+    // the staging directory of this run and the handle that pins it
+    static ref RUN_DIR: Mutex<Option<(PathBuf, std::fs::File)>> = Mutex::new(None);
+}
 
 pub fn get_desktop() -> Result<String, anyhow::Error> {
+    get_known_folder(&FOLDERID_Desktop)
+}
+
+pub fn get_downloads() -> Result<String, anyhow::Error> {
+    get_known_folder(&FOLDERID_Downloads)
+}
+
+// Packages are deployed to WindowsApps under this folder.
+pub fn get_program_files() -> Result<String, anyhow::Error> {
+    get_known_folder(&FOLDERID_ProgramFiles)
+}
+
+fn get_known_folder(folder_id: &GUID) -> Result<String, anyhow::Error> {
     let pwstr = unsafe {
-        SHGetKnownFolderPath(&FOLDERID_Desktop, KF_FLAG_DEFAULT, None)
+        SHGetKnownFolderPath(folder_id, KF_FLAG_DEFAULT, None)
             .map(|pwstr| {
                 pwstr
                     .to_string()
@@ -12,3 +55,123 @@ pub fn get_desktop() -> Result<String, anyhow::Error> {
     };
     Ok(pwstr)
 }
+
+pub fn get_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("HutaoInstaller").join("Cache")
+}
+
+pub fn get_local_app_data() -> Result<String, anyhow::Error> {
+    get_known_folder(&FOLDERID_LocalAppData)
+}
+
+pub fn get_data_dir() -> Result<PathBuf, anyhow::Error> {
+    let local_app_data = get_local_app_data()?;
+    Ok(PathBuf::from(local_app_data).join("HutaoInstaller"))
+}
+
+// Bytes available to the current user on the volume holding `path`.
+pub fn get_free_space(path: &Path) -> Result<u64, anyhow::Error> {
+    let mut free_bytes = 0u64;
+    let res = unsafe {
+        GetDiskFreeSpaceExW(
+            &HSTRING::from(path.as_os_str()),
+            Some(&mut free_bytes as *mut u64),
+            None,
+            None,
+        )
+    };
+    if res.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to get free space of {:?}: {:?}",
+            path,
+            res.err()
+        ));
+    }
+    Ok(free_bytes)
+}
+
+// Only SYSTEM and Administrators may change the directory or anything in it,
+// its owner is left with nothing but reading the descriptor. An installer
+// that is not elevated has nobody to keep out, so the owner keeps access.
+fn create_private_dir(path: &Path) -> Result<(), anyhow::Error> {
+    let sddl = if unsafe { IsUserAnAdmin() }.as_bool() {
+        w!("O:BAD:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;RC;;;OW)")
+    } else {
+        w!("D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;FA;;;OW)")
+    };
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    unsafe {
+        let res = ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl,
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        );
+        if res.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to create directory security descriptor: {:?}",
+                res.err()
+            ));
+        }
+    }
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor.0,
+        bInheritHandle: false.into(),
+    };
+    // fails on an existing directory, whoever created it first
+    let res = unsafe { CreateDirectoryW(&HSTRING::from(path.as_os_str()), Some(&attributes)) };
+    unsafe {
+        let _ = LocalFree(Some(HLOCAL(descriptor.0)));
+    }
+    if res.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to create {:?}: {:?}",
+            path,
+            res.err()
+        ));
+    }
+    Ok(())
+}
+
+// Prerequisites are staged here before they are run or imported elevated.
+// The directory is new for every run and stays open without delete sharing,
+// so it cannot be renamed and replaced while the installer uses it.
+pub fn get_run_dir() -> Result<PathBuf, anyhow::Error> {
+    let mut run_dir = RUN_DIR.lock().unwrap();
+    if let Some((path, _)) = run_dir.as_ref() {
+        return Ok(path.clone());
+    }
+
+    let parent = std::env::temp_dir().join("HutaoInstaller");
+    let _ = std::fs::create_dir_all(&parent);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let path = parent.join(format!("Run-{}-{}", std::process::id(), nanos));
+    create_private_dir(&path)?;
+    let handle = std::fs::OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ.0 | FILE_SHARE_WRITE.0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS.0)
+        .open(&path);
+    if handle.is_err() {
+        let _ = std::fs::remove_dir(&path);
+        return Err(anyhow::anyhow!(
+            "Failed to open {:?}: {:?}",
+            path,
+            handle.err()
+        ));
+    }
+    *run_dir = Some((path.clone(), handle.unwrap()));
+    Ok(path)
+}
+
+pub fn remove_run_dir() {
+    let run_dir = RUN_DIR.lock().unwrap().take();
+    if let Some((path, handle)) = run_dir {
+        drop(handle);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
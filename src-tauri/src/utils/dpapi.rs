@@ -0,0 +1,66 @@
+use windows::{
+    Win32::{
+        Foundation::{HLOCAL, LocalFree},
+        Security::Cryptography::{
+            CRYPT_INTEGER_BLOB, CRYPTPROTECT_UI_FORBIDDEN, CryptProtectData, CryptUnprotectData,
+        },
+    },
+    core::PCWSTR,
+};
+
+const ENTROPY: &[u8] = b"Snap.Hutao.Installer";
+
+fn blob(data: &[u8]) -> CRYPT_INTEGER_BLOB {
+    CRYPT_INTEGER_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut u8,
+    }
+}
+
+unsafe fn take_blob(out: CRYPT_INTEGER_BLOB) -> Vec<u8> {
+    let data = std::slice::from_raw_parts(out.pbData, out.cbData as usize).to_vec();
+    let _ = LocalFree(Some(HLOCAL(out.pbData as _)));
+    data
+}
+
+pub fn protect(data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let input = blob(data);
+    let entropy = blob(ENTROPY);
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        let res = CryptProtectData(
+            &input,
+            PCWSTR::null(),
+            Some(&entropy),
+            None,
+            None,
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        );
+        if res.is_err() {
+            return Err(anyhow::anyhow!("Failed to protect data: {:?}", res.err()));
+        }
+        Ok(take_blob(output))
+    }
+}
+
+pub fn unprotect(data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let input = blob(data);
+    let entropy = blob(ENTROPY);
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        let res = CryptUnprotectData(
+            &input,
+            None,
+            Some(&entropy),
+            None,
+            None,
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        );
+        if res.is_err() {
+            return Err(anyhow::anyhow!("Failed to unprotect data: {:?}", res.err()));
+        }
+        Ok(take_blob(output))
+    }
+}
@@ -1,5 +1,9 @@
 use std::path::Path;
 
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
 pub async fn run_sha256_hash_async(ctn: &str) -> Result<String, anyhow::Error> {
     let sha256 = chksum_sha2_256::async_chksum(ctn.as_bytes()).await;
     if sha256.is_err() {
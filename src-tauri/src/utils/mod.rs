@@ -1,10 +1,15 @@
+pub mod authenticode;
 pub mod cert;
 pub mod device;
 pub mod dir;
+pub mod dpapi;
 pub mod font;
 pub mod hash;
+pub mod msix;
 pub mod package_manager;
 pub mod process;
+pub mod secret;
+pub mod signature;
 pub mod windows_version;
 
 #[macro_export]
@@ -43,6 +48,14 @@ macro_rules! capture_and_return_err_message_string {
     }};
 }
 
+// Seconds since the epoch, cached responses are stamped with it.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 pub struct Version {
     pub major: u64,
     pub minor: u64,
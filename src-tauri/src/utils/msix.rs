@@ -0,0 +1,121 @@
+use crate::utils::authenticode::verify_trust;
+use std::path::Path;
+use windows::{
+    Win32::{
+        Storage::Packaging::Appx::{
+            APPX_PACKAGE_ARCHITECTURE, APPX_PACKAGE_ARCHITECTURE_ARM,
+            APPX_PACKAGE_ARCHITECTURE_ARM64, APPX_PACKAGE_ARCHITECTURE_NEUTRAL,
+            APPX_PACKAGE_ARCHITECTURE_X64, APPX_PACKAGE_ARCHITECTURE_X86, AppxFactory,
+            IAppxFactory,
+        },
+        System::Com::{CLSCTX_INPROC_SERVER, CoCreateInstance, CoTaskMemFree, STGM_READ},
+        UI::Shell::SHCreateStreamOnFileEx,
+    },
+    core::{HSTRING, PWSTR},
+};
+
+#[derive(Debug, Clone)]
+pub struct PackageIdentity {
+    pub name: String,
+    pub publisher: String,
+    pub family_name: String,
+    pub version: String,
+    pub architecture: String,
+}
+
+// Spelled as ProcessorArchitecture in AppxManifest.xml.
+fn architecture_name(architecture: APPX_PACKAGE_ARCHITECTURE) -> String {
+    match architecture {
+        APPX_PACKAGE_ARCHITECTURE_X86 => "x86",
+        APPX_PACKAGE_ARCHITECTURE_ARM => "arm",
+        APPX_PACKAGE_ARCHITECTURE_X64 => "x64",
+        APPX_PACKAGE_ARCHITECTURE_NEUTRAL => "neutral",
+        APPX_PACKAGE_ARCHITECTURE_ARM64 => "arm64",
+        _ => "unknown",
+    }
+    .to_string()
+}
+
+unsafe fn take_string(value: PWSTR) -> Result<String, anyhow::Error> {
+    unsafe {
+        let res = value.to_string();
+        CoTaskMemFree(Some(value.0 as _));
+        Ok(res?)
+    }
+}
+
+// Reads the identity from the manifest inside the package, nothing is
+// installed or registered.
+pub fn read_package_identity(path: &Path) -> Result<PackageIdentity, anyhow::Error> {
+    let _com_guard = winsafe::CoInitializeEx(
+        winsafe::co::COINIT::MULTITHREADED | winsafe::co::COINIT::DISABLE_OLE1DDE,
+    );
+
+    unsafe {
+        let factory: Result<IAppxFactory, _> =
+            CoCreateInstance(&AppxFactory, None, CLSCTX_INPROC_SERVER);
+        if factory.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to create appx factory: {:?}",
+                factory.err()
+            ));
+        }
+        let factory = factory?;
+
+        let stream = SHCreateStreamOnFileEx(&HSTRING::from(path), STGM_READ.0, 0, false, None);
+        if stream.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to open package: {:?}",
+                stream.err()
+            ));
+        }
+        let stream = stream?;
+
+        let reader = factory.CreatePackageReader(&stream);
+        if reader.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to read package: {:?}",
+                reader.err()
+            ));
+        }
+        let id = reader?
+            .GetManifest()
+            .and_then(|manifest| manifest.GetPackageId());
+        if id.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to read package identity: {:?}",
+                id.err()
+            ));
+        }
+        let id = id?;
+
+        let version = id.GetVersion()?;
+        Ok(PackageIdentity {
+            name: take_string(id.GetName()?)?,
+            publisher: take_string(id.GetPublisher()?)?,
+            family_name: take_string(id.GetPackageFamilyName()?)?,
+            architecture: architecture_name(id.GetArchitecture()?),
+            // PACKAGE_VERSION packs major.minor.build.revision into 16 bits each
+            version: format!(
+                "{}.{}.{}.{}",
+                version >> 48,
+                (version >> 32) & 0xFFFF,
+                (version >> 16) & 0xFFFF,
+                version & 0xFFFF
+            ),
+        })
+    }
+}
+
+// Checks the package signature chains to a trusted root, revocation is not
+// checked so this also works without network access.
+pub fn verify_package_signature(path: &Path) -> Result<(), anyhow::Error> {
+    let res = verify_trust(path);
+    if res.is_err() {
+        return Err(anyhow::anyhow!(
+            "Package signature is not trusted: {:?}",
+            res.err()
+        ));
+    }
+    Ok(())
+}
@@ -4,11 +4,13 @@ use crate::{
 use windows::{
     Foundation::Uri,
     Management::Deployment::{
-        AddPackageOptions, DeploymentProgress, PackageManager, RemovalOptions,
+        AddPackageOptions, DeploymentOptions, DeploymentProgress, DeploymentResult,
+        PackageInstallState, PackageManager, RemovalOptions,
     },
     core::{Error, HRESULT, HSTRING},
 };
-use windows_future::AsyncStatus;
+use windows_collections::IIterable;
+use windows_future::{AsyncStatus, IAsyncOperationWithProgress};
 
 pub fn check() {
     let package_manager = PackageManager::new();
@@ -186,6 +188,8 @@ pub fn try_get_hutao_version() -> Option<String> {
 
 pub fn add_package(
     raw_package_path: String,
+    interactive: bool,
+    allow_downgrade: bool,
     handler: impl Fn(serde_json::Value) + Send + 'static,
 ) -> Result<bool, anyhow::Error> {
     let package_manager = PackageManager::new();
@@ -215,6 +219,9 @@ pub fn add_package(
     let options = options?;
     let _ = options.SetForceAppShutdown(true);
     let _ = options.SetRetainFilesOnFailure(true);
+    if allow_downgrade {
+        let _ = options.SetForceUpdateFromAnyVersion(true);
+    }
     let op = package_manager.AddPackageByUriAsync(&package_uri, &options);
     if op.is_err() {
         capture_and_return_err!(anyhow::anyhow!("Failed to add package: {:?}", op.err()));
@@ -266,13 +273,17 @@ pub fn add_package(
         if ex_code == HRESULT(0x80070570u32 as i32) || ex_code == HRESULT(0x80070057u32 as i32) {
             let _ = std::fs::remove_file(raw_package_path);
         } else if ex_code == HRESULT(0x80073CFFu32 as i32) {
-            rfd::MessageDialog::new()
-                .set_title("错误")
-                .set_description("部署包失败，请启用开发者模式\n\n点击确定将跳转到开发者设置页面")
-                .set_level(rfd::MessageLevel::Error)
-                .show();
-
-            process::run(false, "ms-settings:developers", None::<&str>, None::<&str>);
+            if interactive {
+                rfd::MessageDialog::new()
+                    .set_title("错误")
+                    .set_description(
+                        "部署包失败，请启用开发者模式\n\n点击确定将跳转到开发者设置页面",
+                    )
+                    .set_level(rfd::MessageLevel::Error)
+                    .show();
+
+                process::run(false, "ms-settings:developers", None::<&str>, None::<&str>);
+            }
             return Ok(false);
         } else if ex_code == HRESULT(0) {
             capture_and_return_err!(anyhow::anyhow!(
@@ -290,7 +301,11 @@ pub fn add_package(
     }
 }
 
-pub fn remove_package(package_family_name: String) -> Result<(), anyhow::Error> {
+// Returns the full name of the removed package, or None if it was not installed.
+pub fn remove_package(
+    package_family_name: String,
+    all_users: bool,
+) -> Result<Option<String>, anyhow::Error> {
     let package_manager = PackageManager::new();
     if package_manager.is_err() {
         capture_and_return_err!(anyhow::anyhow!(
@@ -327,7 +342,7 @@ pub fn remove_package(package_family_name: String) -> Result<(), anyhow::Error>
         ));
     }
     if !has_current? {
-        return Ok(());
+        return Ok(None);
     }
 
     let package = iter.Current();
@@ -357,13 +372,65 @@ pub fn remove_package(package_family_name: String) -> Result<(), anyhow::Error>
     }
     let package_full_name = package_full_name?;
 
+    remove_package_by_full_name(&package_full_name.to_string(), all_users)?;
+    Ok(Some(package_full_name.to_string()))
+}
+
+pub fn remove_package_by_full_name(
+    package_full_name: &str,
+    all_users: bool,
+) -> Result<(), anyhow::Error> {
+    let package_manager = PackageManager::new();
+    if package_manager.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to create package manager: {:?}",
+            package_manager.err()
+        ));
+    }
+    let package_manager = package_manager?;
+
+    let removal_options = if all_users {
+        RemovalOptions::RemoveForAllUsers
+    } else {
+        RemovalOptions::None
+    };
     let op = package_manager
-        .RemovePackageWithOptionsAsync(&package_full_name, RemovalOptions::RemoveForAllUsers);
+        .RemovePackageWithOptionsAsync(&HSTRING::from(package_full_name), removal_options);
     if op.is_err() {
         capture_and_return_err!(anyhow::anyhow!("Failed to remove package: {:?}", op.err()));
     }
-    let op = op?;
+    wait_deployment(op?, "remove package")
+}
 
+// Registers an already staged package for the current user again.
+pub fn register_package_by_full_name(package_full_name: &str) -> Result<(), anyhow::Error> {
+    let package_manager = PackageManager::new();
+    if package_manager.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to create package manager: {:?}",
+            package_manager.err()
+        ));
+    }
+    let package_manager = package_manager?;
+
+    let op = package_manager.RegisterPackageByFullNameAsync(
+        &HSTRING::from(package_full_name),
+        None::<&IIterable<HSTRING>>,
+        DeploymentOptions::ForceApplicationShutdown,
+    );
+    if op.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to register package: {:?}",
+            op.err()
+        ));
+    }
+    wait_deployment(op?, "register package")
+}
+
+fn wait_deployment(
+    op: IAsyncOperationWithProgress<DeploymentResult, DeploymentProgress>,
+    action: &str,
+) -> Result<(), anyhow::Error> {
     let res = op.join();
     if res.is_err() {
         capture_and_return_err!(anyhow::anyhow!("Failed to get result: {:?}", res.err()));
@@ -400,14 +467,16 @@ pub fn remove_package(package_family_name: String) -> Result<(), anyhow::Error>
 
         if extended_error_code == HRESULT(0) {
             capture_and_return_err!(anyhow::anyhow!(
-                "Failed to remove package: {:?}, HResult Last Error: {:?}",
+                "Failed to {}: {:?}, HResult Last Error: {:?}",
+                action,
                 error_text,
                 Error::from_thread()
             ));
         }
 
         capture_and_return_err!(anyhow::anyhow!(
-            "Failed to remove package: {:?}, HResult Error: {:?}",
+            "Failed to {}: {:?}, HResult Error: {:?}",
+            action,
             error_text,
             Error::from_hresult(extended_error_code)
         ));
@@ -415,3 +484,105 @@ pub fn remove_package(package_family_name: String) -> Result<(), anyhow::Error>
 
     Ok(())
 }
+
+#[derive(Debug, Clone)]
+pub struct PackageRecord {
+    pub full_name: String,
+    pub version: String,
+    // registered for the user running the installer
+    pub current_user: bool,
+    // installed (not only staged) for at least one user
+    pub installed: bool,
+    pub problems: Vec<String>,
+}
+
+// Lists every package of the family on this machine, including packages that
+// are only staged or registered for other users.
+pub fn find_package_records(
+    package_family_name: &str,
+) -> Result<Vec<PackageRecord>, anyhow::Error> {
+    let package_manager = PackageManager::new();
+    if package_manager.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to create package manager: {:?}",
+            package_manager.err()
+        ));
+    }
+    let package_manager = package_manager?;
+    let family_name = HSTRING::from(package_family_name);
+
+    let current = package_manager
+        .FindPackagesByUserSecurityIdPackageFamilyName(&HSTRING::new(), &family_name);
+    if current.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to find packages for current user: {:?}",
+            current.err()
+        ));
+    }
+    let current_full_names: Vec<String> = current?
+        .into_iter()
+        .filter_map(|package| package.Id().and_then(|id| id.FullName()).ok())
+        .map(|full_name| full_name.to_string())
+        .collect();
+
+    let packages = package_manager.FindPackagesByPackageFamilyName(&family_name);
+    if packages.is_err() {
+        capture_and_return_err!(anyhow::anyhow!(
+            "Failed to find packages by package family name: {:?}",
+            packages.err()
+        ));
+    }
+
+    let mut records = Vec::new();
+    for package in packages? {
+        let id = package.Id();
+        if id.is_err() {
+            capture_and_return_err!(anyhow::anyhow!("Failed to get package ID: {:?}", id.err()));
+        }
+        let id = id?;
+        let full_name = id.FullName().map(|n| n.to_string()).unwrap_or_default();
+        let version = id
+            .Version()
+            .map(|v| format!("{}.{}.{}.{}", v.Major, v.Minor, v.Build, v.Revision))
+            .unwrap_or_default();
+
+        let mut problems = Vec::new();
+        if let Ok(status) = package.Status() {
+            let checks: [(&str, windows::core::Result<bool>); 8] = [
+                ("not available", status.NotAvailable()),
+                ("package offline", status.PackageOffline()),
+                ("needs remediation", status.NeedsRemediation()),
+                ("modified", status.Modified()),
+                ("tampered", status.Tampered()),
+                ("dependency issue", status.DependencyIssue()),
+                ("deployment in progress", status.DeploymentInProgress()),
+                ("partially staged", status.IsPartiallyStaged()),
+            ];
+            for (name, flag) in checks {
+                if flag.unwrap_or(false) {
+                    problems.push(name.to_string());
+                }
+            }
+        }
+
+        let installed = package_manager
+            .FindUsers(&HSTRING::from(full_name.as_str()))
+            .map(|users| {
+                users.into_iter().any(|user| {
+                    user.InstallState()
+                        .is_ok_and(|state| state == PackageInstallState::Installed)
+                })
+            })
+            .unwrap_or(false);
+
+        records.push(PackageRecord {
+            current_user: current_full_names.contains(&full_name),
+            full_name,
+            version,
+            installed,
+            problems,
+        });
+    }
+
+    Ok(records)
+}
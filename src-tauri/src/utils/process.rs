@@ -9,7 +9,7 @@ use windows::{
                 TH32CS_SNAPPROCESS,
             },
             Threading::{
-                GetExitCodeProcess, INFINITE, OpenProcess, PROCESS_NAME_FORMAT,
+                GetExitCodeProcess, GetProcessId, INFINITE, OpenProcess, PROCESS_NAME_FORMAT,
                 PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE,
                 QueryFullProcessImageNameW,
             },
@@ -31,7 +31,7 @@ pub fn run<P: AsRef<OsStr>, W: AsRef<OsStr>, A: AsRef<OsStr>>(
     program_path: P,
     working_dir: Option<W>,
     args: Option<A>,
-) {
+) -> Option<u32> {
     let file = PCWSTR(HSTRING::from(program_path.as_ref()).as_ptr());
     let dir = if let Some(dir) = working_dir {
         PCWSTR(HSTRING::from(dir.as_ref()).as_ptr())
@@ -58,7 +58,12 @@ pub fn run<P: AsRef<OsStr>, W: AsRef<OsStr>, A: AsRef<OsStr>>(
     unsafe {
         let _ = ShellExecuteExW(&mut sei);
         let process = sei.hProcess;
+        if process.is_invalid() {
+            return None;
+        }
+        let pid = GetProcessId(process);
         let _ = CloseHandle(process);
+        (pid != 0).then_some(pid)
     }
 }
 
@@ -0,0 +1,152 @@
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader},
+    os::windows::io::{FromRawHandle, RawHandle},
+    sync::RwLock,
+};
+use windows::Win32::{
+    Foundation::{GetHandleInformation, HANDLE, HANDLE_FLAG_INHERIT},
+    Storage::FileSystem::{FILE_TYPE_DISK, FILE_TYPE_PIPE, GetFileType},
+};
+
+const REDACTED: &str = "[redacted]";
+
+lazy_static::lazy_static! {
+    static ref SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+// Every registered secret is replaced in whatever is sent to Sentry.
+pub fn register(secret: &str) {
+    let secret = secret.trim();
+    // too short to be told apart from ordinary text
+    if secret.len() < 8 {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+pub fn redact(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.read().unwrap().iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+    text
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = redact(text),
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        Value::Object(map) => map.values_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+// Goes through the serialized form so contexts, tags, extra data and
+// breadcrumbs are all covered. Should that round trip fail, `fallback`
+// redacts the fields known to carry text instead.
+fn redact_serialized<T>(item: T, fallback: fn(T) -> T) -> T
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    if SECRETS.read().unwrap().is_empty() {
+        return item;
+    }
+    let Ok(mut value) = serde_json::to_value(&item) else {
+        return fallback(item);
+    };
+    redact_value(&mut value);
+    match serde_json::from_value(value) {
+        Ok(redacted) => redacted,
+        Err(_) => fallback(item),
+    }
+}
+
+fn redact_breadcrumb_fields(mut breadcrumb: sentry::Breadcrumb) -> sentry::Breadcrumb {
+    breadcrumb.message = breadcrumb.message.as_deref().map(redact);
+    breadcrumb.data.values_mut().for_each(redact_value);
+    breadcrumb
+}
+
+// Contexts and the request have no plain text fields to go through, they are
+// dropped rather than sent unredacted.
+fn redact_event_fields(
+    mut event: sentry::protocol::Event<'static>,
+) -> sentry::protocol::Event<'static> {
+    event.message = event.message.as_deref().map(redact);
+    if let Some(logentry) = &mut event.logentry {
+        logentry.message = redact(&logentry.message);
+        logentry.params.iter_mut().for_each(redact_value);
+    }
+    for exception in event.exception.values.iter_mut() {
+        exception.value = exception.value.as_deref().map(redact);
+    }
+    event.breadcrumbs.values = std::mem::take(&mut event.breadcrumbs.values)
+        .into_iter()
+        .map(redact_breadcrumb_fields)
+        .collect();
+    event.extra.values_mut().for_each(redact_value);
+    event.tags.values_mut().for_each(|tag| *tag = redact(tag));
+    event.contexts.clear();
+    event.request = None;
+    event
+}
+
+pub fn redact_event(
+    event: sentry::protocol::Event<'static>,
+) -> Option<sentry::protocol::Event<'static>> {
+    Some(redact_serialized(event, redact_event_fields))
+}
+
+pub fn redact_breadcrumb(breadcrumb: sentry::Breadcrumb) -> Option<sentry::Breadcrumb> {
+    Some(redact_serialized(breadcrumb, redact_breadcrumb_fields))
+}
+
+fn read_line(reader: impl std::io::Read) -> Result<String, anyhow::Error> {
+    let mut line = String::new();
+    let res = BufReader::new(reader).read_line(&mut line);
+    if res.is_err() {
+        return Err(anyhow::anyhow!("Failed to read secret: {:?}", res.err()));
+    }
+    let line = line.trim().to_string();
+    if line.is_empty() {
+        return Err(anyhow::anyhow!("No secret was provided"));
+    }
+    register(&line);
+    Ok(line)
+}
+
+// Reads a secret from the first line of stdin.
+pub fn read_stdin() -> Result<String, anyhow::Error> {
+    read_line(std::io::stdin().lock())
+}
+
+// Reads a secret from the first line of an inherited pipe or file handle,
+// the handle is closed afterwards. The number comes from the command line,
+// so it is only taken over once it turns out to be an inheritable pipe or
+// disk file, none of the installer's own handles is.
+pub fn read_handle(handle: usize) -> Result<String, anyhow::Error> {
+    if handle == 0 {
+        return Err(anyhow::anyhow!("Invalid secret handle"));
+    }
+    let raw = HANDLE(handle as RawHandle);
+    let mut flags = 0u32;
+    let res = unsafe { GetHandleInformation(raw, &mut flags) };
+    if res.is_err() {
+        return Err(anyhow::anyhow!("Invalid secret handle: {:?}", res.err()));
+    }
+    if flags & HANDLE_FLAG_INHERIT.0 == 0 {
+        return Err(anyhow::anyhow!("Secret handle was not inherited"));
+    }
+    let file_type = unsafe { GetFileType(raw) };
+    if file_type != FILE_TYPE_PIPE && file_type != FILE_TYPE_DISK {
+        return Err(anyhow::anyhow!("Secret handle is not a pipe or a file"));
+    }
+    let file = unsafe { std::fs::File::from_raw_handle(handle as RawHandle) };
+    read_line(file)
+}
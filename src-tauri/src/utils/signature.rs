@@ -0,0 +1,80 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde_json::Value;
+
+pub fn canonical_json(mut value: Value, excluded_keys: &[&str]) -> Vec<u8> {
+    if let Value::Object(map) = &mut value {
+        for key in excluded_keys {
+            map.remove(*key);
+        }
+    }
+    sort_keys(&mut value);
+    value.to_string().into_bytes()
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.sort_keys();
+            for (_, v) in map.iter_mut() {
+                sort_keys(v);
+            }
+        }
+        Value::Array(arr) => {
+            for v in arr.iter_mut() {
+                sort_keys(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn verify_ed25519(
+    message: &[u8],
+    signature: &str,
+    trusted_keys: &[&str],
+) -> Result<(), anyhow::Error> {
+    let raw_signature = STANDARD.decode(signature.trim());
+    if raw_signature.is_err() {
+        return Err(anyhow::anyhow!(
+            "Failed to decode signature: {:?}",
+            raw_signature.err()
+        ));
+    }
+    let signature = Signature::from_slice(&raw_signature?);
+    if signature.is_err() {
+        return Err(anyhow::anyhow!(
+            "Malformed ed25519 signature: {:?}",
+            signature.err()
+        ));
+    }
+    let signature = signature?;
+
+    for (idx, key) in trusted_keys.iter().enumerate() {
+        let raw_key = STANDARD.decode(key);
+        if raw_key.is_err() {
+            return Err(anyhow::anyhow!(
+                "Failed to decode trusted key #{}: {:?}",
+                idx,
+                raw_key.err()
+            ));
+        }
+        let raw_key: Result<[u8; 32], _> = raw_key?.try_into();
+        if raw_key.is_err() {
+            return Err(anyhow::anyhow!("Trusted key #{} has invalid length", idx));
+        }
+        let verifying_key = VerifyingKey::from_bytes(&raw_key.unwrap());
+        if verifying_key.is_err() {
+            return Err(anyhow::anyhow!(
+                "Trusted key #{} is not a valid ed25519 key: {:?}",
+                idx,
+                verifying_key.err()
+            ));
+        }
+        if verifying_key?.verify_strict(message, &signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow::anyhow!("Signature does not match any trusted key"))
+}
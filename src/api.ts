@@ -15,7 +15,6 @@ mkXckI5O98GdlszEmQ0Ejv5Fx9fR2rXRwM76S4iZTfabYpiMbb4bM42mHMauupj6
 -----END PUBLIC KEY-----`;
 
 let cachedData: GenericPatchData | null = null;
-let cachedToken: string | null = null;
 
 export async function fetchIsOversea(): Promise<boolean> {
   return await invoke<boolean>('generic_is_oversea');
@@ -69,7 +68,7 @@ export async function RegisterHomaPassportAndUseRedeemCode(
     Password: encrypt(password),
     VerifyCode: encrypt(verifyCode),
   };
-  const res = await invoke<HomaPassportOperationResp>('homa_register', {
+  const res = await invoke<HomaResp>('homa_register', {
     registerReq: req,
   });
   if (res.retcode !== 0) {
@@ -80,10 +79,7 @@ export async function RegisterHomaPassportAndUseRedeemCode(
     return false;
   }
 
-  cachedToken = res.data ?? null;
-
-  const redeemRes = await invoke<HomaPassportOperationResp>('homa_use_redeem_code', {
-    token: cachedToken,
+  const redeemRes = await invoke<HomaResp>('homa_use_redeem_code', {
     code: redeemCode,
   });
   if (redeemRes.retcode !== 0) {
@@ -105,11 +101,10 @@ export async function LoginHomaPassport(
     UserName: encrypt(username),
     Password: encrypt(password),
   };
-  const res = await invoke<HomaPassportOperationResp>('homa_login', {
+  const res = await invoke<HomaResp>('homa_login', {
     loginReq: req,
   });
   if (res.retcode === 0) {
-    cachedToken = res.data ?? null;
     return true;
   }
 
@@ -120,47 +115,32 @@ export async function LoginHomaPassport(
   return false;
 }
 
-export async function IsLoggedIn(): Promise<boolean> {
-  return !!cachedToken;
+export async function GetSession(): Promise<HomaSession> {
+  return await invoke<HomaSession>('homa_get_session');
 }
 
-export async function Logout(): Promise<void> {
-  cachedToken = null;
+export async function IsLoggedIn(): Promise<boolean> {
+  return (await GetSession()).logged_in;
 }
 
-export async function LoadToken(token: string): Promise<void> {
-  cachedToken = token;
+export async function Logout(): Promise<void> {
+  await invoke('homa_logout');
 }
 
 export async function GetUserInfo(): Promise<HomaPassportUserInfo> {
-  return await invoke<HomaPassportUserInfo>('homa_fetch_userinfo', {
-    token: cachedToken,
-  });
+  return await invoke<HomaPassportUserInfo>('homa_fetch_userinfo');
 }
 
 export async function IsCdnAvailable(): Promise<boolean> {
-  if (!cachedToken) {
+  try {
+    return (await GetSession()).cdn_available;
+  } catch {
     return false;
   }
-
-  const userinfo = await invoke<HomaPassportUserInfo>('homa_fetch_userinfo', {
-    token: cachedToken,
-  });
-
-  return (
-    userinfo.IsLicensedDeveloper ||
-    userinfo.IsMaintainer ||
-    new Date(userinfo.CdnExpireAt) > new Date()
-  );
 }
 
 export async function GetCdnUrl(filename: string): Promise<string> {
-  if (!cachedToken) {
-    throw new Error('Not logged in');
-  }
-
   return await invoke<string>('homa_fetch_cdn', {
-    token: cachedToken,
     filename: filename,
   });
 }
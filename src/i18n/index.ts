@@ -25,25 +25,8 @@ const getLocale = () => {
 const messages: Record<string, Record<string, string>> = { chs, cht, en, ja };
 const locale = getLocale();
 
-export const getLang = () => {
-  let locale = navigator.language || 'en';
-  if (locale.includes('-')) {
-    locale = locale.split('-')[0];
-  }
-
-  switch (locale) {
-    case 'zh':
-      return 'zh';
-    case 'id':
-      return 'id';
-    case 'ru':
-      return 'ru';
-    case 'ja':
-      return 'jp';
-    default:
-      return 'en';
-  }
-};
+// mapped to the codes the changelog API serves by the backend
+export const getChangelogLang = () => navigator.language || 'en';
 
 export const getLocalizedString = (key: string): string => {
   if (messages[locale] && messages[locale][key]) {
@@ -3,12 +3,6 @@ type HomaResp = {
   message: string;
 }
 
-type HomaPassportOperationResp = {
-  retcode: number;
-  message: string;
-  data?: string;
-};
-
 type HomaPassportLoginReq = { UserName: string; Password: string };
 
 type HomaPassportRegisterReq = { UserName: string; Password: string, VerifyCode: string };
@@ -22,6 +16,13 @@ type HomaPassportUserInfo = {
   CdnExpireAt: string;
 };
 
+type HomaSession = {
+  logged_in: boolean;
+  username: string | null;
+  cdn_available: boolean;
+  cdn_expire_at: string | null;
+};
+
 type GenericPatchData = {
   version: string;
   validation: string;
@@ -29,6 +30,14 @@ type GenericPatchData = {
   mirrors: GenericPatchPackageMirror[];
   urls: string[];
   sha256: string;
+  size?: number;
+  patches?: GenericDeltaPatch[];
+};
+
+type GenericDeltaPatch = {
+  from: string;
+  format: string;
+  urls: string[];
 };
 
 type GenericPatchPackageMirror = {
@@ -38,6 +47,28 @@ type GenericPatchPackageMirror = {
   speed: number | null;
 };
 
+type VerifiedPackage = {
+  version: string;
+  sha256: string;
+};
+
+type ChangelogSection = {
+  title: string | null;
+  items: string[];
+};
+
+type ChangelogEntry = {
+  version: string;
+  date: string | null;
+  sections: ChangelogSection[];
+};
+
+type Changelog = {
+  lang: string;
+  entries: ChangelogEntry[];
+  html?: string;
+};
+
 interface Config {
   version: string;
   is_update: boolean;
@@ -45,13 +76,71 @@ interface Config {
   skip_self_update: boolean;
   is_offline_mode: boolean;
   embedded_version: string | null;
+  embedded_package: EmbeddedPackage | null;
   curr_version: string | null;
-  token: string | null;
+  channel: ReleaseChannel;
 }
 
+type EmbeddedPackage = {
+  name: string;
+  publisher: string;
+  version: string;
+  architecture: string;
+  sha256: string;
+};
+
+type ReleaseChannel = 'stable' | 'beta' | 'canary';
+
 type InstallStat = {
   speedLastSize: number;
   lastTime: DOMHighResTimeStamp;
   speed: number;
   lowSpeedCount: number;
 };
+
+type PipelineStepId =
+  | 'package'
+  | 'verify'
+  | 'vcrt'
+  | 'certificate'
+  | 'font'
+  | 'long_path'
+  | 'stop_hutao'
+  | 'migration'
+  | 'deploy'
+  | 'shortcut';
+
+type PipelineStepStatus = 'done' | 'skipped' | 'failed';
+
+type PipelinePrompt =
+  | { kind: 'close_hutao' }
+  | { kind: 'migrate' }
+  | { kind: 'step_failed'; step: PipelineStepId; error: string; skippable: boolean };
+
+type PipelinePromptAnswer = 'yes' | 'no' | 'retry' | 'skip' | 'abort';
+
+type PipelineTimelineEvent =
+  | { type: 'planned'; steps: { id: PipelineStepId; weight: number; depends_on: PipelineStepId[]; skippable: boolean }[] }
+  | { type: 'step_started'; step: PipelineStepId; attempt: number; progress: number }
+  | { type: 'step_progress'; step: PipelineStepId; current: number; total: number; progress: number }
+  | { type: 'step_finished'; step: PipelineStepId; status: PipelineStepStatus; progress: number }
+  | { type: 'step_failed'; step: PipelineStepId; attempt: number; error: string; will_retry: boolean }
+  | { type: 'prompt'; id: number; prompt: PipelinePrompt }
+  | { type: 'rolled_back'; version: string; success: boolean }
+  | { type: 'finished'; success: boolean; reboot_required: boolean };
+
+type BrowserDownloadEvent =
+  | { type: 'growing'; name: string; current: number; total: number | null }
+  | { type: 'rejected'; name: string; reason: string }
+  | { type: 'matched'; name: string };
+
+type SelfUpdateEvent =
+  | { type: 'downloading'; current: number; total: number }
+  | { type: 'patching' }
+  | { type: 'verifying' }
+  | { type: 'restarting' };
+
+type PipelineReport = {
+  reboot_required: boolean;
+  skipped: PipelineStepId[];
+};